@@ -0,0 +1,16 @@
+// Fuzz target of the parser of the server configuration file
+//
+// Any text may be rejected as an invalid configuration, none may panic.
+
+#![no_main]
+
+// Import the fuzzer entry point
+use libfuzzer_sys::fuzz_target;
+// Import the parser under test
+use quicssh::server::parse_server_conf;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(content) = std::str::from_utf8(data) {
+        let _ = parse_server_conf(content);
+    }
+});
@@ -0,0 +1,254 @@
+// Fuzz target of the server connection handler
+//
+// Each input is sent by a QUIC client as the payload of its first stream, then
+// the client waits for the reply and closes the connection. handle_connection
+// may reject the stream but must never panic. Client and server exchange their
+// datagrams through memory, the only sockets are the echo backend's.
+
+#![no_main]
+
+// Import clap to build the server options from arguments
+use clap::Parser;
+// Import the fuzzer entry point
+use libfuzzer_sys::fuzz_target;
+// Import the library under test
+use quicssh::backend::BackendAddr;
+use quicssh::client::configure_client;
+use quicssh::connlog::TrafficCounters;
+use quicssh::server::{self, configure_server, handle_connection};
+use quicssh::tls::{ClientTlsOpt, ServerTlsOpt, SniCerts};
+use quicssh::transport::TransportOpt;
+// Import Quinn endpoint and socket abstraction types
+use quinn::udp::{RecvMeta, Transmit, UdpState};
+use quinn::{AsyncUdpSocket, Endpoint, EndpointConfig, TokioRuntime};
+// Import standard library I/O, networking and synchronization utilities
+use std::io::{self, IoSliceMut};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+// Import tokio runtime, networking, channel and I/O utilities
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+// Addresses the in-memory sockets claim to have
+const SERVER_ADDR: &str = "127.0.0.1:4433";
+const CLIENT_ADDR: &str = "127.0.0.1:50000";
+
+// Time the client waits for the reply to an input
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+// Time the handler gets to finish once the client closed the connection
+const HANDLER_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Datagram with the address of its sender
+type Datagram = (SocketAddr, Vec<u8>);
+
+// One end of an in-memory UDP link, delivering every datagram in order
+#[derive(Debug)]
+struct MemorySocket {
+    addr: SocketAddr,
+    peer: UnboundedSender<Datagram>,
+    inbox: Mutex<UnboundedReceiver<Datagram>>,
+}
+
+impl MemorySocket {
+    // Two sockets with addresses A and B, each receiving what the other sends
+    fn pair(a: SocketAddr, b: SocketAddr) -> (MemorySocket, MemorySocket) {
+        let (to_a, inbox_a) = unbounded_channel();
+        let (to_b, inbox_b) = unbounded_channel();
+        (
+            MemorySocket {
+                addr: a,
+                peer: to_b,
+                inbox: Mutex::new(inbox_a),
+            },
+            MemorySocket {
+                addr: b,
+                peer: to_a,
+                inbox: Mutex::new(inbox_b),
+            },
+        )
+    }
+}
+
+impl AsyncUdpSocket for MemorySocket {
+    fn poll_send(
+        &self,
+        _state: &UdpState,
+        _cx: &mut Context,
+        transmits: &[Transmit],
+    ) -> Poll<Result<usize, io::Error>> {
+        for transmit in transmits {
+            // A transmit carries several datagrams when segment_size is set
+            let size = transmit
+                .segment_size
+                .unwrap_or(transmit.contents.len())
+                .max(1);
+            for datagram in transmit.contents.chunks(size) {
+                // A closed peer behaves like a lost datagram
+                let _ = self.peer.send((self.addr, datagram.to_vec()));
+            }
+        }
+        Poll::Ready(Ok(transmits.len()))
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        match self.inbox.lock().unwrap().poll_recv(cx) {
+            Poll::Ready(Some((addr, data))) => {
+                let len = data.len().min(bufs[0].len());
+                bufs[0][..len].copy_from_slice(&data[..len]);
+                meta[0] = RecvMeta {
+                    addr,
+                    len,
+                    stride: len,
+                    ..RecvMeta::default()
+                };
+                Poll::Ready(Ok(1))
+            }
+            // Both ends live as long as the process
+            Poll::Ready(None) | Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+
+    fn may_fragment(&self) -> bool {
+        false
+    }
+}
+
+// Runtime, endpoints and options shared by every input
+struct Harness {
+    runtime: Runtime,
+    server: Endpoint,
+    client: Endpoint,
+    options: Arc<server::Opt>,
+    backend: BackendAddr,
+}
+
+// Start the runtime, the echo backend and both endpoints
+fn harness() -> &'static Harness {
+    static HARNESS: OnceLock<Harness> = OnceLock::new();
+    HARNESS.get_or_init(|| {
+        let runtime = Runtime::new().unwrap();
+        let _guard = runtime.enter();
+        let backend = runtime.block_on(echo_backend());
+
+        // Forwarding headers are parsed from the start of the stream, their
+        // targets get little time to answer
+        let args = [
+            "server",
+            "--listen",
+            SERVER_ADDR,
+            "--proxy-to",
+            &backend.to_string(),
+            "--allow-forward",
+            "--allow-tcp-forward",
+            "--tcp-connect-timeout-ms",
+            "100",
+        ];
+        let options = Arc::new(server::Opt::try_parse_from(args).unwrap());
+
+        let transport = TransportOpt::default();
+        let (server_config, _) =
+            configure_server(&transport, &ServerTlsOpt::default(), &SniCerts::new()).unwrap();
+        let (server_socket, client_socket) =
+            MemorySocket::pair(SERVER_ADDR.parse().unwrap(), CLIENT_ADDR.parse().unwrap());
+        let server = Endpoint::new_with_abstract_socket(
+            EndpointConfig::default(),
+            Some(server_config),
+            server_socket,
+            Arc::new(TokioRuntime),
+        )
+        .unwrap();
+        let mut client = Endpoint::new_with_abstract_socket(
+            EndpointConfig::default(),
+            None,
+            client_socket,
+            Arc::new(TokioRuntime),
+        )
+        .unwrap();
+        client.set_default_client_config(
+            configure_client(&transport, &ClientTlsOpt::default()).unwrap(),
+        );
+
+        Harness {
+            runtime,
+            server,
+            client,
+            options,
+            backend: backend.to_string().parse().unwrap(),
+        }
+    })
+}
+
+// TCP backend writing back everything it reads
+async fn echo_backend() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.into_split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+                let _ = writer.shutdown().await;
+            });
+        }
+    });
+    addr
+}
+
+// Send DATA on a new connection and wait for the handler of that connection
+async fn run_input(harness: &Harness, data: &[u8]) {
+    let connecting = harness
+        .client
+        .connect(SERVER_ADDR.parse().unwrap(), "localhost")
+        .unwrap();
+    let (client, incoming) = tokio::join!(connecting, harness.server.accept());
+    let (Ok(client), Some(incoming)) = (client, incoming) else {
+        panic!("in-memory handshake failed");
+    };
+    let connection = incoming.await.expect("in-memory handshake failed");
+
+    let handler = tokio::spawn(handle_connection(
+        harness.options.clone(),
+        "localhost".to_string(),
+        vec![harness.backend.clone()],
+        SERVER_ADDR.parse().unwrap(),
+        connection,
+        Arc::new(TrafficCounters::default()),
+        Default::default(),
+        None,
+    ));
+
+    // Errors of the stream are expected, the handler may reject any input
+    if let Ok((mut send, mut recv)) = client.open_bi().await {
+        let _ = send.write_all(data).await;
+        let _ = send.finish().await;
+        let _ = tokio::time::timeout(REPLY_TIMEOUT, recv.read_to_end(usize::MAX)).await;
+    }
+    client.close(0u32.into(), b"done");
+
+    // Returning an error is fine, panicking is the bug being looked for; a
+    // handler still waiting on a forward target is stopped
+    let abort = handler.abort_handle();
+    match tokio::time::timeout(HANDLER_TIMEOUT, handler).await {
+        Ok(Err(e)) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Ok(_) => {}
+        Err(_) => abort.abort(),
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let harness = harness();
+    harness.runtime.block_on(run_input(harness, data));
+});
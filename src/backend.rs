@@ -0,0 +1,93 @@
+// Import serde for reading backend addresses from the configuration file
+use serde::Deserialize;
+// Import standard library formatting, I/O and address types
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+// Import tokio stream traits and socket types
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+
+// Prefix of Unix domain socket backends, e.g. unix:/run/sshd.sock
+const UNIX_PREFIX: &str = "unix:";
+
+// Address of an SSH backend
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(try_from = "String")]
+pub enum BackendAddr {
+    // TCP socket address
+    Tcp(SocketAddr),
+    // Unix domain socket path, only available on Unix
+    Unix(PathBuf),
+}
+
+// Byte stream to an SSH backend, split like a TCP stream by the proxy
+pub trait BackendStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> BackendStream for T {}
+
+impl BackendAddr {
+    // Open a stream to the backend
+    pub async fn connect(&self) -> io::Result<Box<dyn BackendStream>> {
+        match self {
+            BackendAddr::Tcp(addr) => Ok(Box::new(TcpStream::connect(addr).await?)),
+            #[cfg(unix)]
+            BackendAddr::Unix(path) => Ok(Box::new(UnixStream::connect(path).await?)),
+            #[cfg(not(unix))]
+            BackendAddr::Unix(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "unix socket backends are only supported on Unix",
+            )),
+        }
+    }
+}
+
+impl FromStr for BackendAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix(UNIX_PREFIX) {
+            Some("") => Err(format!("missing socket path in {}", s)),
+            #[cfg(unix)]
+            Some(path) => Ok(BackendAddr::Unix(PathBuf::from(path))),
+            #[cfg(not(unix))]
+            Some(_) => Err(format!(
+                "{}: unix socket backends are only supported on Unix",
+                s
+            )),
+            None => s.parse().map(BackendAddr::Tcp).map_err(|_| {
+                format!(
+                    "invalid backend address {}, expected ip:port or unix:/path",
+                    s
+                )
+            }),
+        }
+    }
+}
+
+impl TryFrom<String> for BackendAddr {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<SocketAddr> for BackendAddr {
+    fn from(addr: SocketAddr) -> Self {
+        BackendAddr::Tcp(addr)
+    }
+}
+
+impl fmt::Display for BackendAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendAddr::Tcp(addr) => write!(f, "{}", addr),
+            BackendAddr::Unix(path) => write!(f, "{}{}", UNIX_PREFIX, path.display()),
+        }
+    }
+}
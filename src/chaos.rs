@@ -0,0 +1,227 @@
+// Import clap for the shared command line options
+use clap::Args;
+// Import Quinn socket abstraction types
+use quinn::udp::{RecvMeta, Transmit, UdpState};
+use quinn::AsyncUdpSocket;
+// Import random number generation for loss and delay sampling
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::{Distribution, Normal};
+// Import standard library collections, I/O and synchronization utilities
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::io::{self, IoSliceMut};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::Duration;
+// Import tokio timers used to release delayed packets
+use tokio::time::{sleep_until, Instant, Sleep};
+
+// Fault injection options for robustness testing, never use them in production
+#[derive(Args, Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct ChaosOpt {
+    // Probability (0.0-1.0) of dropping each received UDP datagram
+    #[clap(long = "chaos-packet-loss-rate", value_parser = parse_loss_rate)]
+    pub packet_loss_rate: Option<f64>,
+    // Mean and standard deviation in milliseconds of the delay added to received datagrams
+    #[clap(
+        long = "chaos-packet-delay-ms",
+        num_args = 2,
+        value_names = ["MEAN", "STDDEV"]
+    )]
+    pub packet_delay_ms: Option<Vec<f64>>,
+}
+
+// Parse a probability between 0.0 and 1.0
+fn parse_loss_rate(s: &str) -> Result<f64, String> {
+    let rate: f64 = s.parse().map_err(|e| format!("invalid loss rate: {}", e))?;
+    if !(0.0..=1.0).contains(&rate) {
+        return Err("loss rate must be between 0.0 and 1.0".to_string());
+    }
+    Ok(rate)
+}
+
+impl ChaosOpt {
+    // Whether any fault is injected
+    pub fn enabled(&self) -> bool {
+        self.packet_loss_rate.is_some() || self.packet_delay_ms.is_some()
+    }
+
+    // Delay distribution in milliseconds
+    fn delay_distribution(&self) -> io::Result<Option<Normal<f64>>> {
+        match self.packet_delay_ms.as_deref() {
+            Some(&[mean, stddev]) => Normal::new(mean, stddev)
+                .map(Some)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string())),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl fmt::Display for ChaosOpt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "packet loss rate {}",
+            self.packet_loss_rate.unwrap_or(0.0)
+        )?;
+        if let Some([mean, stddev]) = self.packet_delay_ms.as_deref() {
+            write!(f, ", packet delay {} ms (stddev {} ms)", mean, stddev)?;
+        }
+        Ok(())
+    }
+}
+
+// A received datagram held back until its delivery time
+struct DelayedDatagram {
+    deliver_at: Instant,
+    data: Vec<u8>,
+    meta: RecvMeta,
+}
+
+// Mutable state of the chaos socket
+struct ChaosState {
+    rng: StdRng,
+    // Delayed datagrams ordered by delivery time
+    delayed: VecDeque<DelayedDatagram>,
+    // Timer firing when the first delayed datagram is due
+    timer: Option<Pin<Box<Sleep>>>,
+}
+
+// UDP socket wrapper dropping and delaying received datagrams
+//
+// Faults are injected on the receive path only, where the datagrams are
+// owned by the wrapper; enabling chaos on both peers affects both directions.
+pub struct ChaosUdpSocket {
+    inner: Box<dyn AsyncUdpSocket>,
+    loss_rate: f64,
+    delay: Option<Normal<f64>>,
+    state: Mutex<ChaosState>,
+}
+
+impl ChaosUdpSocket {
+    // Wrap a socket with the configured faults
+    pub fn new(inner: Box<dyn AsyncUdpSocket>, options: &ChaosOpt) -> io::Result<Self> {
+        Ok(ChaosUdpSocket {
+            inner,
+            loss_rate: options.packet_loss_rate.unwrap_or(0.0),
+            delay: options.delay_distribution()?,
+            state: Mutex::new(ChaosState {
+                rng: StdRng::from_entropy(),
+                delayed: VecDeque::new(),
+                timer: None,
+            }),
+        })
+    }
+}
+
+impl fmt::Debug for ChaosUdpSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChaosUdpSocket")
+            .field("inner", &self.inner)
+            .field("loss_rate", &self.loss_rate)
+            .field("delay", &self.delay)
+            .finish()
+    }
+}
+
+impl AsyncUdpSocket for ChaosUdpSocket {
+    fn poll_send(
+        &self,
+        state: &UdpState,
+        cx: &mut Context,
+        transmits: &[Transmit],
+    ) -> Poll<Result<usize, io::Error>> {
+        self.inner.poll_send(state, cx, transmits)
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            // Deliver a delayed datagram once it is due
+            if state
+                .delayed
+                .front()
+                .is_some_and(|d| d.deliver_at <= Instant::now())
+            {
+                let datagram = state.delayed.pop_front().unwrap();
+                let len = datagram.data.len().min(bufs[0].len());
+                bufs[0][..len].copy_from_slice(&datagram.data[..len]);
+                meta[0] = datagram.meta;
+                meta[0].len = len;
+                return Poll::Ready(Ok(1));
+            }
+
+            match self.inner.poll_recv(cx, bufs, meta) {
+                Poll::Ready(Ok(n)) => {
+                    // Keep the datagrams that are neither dropped nor delayed at the front
+                    let mut kept = 0;
+                    for i in 0..n {
+                        if state.rng.gen_bool(self.loss_rate) {
+                            continue;
+                        }
+                        let delay = self
+                            .delay
+                            .map(|d| d.sample(&mut state.rng).max(0.0))
+                            .unwrap_or(0.0);
+                        if delay > 0.0 {
+                            let deliver_at =
+                                Instant::now() + Duration::from_secs_f64(delay / 1000.0);
+                            let datagram = DelayedDatagram {
+                                deliver_at,
+                                data: bufs[i][..meta[i].len].to_vec(),
+                                meta: meta[i],
+                            };
+                            let pos = state
+                                .delayed
+                                .partition_point(|d| d.deliver_at <= deliver_at);
+                            state.delayed.insert(pos, datagram);
+                            continue;
+                        }
+                        if kept != i {
+                            let (head, tail) = bufs.split_at_mut(i);
+                            head[kept][..meta[i].len].copy_from_slice(&tail[0][..meta[i].len]);
+                            meta[kept] = meta[i];
+                        }
+                        kept += 1;
+                    }
+                    if kept > 0 {
+                        return Poll::Ready(Ok(kept));
+                    }
+                }
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => {
+                    // Wake up when the first delayed datagram is due
+                    let deliver_at = match state.delayed.front() {
+                        Some(d) => d.deliver_at,
+                        None => return Poll::Pending,
+                    };
+                    let timer = state
+                        .timer
+                        .get_or_insert_with(|| Box::pin(sleep_until(deliver_at)));
+                    timer.as_mut().reset(deliver_at);
+                    if timer.as_mut().poll(cx).is_pending() {
+                        return Poll::Pending;
+                    }
+                }
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    fn may_fragment(&self) -> bool {
+        self.inner.may_fragment()
+    }
+}
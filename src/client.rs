@@ -2,13 +2,29 @@
 // #![cfg(feature = "rustls")]
 
 // Import clap for command line argument parsing
-use clap::Parser;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, Parser, ValueEnum};
+// Import serde for reading the profiles file
+use serde::{Deserialize, Serialize};
+use serde_json::json;
 // Import Quinn QUIC library components
-use quinn::{ClientConfig, Endpoint, VarInt};
+use quinn::{ClientConfig, Connection, ConnectionError, Endpoint, RecvStream, SendStream, ZeroRttAccepted};
 // Import standard library error handling and networking
-use std::{error::Error, net::SocketAddr, sync::Arc};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll};
+use std::{error::Error, future::Future};
+// Import time utilities for the connection budget
+use std::time::{Duration, Instant};
 // Import tokio async I/O traits
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+// Import the listener for forwarded ports
+use tokio::net::{TcpListener, TcpStream};
 
 // Import Unix signal handling for non-Windows systems
 #[cfg(not(windows))]
@@ -18,6 +34,36 @@ use tokio::signal::unix::{signal, SignalKind};
 use tokio::signal::windows::ctrl_c;
 // Import URL parsing functionality
 use url::Url;
+// Import the SOCKS5 relayed UDP socket
+use crate::socks5::{self, Socks5UdpSocket};
+// Import futures helpers for concurrent connection attempts
+use futures::future;
+use futures::stream::{FuturesUnordered, StreamExt};
+
+// Import QUIC transport options shared with the server
+use crate::transport::TransportOpt;
+// Import forwarding header used for proxy chains
+use crate::forward::{relay_tcp, ConnectHeader, ForwardHeader, TraceHeader, DEFAULT_FORWARD_TTL};
+// Import the OpenTelemetry trace export
+use crate::otel;
+// Import tracing to record the span of each session
+use tracing::Instrument;
+// Import the conversion of profile options to command line arguments
+use crate::optconf::option_args;
+// Import the output format of the commands
+use crate::output::{self, OutputFormat};
+// Import the registry of connections shared by --multiplex sessions
+use crate::registry::ConnectionRegistry;
+// Import the close codes of the server refusing a client
+use crate::server::{
+    ACCESS_DENIED_CODE, CLIENT_CERT_REQUIRED_CODE, FORWARD_REJECTED_CODE, HOST_KEY_MISMATCH_CODE,
+    PROXY_LOOP_CODE, TOO_MANY_RECONNECTS_CODE,
+};
+// Import the non-blocking stdin and stdout of --proxycommand
+#[cfg(unix)]
+use crate::stdio::RawStdio;
+// Import client TLS options and certificate helpers
+use crate::tls::{self, ClientTlsOpt};
 
 // Import logging macros (some may be unused)
 #[allow(unused_imports)]
@@ -26,24 +72,319 @@ use log::{debug, error, info, trace, warn, Level};
 // Define command line options structure for the client
 #[derive(Parser, Debug)]
 #[clap(name = "client")]
+#[non_exhaustive]
 pub struct Opt {
-    // URL of the QUIC server to connect to
+    // URL of the QUIC server to connect to, may come from --profile
+    #[clap(required = false, required_unless_present = "profile")]
+    url: Url,
+    // Profile of the profiles file whose settings fill in the options not
+    // given on the command line, see profile_args
+    #[clap(long = "profile", value_name = "NAME")]
+    profile: Option<String>,
+    // Optional local address to bind the client to
+    #[clap(long = "bind", short = 'b')]
+    bind_addr: Option<SocketAddr>,
+    // Optional comma-separated QUIC versions to try, in order of preference
+    #[clap(long = "quic-supported-versions", value_delimiter = ',', value_parser = parse_quic_version)]
+    quic_supported_versions: Vec<u32>,
+    // Intermediate quicssh-rs servers to traverse, in order, before reaching URL
+    #[clap(long = "proxy-chain", value_name = "URL")]
+    proxy_chain: Vec<Url>,
+    // Other servers to try along with URL, the first to connect is used;
+    // like URL they must use the quic:// scheme
+    #[clap(long = "fallback-url", value_name = "URL", conflicts_with = "proxy_chain")]
+    fallback_urls: Vec<Url>,
+    // Forward connections to 127.0.0.1:PORT to HOST:HOSTPORT as seen from the
+    // server (like ssh -L) instead of relaying stdin/stdout; needs a server
+    // started with --allow-tcp-forward
+    #[clap(
+        long = "local-forward",
+        short = 'L',
+        value_name = "PORT:HOST:HOSTPORT",
+        value_parser = parse_local_forward,
+        conflicts_with_all = ["proxy_chain", "reconnect"]
+    )]
+    local_forwards: Vec<LocalForward>,
+    // Act as a SOCKS5 proxy on this address whose CONNECT requests are made
+    // from the server (like ssh -D); needs a server started with --allow-tcp-forward
+    #[clap(
+        long = "socks5-listen",
+        short = 'D',
+        value_name = "ADDR",
+        conflicts_with_all = ["proxy_chain", "reconnect"]
+    )]
+    socks5_listen: Option<SocketAddr>,
+    // Accept SSH connections on this address (e.g. 127.0.0.1:2222 for
+    // ssh -p 2222 localhost) instead of relaying stdin/stdout, each relayed over
+    // its own stream of a connection to the server shared with the other sessions
+    #[clap(
+        long = "multiplex",
+        value_name = "ADDR",
+        conflicts_with_all = ["proxy_chain", "fallback_urls", "reconnect", "local_forwards", "socks5_listen"]
+    )]
+    multiplex: Option<SocketAddr>,
+    // Export session traces to this OTLP gRPC collector and pass the trace
+    // context to the server, which joins the trace when it also exports traces
+    #[clap(long = "otel-endpoint", value_name = "URL")]
+    otel_endpoint: Option<String>,
+    // SOCKS5 proxy relaying the QUIC datagrams (UDP ASSOCIATE, no authentication)
+    #[clap(long = "socks5-proxy", value_name = "ADDR")]
+    socks5_proxy: Option<SocketAddr>,
+    // Time allowed for each of URL and the --fallback-url servers to complete the handshake
+    #[clap(long = "connect-timeout-ms", default_value = "5000", value_parser = clap::value_parser!(u64).range(1..))]
+    connect_timeout_ms: u64,
+    // QUIC transport tuning options
+    #[clap(flatten)]
+    transport: TransportOpt,
+    // QUIC application error code sent when the client closes the connection on a signal
+    #[clap(long = "shutdown-error-code", default_value = "0")]
+    shutdown_error_code: u32,
+    // Reason phrase sent along with --shutdown-error-code
+    #[clap(long = "shutdown-reason", default_value = "signal HUP")]
+    shutdown_reason: String,
+    // Total time allowed from the first connection attempt until the stream is open
+    #[clap(long = "connection-timeout-ms", value_parser = clap::value_parser!(u64).range(1..))]
+    connection_timeout_ms: Option<u64>,
+    // Size in bytes of the buffers relaying data in each direction; values above
+    // the OS socket buffer sizes give diminishing returns
+    #[clap(long = "buf-size", default_value = "65536", value_parser = clap::value_parser!(u32).range(1..))]
+    buf_size: u32,
+    // Reconnect with exponential backoff when the connection fails or is lost
+    // before the SSH session exchanged any data; a session already started
+    // cannot be resumed, and errors connecting again cannot fix are reported
+    #[clap(long = "reconnect")]
+    reconnect: bool,
+    // Upper bound in seconds of the delay between reconnection attempts
+    #[clap(long = "max-reconnect-delay-secs", default_value = "60", value_parser = clap::value_parser!(u64).range(1..))]
+    max_reconnect_delay_secs: u64,
+    // Poll stdin and stdout in non-blocking mode instead of reading them on a
+    // blocking thread, for use as an OpenSSH ProxyCommand (Unix only)
+    #[clap(long = "proxycommand")]
+    proxycommand: bool,
+    // TLS options such as the client certificate for mTLS
+    #[clap(flatten)]
+    tls: ClientTlsOpt,
+}
+
+// Client profile, a [name] table of the profiles file
+#[derive(Deserialize, Debug)]
+struct ProfileConf {
+    // Server URL, used when none is given on the command line
+    url: Option<String>,
+    // Other client options keyed by their long flag name (dashes or underscores)
+    #[serde(flatten)]
+    options: toml::Table,
+}
+
+// Profiles file: $QUICSSH_CONFIG, or ~/.config/quicssh/profiles.toml
+fn profiles_path() -> Result<PathBuf, Box<dyn Error + Send + Sync>> {
+    if let Some(path) = std::env::var_os("QUICSSH_CONFIG") {
+        return Ok(PathBuf::from(path));
+    }
+    let home = std::env::var_os("HOME").ok_or("cannot find the profiles file, $HOME is not set")?;
+    Ok(PathBuf::from(home).join(".config/quicssh/profiles.toml"))
+}
+
+// Read every profile of the profiles file
+fn load_profiles() -> Result<(PathBuf, BTreeMap<String, ProfileConf>), Box<dyn Error + Send + Sync>> {
+    let path = profiles_path()?;
+    let content = std::fs::read_to_string(&path)
+        .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    let profiles = toml::from_str(&content)
+        .map_err(|e| format!("invalid profiles file {}: {}", path.display(), e))?;
+    Ok((path, profiles))
+}
+
+// Build extra command line arguments for the settings of the --profile profile
+//
+// Options given on the command line or through an environment variable are
+// skipped, see option_args; so is the profile URL when one is given.
+pub fn profile_args(matches: &ArgMatches) -> Result<Vec<OsString>, Box<dyn Error + Send + Sync>> {
+    let name = match matches.get_one::<String>("profile") {
+        Some(name) => name,
+        None => return Ok(Vec::new()),
+    };
+    let (path, mut profiles) = load_profiles()?;
+    let profile = profiles
+        .remove(name)
+        .ok_or_else(|| format!("no profile {} in {}", name, path.display()))?;
+
+    let context = format!("profile {}", name);
+    let mut args = option_args(&Opt::command(), matches, profile.options, &context, "profile")?;
+    // URL is positional, it goes last
+    if matches.value_source("url") != Some(ValueSource::CommandLine) {
+        let url = profile
+            .url
+            .ok_or_else(|| format!("profile {} has no url and none is given", name))?;
+        args.push(url.into());
+    }
+    Ok(args)
+}
+
+// Profile as listed by list-profiles
+#[derive(Serialize)]
+struct ProfileListing {
+    name: String,
+    url: Option<String>,
+    // Server address the URL resolves to
+    address: Option<SocketAddr>,
+    // Why the URL is invalid or does not resolve
+    error: Option<String>,
+    // Address column of the text listing
+    #[serde(skip)]
+    text: String,
+}
+
+// Print the profiles of the profiles file and the server address of each
+pub fn list_profiles(format: OutputFormat) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (path, profiles) = load_profiles()?;
+    let listings: Vec<ProfileListing> = profiles
+        .into_iter()
+        .map(|(name, profile)| {
+            let (address, error, text) = match profile.url.as_deref().map(Url::parse) {
+                None => (None, Some("no url".to_string()), "no url".to_string()),
+                Some(Err(e)) => {
+                    let e = format!("invalid url: {}", e);
+                    (None, Some(e.clone()), e)
+                }
+                Some(Ok(url)) => match resolve_url(&url) {
+                    Ok((addr, _)) => (Some(addr), None, format!("{} ({})", url, addr)),
+                    Err(e) => (None, Some(e.to_string()), format!("{} (unresolved: {})", url, e)),
+                },
+            };
+            ProfileListing {
+                name,
+                url: profile.url,
+                address,
+                error,
+                text,
+            }
+        })
+        .collect();
+
+    if format == OutputFormat::Json {
+        return output::print_json(&json!({ "path": path, "profiles": listings }));
+    }
+    println!("profiles in {}:", path.display());
+    let width = listings.iter().map(|listing| listing.name.len()).max().unwrap_or(0);
+    for listing in listings {
+        println!("  {:width$}  {}", listing.name, listing.text, width = width);
+    }
+    Ok(())
+}
+
+// Define command line options of the fingerprint command
+#[derive(Parser, Debug)]
+#[clap(name = "fingerprint")]
+#[non_exhaustive]
+pub struct FingerprintOpt {
+    // URL of the QUIC server whose certificate fingerprint is printed
+    url: Url,
+    // Optional local address to bind the client to
+    #[clap(long = "bind", short = 'b')]
+    bind_addr: Option<SocketAddr>,
+}
+
+// Define command line options of the test command
+#[derive(Parser, Debug)]
+#[clap(name = "test")]
+#[non_exhaustive]
+pub struct TestOpt {
+    // URL of the QUIC server to test
     url: Url,
+    // Hex-encoded SHA-256 fingerprint the server certificate must match
+    #[clap(long = "fingerprint", value_parser = tls::parse_cert_fingerprint)]
+    fingerprint: Option<[u8; 32]>,
+    // Time allowed for the handshake to complete
+    #[clap(long = "timeout-ms", default_value_t = 5000)]
+    timeout_ms: u64,
     // Optional local address to bind the client to
     #[clap(long = "bind", short = 'b')]
     bind_addr: Option<SocketAddr>,
 }
 
+// Define command line options of the show-cert command
+#[derive(Parser, Debug)]
+#[clap(name = "show-cert")]
+#[non_exhaustive]
+pub struct ShowCertOpt {
+    // URL of the QUIC server whose certificates are shown
+    url: Url,
+    // Output format
+    #[clap(long = "format", value_enum, default_value_t = CertOutputFormat::Text)]
+    format: CertOutputFormat,
+    // Time allowed for the handshake to complete
+    #[clap(long = "timeout-ms", default_value_t = 5000)]
+    timeout_ms: u64,
+    // Optional local address to bind the client to
+    #[clap(long = "bind", short = 'b')]
+    bind_addr: Option<SocketAddr>,
+}
+
+// Output format of the show-cert command
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CertOutputFormat {
+    // One block of fields per certificate
+    Text,
+    // JSON array of certificates
+    Json,
+}
+
+// QUIC versions implemented by Quinn (RFC 9000 and drafts 29 to 34)
+const QUIC_VERSIONS: &[u32] = &[
+    0x0000_0001,
+    0xff00_001d,
+    0xff00_001e,
+    0xff00_001f,
+    0xff00_0020,
+    0xff00_0021,
+    0xff00_0022,
+];
+
+// Number of servers whose TLS session tickets are kept for 0-RTT
+const SESSION_CACHE_SIZE: usize = 32;
+
+// TLS session tickets shared by the connections of this process, so that a
+// reconnection can resume the session of the previous connection with 0-RTT
+static SESSION_CACHE: OnceLock<Arc<rustls::client::ClientSessionMemoryCache>> = OnceLock::new();
+
+// Parse a QUIC version number given in decimal or 0x-prefixed hexadecimal
+pub fn parse_quic_version(s: &str) -> Result<u32, String> {
+    let version = match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse::<u32>(),
+    }
+    .map_err(|e| format!("invalid QUIC version {}: {}", s, e))?;
+
+    if !QUIC_VERSIONS.contains(&version) {
+        return Err(format!(
+            "unsupported QUIC version {:#x}, expected one of: {}",
+            version,
+            format_quic_versions(QUIC_VERSIONS)
+        ));
+    }
+    Ok(version)
+}
+
+// Format QUIC version numbers as a comma-separated hexadecimal list
+pub fn format_quic_versions(versions: &[u32]) -> String {
+    versions
+        .iter()
+        .map(|v| format!("{:#x}", v))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 // Enable MTU Discovery (MTUD) for non-Windows/Linux systems
 // Returns default transport config since MTUD is not supported
-#[cfg(not(any(windows, os = "linux")))]
+#[cfg(not(any(windows, target_os = "linux")))]
 pub fn enable_mtud_if_supported() -> quinn::TransportConfig {
     quinn::TransportConfig::default()
 }
 
 // Enable MTU Discovery (MTUD) for Windows and Linux systems
 // Configures transport to use MTU discovery for optimal packet sizing
-#[cfg(any(windows, os = "linux"))]
+#[cfg(any(windows, target_os = "linux"))]
 pub fn enable_mtud_if_supported() -> quinn::TransportConfig {
     // Create default transport configuration
     let mut transport_config = quinn::TransportConfig::default();
@@ -81,25 +422,101 @@ impl rustls::client::ServerCertVerifier for SkipServerVerification {
     }
 }
 
+// Certificate verifier accepting only the server certificate with a pinned SHA-256 fingerprint
+struct FingerprintVerifier {
+    expected: [u8; 32],
+}
+
+impl FingerprintVerifier {
+    // Create a new instance wrapped in Arc for thread safety
+    fn new(expected: [u8; 32]) -> Arc<Self> {
+        Arc::new(Self { expected })
+    }
+}
+
+impl rustls::client::ServerCertVerifier for FingerprintVerifier {
+    // Accept the certificate only if its digest matches the pinned fingerprint
+    // The chain and name are not checked, the pin identifies the certificate itself
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: std::time::SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        let actual = tls::cert_digest(end_entity);
+        if actual != self.expected {
+            let message = format!(
+                "server certificate fingerprint mismatch: got {}",
+                hex::encode(actual)
+            );
+            error!("[client] {}", message);
+            // Reported to the server as a certificate_unknown alert
+            return Err(rustls::Error::InvalidCertificate(
+                rustls::CertificateError::Other(Arc::from(Box::<dyn Error + Send + Sync>::from(message))),
+            ));
+        }
+        Ok(rustls::client::ServerCertVerified::assertion())
+    }
+}
+
 // Configure the QUIC client with TLS and transport settings
-fn configure_client() -> Result<ClientConfig, Box<dyn Error>> {
-    // Build rustls client configuration with insecure certificate verification
-    let crypto = rustls::ClientConfig::builder()
-        .with_safe_defaults()                                           // Use safe default cryptographic settings
-        .with_custom_certificate_verifier(SkipServerVerification::new()) // Skip certificate verification (insecure)
-        .with_no_client_auth();                                         // No client certificate authentication
+pub fn configure_client(
+    transport: &TransportOpt,
+    tls: &ClientTlsOpt,
+) -> Result<ClientConfig, Box<dyn Error + Send + Sync>> {
+    // Pin the server certificate when a fingerprint is given, otherwise
+    // fall back to the insecure verifier that accepts any certificate
+    let verifier: Arc<dyn rustls::client::ServerCertVerifier> = match tls.server_fingerprint {
+        Some(expected) => FingerprintVerifier::new(expected),
+        None => SkipServerVerification::new(),
+    };
+    configure_client_with_verifier(transport, tls, verifier)
+}
+
+// Create QUIC client configuration checking the server certificate with VERIFIER
+fn configure_client_with_verifier(
+    transport: &TransportOpt,
+    tls: &ClientTlsOpt,
+    verifier: Arc<dyn rustls::client::ServerCertVerifier>,
+) -> Result<ClientConfig, Box<dyn Error + Send + Sync>> {
+    // Build rustls client configuration with the chosen certificate verification
+    let builder = rustls::ClientConfig::builder()
+        .with_safe_defaults()                        // Use safe default cryptographic settings
+        .with_custom_certificate_verifier(verifier);
+
+    // Present a client certificate when mTLS is configured
+    let mut crypto = match tls.client_identity()? {
+        Some((chain, key)) => {
+            info!(
+                "[client] using client certificate CN={}",
+                tls::subject_common_name(&chain[0]).unwrap_or_else(|| "<none>".to_string())
+            );
+            builder
+                .with_client_auth_cert(chain, key)
+                .map_err(|e| format!("invalid client certificate or key: {}", e))?
+        }
+        None => builder.with_no_client_auth(),
+    };
+    if let Some(path) = &tls.keylog {
+        crypto.key_log = tls::KeyLogFile::open(path)?;
+    }
+    if tls.enable_0rtt {
+        let cache = SESSION_CACHE
+            .get_or_init(|| Arc::new(rustls::client::ClientSessionMemoryCache::new(SESSION_CACHE_SIZE)));
+        crypto.resumption = rustls::client::Resumption::store(cache.clone());
+        crypto.enable_early_data = true;
+    }
 
     // Create Quinn client configuration with the rustls config
     let mut client_config = ClientConfig::new(Arc::new(crypto));
     
     // Configure transport layer settings
+    // Keep-alive interval and idle timeout come from the transport options
     let mut transport_config = enable_mtud_if_supported();
-    
-    // Set maximum idle timeout to 60 seconds before connection is closed
-    transport_config.max_idle_timeout(Some(VarInt::from_u32(60_000).into()));
-    
-    // Send keep-alive packets every 1 second to maintain connection
-    transport_config.keep_alive_interval(Some(std::time::Duration::from_secs(1)));
+    transport.apply(&mut transport_config)?;
     
     // Apply transport configuration to client config
     client_config.transport_config(Arc::new(transport_config));
@@ -107,19 +524,58 @@ fn configure_client() -> Result<ClientConfig, Box<dyn Error>> {
     Ok(client_config)
 }
 
+// Certificate verifier accepting any server certificate and keeping a copy of
+// the chain, used to show the certificates of a server
+#[derive(Default)]
+struct RecordingVerifier {
+    chain: Mutex<Vec<rustls::Certificate>>,
+}
+
+impl RecordingVerifier {
+    // Certificates presented by the server, the end entity first
+    fn chain(&self) -> Vec<rustls::Certificate> {
+        self.chain.lock().unwrap().clone()
+    }
+}
+
+impl rustls::client::ServerCertVerifier for RecordingVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::Certificate,
+        intermediates: &[rustls::Certificate],
+        _server_name: &rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: std::time::SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        let mut chain = self.chain.lock().unwrap();
+        chain.clear();
+        chain.push(end_entity.clone());
+        chain.extend_from_slice(intermediates);
+        Ok(rustls::client::ServerCertVerified::assertion())
+    }
+}
+
 // Create a QUIC endpoint configured for client-only operation
 // 
 // Arguments:
 // - bind_addr: Local socket address to bind the client endpoint to
+// - transport: QUIC transport tuning options
+// - client_cfg: Default configuration used for outgoing connections
 // 
 // Returns: Configured QUIC endpoint ready for outbound connections
 #[allow(unused)]
-pub fn make_client_endpoint(bind_addr: SocketAddr) -> Result<Endpoint, Box<dyn Error>> {
-    // Get the configured client settings
-    let client_cfg = configure_client()?;
-    
+pub fn make_client_endpoint(
+    bind_addr: SocketAddr,
+    transport: &TransportOpt,
+    client_cfg: ClientConfig,
+) -> Result<Endpoint, Box<dyn Error + Send + Sync>> {
     // Create a client-only endpoint bound to the specified address
-    let mut endpoint = Endpoint::client(bind_addr)?;
+    let socket = std::net::UdpSocket::bind(bind_addr)?;
+    if transport.chaos.enabled() {
+        warn!("[client] chaos mode enabled: {}", transport.chaos);
+    }
+    let mut endpoint = transport.make_endpoint(transport.endpoint_config()?, None, socket)?;
     
     // Set the default client configuration for all connections
     endpoint.set_default_client_config(client_cfg);
@@ -127,12 +583,28 @@ pub fn make_client_endpoint(bind_addr: SocketAddr) -> Result<Endpoint, Box<dyn E
     Ok(endpoint)
 }
 
-// Main async function to run the QUIC client
-#[tokio::main]
-pub async fn run(options: Opt) -> Result<(), Box<dyn Error>> {
-    // Extract URL from command line options
-    let url = options.url;
-    
+// Create a client endpoint whose datagrams are relayed by a SOCKS5 proxy
+pub async fn make_socks5_endpoint(
+    proxy: SocketAddr,
+    bind_addr: SocketAddr,
+    transport: &TransportOpt,
+    client_cfg: ClientConfig,
+) -> Result<Endpoint, Box<dyn Error + Send + Sync>> {
+    let socket = std::net::UdpSocket::bind(bind_addr)?;
+    let socket = Socks5UdpSocket::new(proxy, socket)
+        .await
+        .map_err(|e| std::io::Error::new(e.kind(), format!("SOCKS5 proxy {}: {}", proxy, e)))?;
+    info!("[client] relaying through SOCKS5 proxy {} (relay {})", proxy, socket.relay());
+    if transport.chaos.enabled() {
+        warn!("[client] chaos mode enabled: {}", transport.chaos);
+    }
+    let mut endpoint = transport.make_abstract_endpoint(transport.endpoint_config()?, None, socket)?;
+    endpoint.set_default_client_config(client_cfg);
+    Ok(endpoint)
+}
+
+// Resolve a quic:// URL into the remote socket address and the SNI to present
+pub fn resolve_url(url: &Url) -> Result<(SocketAddr, String), Box<dyn Error + Send + Sync>> {
     // Validate that URL scheme is "quic"
     if url.scheme() != "quic" {
         return Err("URL scheme must be quic".into());
@@ -154,55 +626,755 @@ pub async fn run(options: Opt) -> Result<(), Box<dyn Error>> {
     // Clean up IPv6 address format by removing brackets
     let sni = sni.trim_start_matches('[').trim_end_matches(']');
 
+    Ok((remote, sni.to_string()))
+}
+
+// Auto-select bind address based on remote address type
+pub fn default_bind_addr(remote: &SocketAddr) -> SocketAddr {
+    use std::net::{IpAddr::*, Ipv4Addr, Ipv6Addr};
+    if remote.is_ipv6() {
+        // Bind to IPv6 unspecified address (::) on any port
+        SocketAddr::new(V6(Ipv6Addr::UNSPECIFIED), 0)
+    } else {
+        // Bind to IPv4 unspecified address (0.0.0.0) on any port
+        SocketAddr::new(V4(Ipv4Addr::UNSPECIFIED), 0)
+    }
+}
+
+// Shares of the --connection-timeout-ms budget given to each phase
+const CONNECT_SHARE: f64 = 0.3;
+const HANDSHAKE_SHARE: f64 = 0.4;
+const STREAM_OPEN_SHARE: f64 = 0.3;
+
+// Time budget shared by the phases of establishing a connection
+// A phase never gets more than its share nor more than what is left in total
+struct ConnectionBudget {
+    start: Instant,
+    total: Duration,
+}
+
+impl ConnectionBudget {
+    fn new(total: Duration) -> Self {
+        ConnectionBudget {
+            start: Instant::now(),
+            total,
+        }
+    }
+
+    // Time left in the whole budget
+    fn remaining(&self) -> Duration {
+        self.total.saturating_sub(self.start.elapsed())
+    }
+
+    // Timeout of a phase given its share of the budget
+    fn phase(&self, share: f64) -> Duration {
+        self.total.mul_f64(share).min(self.remaining())
+    }
+}
+
+// Run a connection phase within its share of the budget, if any
+async fn with_budget<T>(
+    budget: Option<&ConnectionBudget>,
+    share: f64,
+    phase: &str,
+    fut: impl Future<Output = T>,
+) -> Result<T, Box<dyn Error + Send + Sync>> {
+    match budget {
+        Some(budget) => {
+            let timeout = budget.phase(share);
+            tokio::time::timeout(timeout, fut)
+                .await
+                .map_err(|_| {
+                    let message = format!("{} timed out after {} ms", phase, timeout.as_millis());
+                    std::io::Error::new(std::io::ErrorKind::TimedOut, message).into()
+                })
+        }
+        None => Ok(fut.await),
+    }
+}
+
+// Check that a UDP socket can be bound to an address, explaining the common failures
+pub fn check_bind_addr(addr: SocketAddr) -> Result<(), Box<dyn Error + Send + Sync>> {
+    match std::net::UdpSocket::bind(addr) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(bind_error(addr, e)),
+    }
+}
+
+// Error binding a UDP socket to an address, explaining the common failures
+pub fn bind_error(addr: SocketAddr, e: std::io::Error) -> Box<dyn Error + Send + Sync> {
+    use std::io::ErrorKind;
+    let reason = match e.kind() {
+        ErrorKind::AddrNotAvailable => {
+            "no such interface or address not assigned to any interface".to_string()
+        }
+        ErrorKind::AddrInUse => "address already in use".to_string(),
+        ErrorKind::PermissionDenied => {
+            "permission denied (ports below 1024 need privileges)".to_string()
+        }
+        _ => e.to_string(),
+    };
+    format!("Cannot bind to {}: {}", addr, reason).into()
+}
+
+// Resolve a server URL and complete the QUIC handshake with it
+async fn connect_url(
+    options: &Opt,
+    url: Url,
+    budget: Option<&ConnectionBudget>,
+) -> Result<(Endpoint, Connection, Option<ZeroRttAccepted>), Box<dyn Error + Send + Sync>> {
+    let resolve = tokio::task::spawn_blocking(move || resolve_url(&url));
+    let (remote, sni) = with_budget(budget, CONNECT_SHARE, "resolving server", resolve).await???;
+    let sni = sni.as_str();
+
     // Log connection attempt
     info!("[client] Connecting to: {} <- {}", remote, sni);
 
-    // Create QUIC endpoint with appropriate bind address
-    let endpoint = make_client_endpoint(match options.bind_addr {
-        // Use user-specified bind address if provided
-        Some(local) => local,
-        // Auto-select bind address based on remote address type
-        None => {
-            use std::net::{IpAddr::*, Ipv4Addr, Ipv6Addr};
-            if remote.is_ipv6() {
-                // Bind to IPv6 unspecified address (::) on any port
-                SocketAddr::new(V6(Ipv6Addr::UNSPECIFIED), 0)
-            } else {
-                // Bind to IPv4 unspecified address (0.0.0.0) on any port
-                SocketAddr::new(V4(Ipv4Addr::UNSPECIFIED), 0)
-            }
+    // Create QUIC endpoint with appropriate bind address, the datagrams go
+    // to the SOCKS5 relay instead of the server when a proxy is set
+    let bind_addr = options
+        .bind_addr
+        .unwrap_or_else(|| default_bind_addr(&options.socks5_proxy.unwrap_or(remote)));
+    check_bind_addr(bind_addr)?;
+    let client_cfg = configure_client(&options.transport, &options.tls)?;
+    let endpoint = match options.socks5_proxy {
+        Some(proxy) => {
+            make_socks5_endpoint(proxy, bind_addr, &options.transport, client_cfg.clone()).await?
         }
-    })?;
-    
+        None => make_client_endpoint(bind_addr, &options.transport, client_cfg.clone())?,
+    };
+
     // Establish QUIC connection to the server
-    let connection = endpoint.connect(remote, sni).unwrap().await.unwrap();
-    
+    let handshake = async {
+        if options.tls.enable_0rtt {
+            connect_0rtt(&endpoint, &client_cfg, remote, sni).await
+        } else {
+            let versions = &options.quic_supported_versions;
+            Ok((connect(&endpoint, &client_cfg, versions, remote, sni).await?, None))
+        }
+    };
+    let (connection, zero_rtt) = with_budget(budget, HANDSHAKE_SHARE, "QUIC handshake", handshake).await??;
+
     // Log successful connection
-    info!(
-        "[client] Connected to: {} <- {}",
-        connection.remote_address(),
-        sni
+    if zero_rtt.is_some() {
+        info!(
+            "[client] Resuming session with 0-RTT to: {} <- {}",
+            connection.remote_address(),
+            sni
+        );
+    } else {
+        info!(
+            "[client] Connected to: {} <- {}",
+            connection.remote_address(),
+            sni
+        );
+    }
+    Ok((endpoint, connection, zero_rtt))
+}
+
+// Connect to URL and the --fallback-url servers concurrently, keeping the first
+// connection established and cancelling the other attempts
+async fn connect_any(
+    options: &Opt,
+    budget: Option<&ConnectionBudget>,
+) -> Result<(Endpoint, Connection, Option<ZeroRttAccepted>), Box<dyn Error + Send + Sync>> {
+    let timeout = Duration::from_millis(options.connect_timeout_ms);
+    let mut attempts: FuturesUnordered<_> = std::iter::once(&options.url)
+        .chain(&options.fallback_urls)
+        .map(|url| async move {
+            match tokio::time::timeout(timeout, connect_url(options, url.clone(), budget)).await {
+                Ok(Ok(connected)) => Ok(connected),
+                Ok(Err(e)) => Err((format!("{}: {}", url, e), is_transient(e.as_ref()))),
+                Err(_) => Err((format!("{}: no connection within {} ms", url, timeout.as_millis()), true)),
+            }
+        })
+        .collect();
+
+    // Every server is reported when none of them could be reached
+    let mut errors = Vec::new();
+    let mut transient = false;
+    while let Some(result) = attempts.next().await {
+        match result {
+            Ok(connected) => return Ok(connected),
+            Err((e, retry)) => {
+                warn!("[client] connection to {} failed", e);
+                errors.push(e);
+                transient |= retry;
+            }
+        }
+    }
+    let message = format!("all servers failed: {}", errors.join("; "));
+    // Worth reconnecting when any of the servers may become reachable
+    if transient {
+        Err(std::io::Error::other(message).into())
+    } else {
+        Err(message.into())
+    }
+}
+
+// Local port forwarded to a target reached from the server
+#[derive(Debug, Clone)]
+pub struct LocalForward {
+    port: u16,
+    target: ConnectHeader,
+}
+
+// Parse PORT:HOST:HOSTPORT, with IPv6 hosts in brackets
+fn parse_local_forward(s: &str) -> Result<LocalForward, String> {
+    let invalid = || format!("invalid forward {}, expected PORT:HOST:HOSTPORT", s);
+    let (port, target) = s.split_once(':').ok_or_else(invalid)?;
+    let (host, host_port) = target.rsplit_once(':').ok_or_else(invalid)?;
+    let host = host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host);
+    if host.is_empty() {
+        return Err(invalid());
+    }
+    Ok(LocalForward {
+        port: port.parse().map_err(|_| invalid())?,
+        target: ConnectHeader {
+            host: host.to_string(),
+            port: host_port.parse().map_err(|_| invalid())?,
+        },
+    })
+}
+
+// Connect to URL, or to whichever of URL and the fallbacks answers first
+// The endpoint must outlive the connection; with 0-RTT the handshake is still
+// in progress and the future tells whether the server accepted the early data
+async fn connect_server(
+    options: &Opt,
+    url: Url,
+    budget: Option<&ConnectionBudget>,
+) -> Result<(Endpoint, Connection, Option<ZeroRttAccepted>), Box<dyn Error + Send + Sync>> {
+    if options.fallback_urls.is_empty() {
+        connect_url(options, url, budget).await
+    } else {
+        connect_any(options, budget).await
+    }
+}
+
+// Listen on the --local-forward ports and the --socks5-listen address and
+// relay each accepted connection over its own stream of a single QUIC connection
+async fn run_forwards(options: &Opt) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // Bind every port before connecting, so that mistakes are reported early
+    // A listener without a target is a SOCKS5 proxy, each client names its own
+    let mut listeners = Vec::new();
+    for forward in &options.local_forwards {
+        let addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), forward.port);
+        let listener = TcpListener::bind(addr)
+            .await
+            .map_err(|e| format!("Cannot bind to {}: {}", addr, e))?;
+        info!("[client] forwarding {} -> {}", addr, forward.target);
+        listeners.push((listener, Some(forward.target.clone())));
+    }
+    if let Some(addr) = options.socks5_listen {
+        let listener = TcpListener::bind(addr)
+            .await
+            .map_err(|e| format!("Cannot bind to {}: {}", addr, e))?;
+        info!("[client] SOCKS5 proxy listening on {}", addr);
+        listeners.push((listener, None));
+    }
+
+    let budget = options
+        .connection_timeout_ms
+        .map(|ms| ConnectionBudget::new(Duration::from_millis(ms)));
+    let (_endpoint, connection, _) = connect_server(options, options.url.clone(), budget.as_ref()).await?;
+
+    let accept = listeners
+        .into_iter()
+        .map(|(listener, target)| accept_forwards(listener, target, connection.clone()));
+    tokio::select! {
+        _ = future::join_all(accept) => {}
+        e = connection.closed() => error!("[client] connection lost: {}", e),
+        _ = create_signal_thread() => {
+            connection.close(
+                options.shutdown_error_code.into(),
+                options.shutdown_reason.as_bytes(),
+            );
+        }
+    }
+
+    info!("[client] exit client");
+    Ok(())
+}
+
+// Relay the connections accepted on a forwarded port to the target, or to the
+// target of their SOCKS5 request when there is none
+async fn accept_forwards(listener: TcpListener, target: Option<ConnectHeader>, connection: Connection) {
+    loop {
+        let (tcp, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                // Usually out of file descriptors, give the others time to close
+                error!("[client] accept forwarded connection error: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+
+        let connection = connection.clone();
+        let target = target.clone();
+        tokio::spawn(async move {
+            if let Err(e) = forward_connection(connection, tcp, peer, target).await {
+                debug!("[client] forward from {} error: {}", peer, e);
+            }
+        });
+    }
+}
+
+// Open a stream asking the server to connect to the target and relay the
+// local connection over it
+async fn forward_connection(
+    connection: Connection,
+    mut tcp: TcpStream,
+    peer: SocketAddr,
+    target: Option<ConnectHeader>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let is_socks5 = target.is_none();
+    let target = match target {
+        Some(target) => target,
+        None => {
+            let (host, port) = socks5::accept_connect(&mut tcp).await?;
+            ConnectHeader { host, port }
+        }
+    };
+
+    let (mut send, recv) = match connection.open_bi().await {
+        Ok(stream) => stream,
+        Err(e) => {
+            if is_socks5 {
+                socks5::reply(&mut tcp, socks5::REPLY_GENERAL_FAILURE).await?;
+            }
+            return Err(format!("open stream for {} error: {}", target, e).into());
+        }
+    };
+    send.write_all(&target.encode()?).await?;
+    // The server resets the stream when it cannot reach the target, which the
+    // SOCKS5 client sees as the connection closing right after success
+    if is_socks5 {
+        socks5::reply(&mut tcp, socks5::REPLY_SUCCEEDED).await?;
+    }
+
+    debug!("[client] forwarding {} -> {}", peer, target);
+    let (up, down) = relay_tcp(tcp, send, recv).await?;
+    debug!(
+        "[client] forward {} -> {} finished, {} bytes sent, {} bytes received",
+        peer, target, up, down
     );
+    Ok(())
+}
 
-    // Open bidirectional stream for communication
-    let (mut send, mut recv) = connection
+// Accept SSH connections on ADDR and relay each over its own stream of the
+// connection to the server, which the sessions share while any is open
+async fn run_multiplex(options: &Opt, addr: SocketAddr) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|e| format!("Cannot bind to {}: {}", addr, e))?;
+    info!("[client] accepting SSH connections on {}", addr);
+
+    // Sessions are keyed by the address URL resolves to when starting
+    let url = options.url.clone();
+    let (server, _) = tokio::task::spawn_blocking(move || resolve_url(&url)).await??;
+    let registry = ConnectionRegistry::default();
+
+    let accept = async {
+        loop {
+            let (tcp, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    // Usually out of file descriptors, give the others time to close
+                    error!("[client] accept ssh connection error: {}", e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+
+            // Connections are made one at a time, so sessions starting
+            // together still share one
+            let connection = match multiplexed_connection(options, &registry, server).await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    error!("[client] connection for {} failed: {}", peer, e);
+                    continue;
+                }
+            };
+            tokio::spawn(async move {
+                if let Err(e) = multiplexed_session(connection, tcp, peer).await {
+                    debug!("[client] session from {} error: {}", peer, e);
+                }
+            });
+        }
+    };
+    tokio::select! {
+        _ = accept => {}
+        _ = create_signal_thread() => {
+            registry.close_all(
+                options.shutdown_error_code.into(),
+                options.shutdown_reason.as_bytes(),
+            );
+        }
+    }
+
+    info!("[client] exit client");
+    Ok(())
+}
+
+// Connection to SERVER shared by the --multiplex sessions, made when there is
+// no live one
+async fn multiplexed_connection(
+    options: &Opt,
+    registry: &ConnectionRegistry,
+    server: SocketAddr,
+) -> Result<Connection, Box<dyn Error + Send + Sync>> {
+    if let Some(connection) = registry.get(server) {
+        debug!("[client] reusing connection to {}", server);
+        return Ok(connection);
+    }
+
+    let budget = options
+        .connection_timeout_ms
+        .map(|ms| ConnectionBudget::new(Duration::from_millis(ms)));
+    let (_endpoint, connection, zero_rtt) = connect_server(options, options.url.clone(), budget.as_ref()).await?;
+    // Streams opened before the handshake completes are lost if the server
+    // rejects 0-RTT, so the handshake is waited for
+    if let Some(accepted) = zero_rtt {
+        accepted.await;
+    }
+    registry.insert(server, connection.clone());
+    Ok(connection)
+}
+
+// Relay one SSH connection over a new stream of CONNECTION
+async fn multiplexed_session(
+    connection: Connection,
+    tcp: TcpStream,
+    peer: SocketAddr,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (send, recv) = connection
         .open_bi()
         .await
-        .map_err(|e| format!("failed to open stream: {}", e))?;
+        .map_err(StreamOpenError)?;
+    info!("[client] session from {} on stream {}", peer, send.id());
+    let (up, down) = relay_tcp(tcp, send, recv).await?;
+    info!(
+        "[client] session from {} finished, {} bytes sent, {} bytes received",
+        peer, up, down
+    );
+    Ok(())
+}
+
+// Delay before the first reconnection attempt, doubled after each failure
+const RECONNECT_INITIAL_DELAY: Duration = Duration::from_millis(500);
+
+// How a proxied session ended
+#[derive(Debug, PartialEq, Eq)]
+enum SessionEnd {
+    // The server finished the stream
+    Finished,
+    // stdin or stdout failed, the local SSH client is gone
+    LocalClosed,
+    // The QUIC connection or stream failed
+    Lost,
+    // Stopped by a signal
+    Signal,
+}
+
+// Application close codes of the server refusing the client, which it would
+// do again on a new connection
+const REJECTED_CODES: [u32; 6] = [
+    FORWARD_REJECTED_CODE,
+    PROXY_LOOP_CODE,
+    TOO_MANY_RECONNECTS_CODE,
+    HOST_KEY_MISMATCH_CODE,
+    CLIENT_CERT_REQUIRED_CODE,
+    ACCESS_DENIED_CODE,
+];
+
+// Whether connecting again may get past ERROR, i.e. the connection failed or
+// was lost on the way
+//
+// Mistakes in the options, an unresolvable URL, an address that cannot be
+// bound, a certificate not matching --server-fingerprint and the server
+// refusing the client are final. Errors built from a message only are final.
+fn is_transient(error: &(dyn Error + 'static)) -> bool {
+    if let Some(e) = error.downcast_ref::<ConnectionError>() {
+        return match e {
+            ConnectionError::TimedOut | ConnectionError::Reset => true,
+            ConnectionError::ApplicationClosed(close) => {
+                !REJECTED_CODES.iter().any(|code| close.error_code == (*code).into())
+            }
+            // The server's TLS stack refused the handshake, e.g. the client certificate
+            ConnectionError::ConnectionClosed(close) => !(0x100..0x200).contains(&u64::from(close.error_code)),
+            // Locally detected failures include the certificate verification
+            ConnectionError::TransportError(_) | ConnectionError::VersionMismatch | ConnectionError::LocallyClosed => {
+                false
+            }
+        };
+    }
+    if let Some(e) = error.downcast_ref::<std::io::Error>() {
+        return match e.get_ref() {
+            Some(inner) if inner.is::<ConnectionError>() => is_transient(inner),
+            _ => true,
+        };
+    }
+    error.source().is_some_and(is_transient)
+}
+
+// Failure to open the stream of a session on an established connection
+#[derive(Debug)]
+struct StreamOpenError(ConnectionError);
+
+impl std::fmt::Display for StreamOpenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "failed to open stream: {}", self.0)
+    }
+}
+
+impl Error for StreamOpenError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+// stdin or stdout of the sessions, recording whether any data went through
+//
+// The server starts a new SSH session on each connection, which the local SSH
+// client cannot take over once it exchanged data with the previous one.
+struct Relayed<T> {
+    inner: T,
+    started: Arc<AtomicBool>,
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Relayed<T> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if buf.filled().len() > filled {
+            self.started.store(true, Ordering::Relaxed);
+        }
+        poll
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Relayed<T> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if matches!(poll, Poll::Ready(Ok(n)) if n > 0) {
+            self.started.store(true, Ordering::Relaxed);
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+// Main async function to run the QUIC client
+#[tokio::main]
+pub async fn run(options: Opt) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // Export the session spans until the client exits
+    let otel = match &options.otel_endpoint {
+        Some(endpoint) => Some(otel::init(endpoint)?),
+        None => None,
+    };
+    let result = run_client(options).await;
+    if let Some(otel) = otel {
+        otel.shutdown().await;
+    }
+    result
+}
+
+// Proxy stdin/stdout, or the forwarded ports, through the server
+async fn run_client(options: Opt) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // stdin is read through the same buffer by every session, so reconnecting
+    // does not drop data already read
+    let (stdin, stdout) = session_stdio(&options);
+    let started = Arc::new(AtomicBool::new(false));
+    let stdin: Box<dyn AsyncRead + Unpin + Send> = Box::new(Relayed {
+        inner: stdin,
+        started: started.clone(),
+    });
+    let mut writer: SessionOutput = Box::new(Relayed {
+        inner: stdout,
+        started: started.clone(),
+    });
+    let mut reader = tokio::io::BufReader::with_capacity(options.buf_size as usize, stdin);
+
+    if options.tls.server_fingerprint.is_none() {
+        warn!(
+            "[client] server certificate is not verified, this is deprecated; \
+             pin it with --server-fingerprint (see the fingerprint command)"
+        );
+    }
+    if let Some(path) = &options.tls.keylog {
+        warn!(
+            "[client] writing TLS secrets to {}, anyone reading it can decrypt the \
+             captured traffic; do not use --keylog in production",
+            path.display()
+        );
+    }
+
+    // Port forwarding and multiplexing replace the stdin/stdout session
+    if !options.local_forwards.is_empty() || options.socks5_listen.is_some() {
+        return run_forwards(&options).await;
+    }
+    if let Some(addr) = options.multiplex {
+        return run_multiplex(&options, addr).await;
+    }
+
+    if !options.reconnect {
+        run_session(&options, &mut reader, &mut writer).instrument(session_span(&options)).await?;
+        info!("[client] exit client");
+        return Ok(());
+    }
+
+    let max_delay = Duration::from_secs(options.max_reconnect_delay_secs);
+    let mut delay = RECONNECT_INITIAL_DELAY;
+    loop {
+        match run_session(&options, &mut reader, &mut writer).instrument(session_span(&options)).await {
+            // A new session would get the rest of the SSH stream without its start
+            Ok(SessionEnd::Lost) if started.load(Ordering::Relaxed) => {
+                error!("[client] connection lost after the SSH session started, it cannot be resumed");
+                break;
+            }
+            // The connection was up, start backing off from the initial delay again
+            Ok(SessionEnd::Lost) => delay = RECONNECT_INITIAL_DELAY,
+            Ok(_) => break,
+            Err(e) if is_transient(e.as_ref()) => warn!("[client] connection failed: {}", e),
+            Err(e) => return Err(e),
+        }
+
+        warn!("[client] reconnecting in {} ms", delay.as_millis());
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = create_signal_thread() => break,
+        }
+        delay = (delay * 2).min(max_delay);
+    }
+
+    info!("[client] exit client");
+    Ok(())
+}
+
+// stdin and stdout of the sessions
+type SessionInput = tokio::io::BufReader<Box<dyn AsyncRead + Unpin + Send>>;
+type SessionOutput = Box<dyn AsyncWrite + Unpin + Send>;
+
+// Open stdin and stdout, polled by the runtime with --proxycommand
+//
+// Tokio reads stdin on a blocking thread whose read cannot be cancelled, which
+// can keep the process from exiting once ssh is done with it. Data is relayed
+// in the order it is read either way, so an SSH packet split across reads
+// reaches the server intact.
+fn session_stdio(options: &Opt) -> (Box<dyn AsyncRead + Unpin + Send>, SessionOutput) {
+    #[cfg(unix)]
+    if options.proxycommand {
+        match (RawStdio::stdin(), RawStdio::stdout()) {
+            (Ok(stdin), Ok(stdout)) => return (Box::new(stdin), Box::new(stdout)),
+            (Err(e), _) | (_, Err(e)) => warn!(
+                "[client] --proxycommand: cannot poll stdin and stdout ({}), reading them on a thread",
+                e
+            ),
+        }
+    }
+    #[cfg(not(unix))]
+    if options.proxycommand {
+        warn!("[client] --proxycommand is only supported on Unix, ignoring it");
+    }
+    (Box::new(tokio::io::stdin()), Box::new(tokio::io::stdout()))
+}
+
+// Span of a session, exported with --otel-endpoint
+fn session_span(options: &Opt) -> tracing::Span {
+    tracing::trace_span!("quicssh.client_session", url = %options.url)
+}
+
+// Connect to the server and proxy stdin/stdout over one stream until it ends
+async fn run_session(
+    options: &Opt,
+    reader: &mut SessionInput,
+    output: &mut SessionOutput,
+) -> Result<SessionEnd, Box<dyn Error + Send + Sync>> {
+    // Hops listed in --proxy-chain are traversed before the target server
+    let mut hops = options.proxy_chain.clone();
+    hops.push(options.url.clone());
+    if hops.len() > DEFAULT_FORWARD_TTL as usize {
+        return Err(format!("proxy chain is limited to {} hops", DEFAULT_FORWARD_TTL).into());
+    }
+    let url = hops.remove(0);
+
+    // Bound the time until data can flow when --connection-timeout-ms is set
+    let budget = options
+        .connection_timeout_ms
+        .map(|ms| ConnectionBudget::new(Duration::from_millis(ms)));
+
+    // The endpoint must outlive the connection
+    let (_endpoint, connection, zero_rtt) = connect_server(options, url, budget.as_ref()).await?;
+
+    // Report the datagram size MTU discovery settles on
+    if !options.transport.disable_mtu_discovery {
+        tokio::spawn(log_path_mtu(connection.clone()));
+    }
+
+    // Headers sent at the start of the stream
+    let mut preamble = Vec::new();
+
+    // Let the server span of the connection join this trace
+    if options.otel_endpoint.is_some() {
+        if let Some(traceparent) = otel::traceparent(&tracing::Span::current()) {
+            preamble.extend_from_slice(&TraceHeader { traceparent }.encode()?);
+        }
+    }
+
+    // Ask the first server to forward the stream along the rest of the chain
+    if !hops.is_empty() {
+        info!("[client] forwarding through: {:?}", hops);
+        let header = ForwardHeader {
+            ttl: DEFAULT_FORWARD_TTL,
+            hops,
+            via: Vec::new(),
+        };
+        preamble.extend_from_slice(&header.encode()?);
+    }
+
+    // Open bidirectional stream for communication
+    let buf_size = options.buf_size as usize;
+    let (mut send, mut recv) = match zero_rtt {
+        Some(accepted) => {
+            let open = open_stream_0rtt(&connection, accepted, reader, &preamble, buf_size);
+            with_budget(budget.as_ref(), STREAM_OPEN_SHARE, "opening stream", open).await??
+        }
+        None => {
+            let (mut send, recv) =
+                with_budget(budget.as_ref(), STREAM_OPEN_SHARE, "opening stream", connection.open_bi())
+                    .await?
+                    .map_err(StreamOpenError)?;
+            send.write_all(&preamble).await?;
+            (send, recv)
+        }
+    };
 
     // Task to handle receiving data from QUIC server and writing to stdout
     let recv_thread = async move {
-        // Buffer for incoming data (2KB)
-        let mut buf = vec![0; 2048];
+        // Buffer for incoming data
+        let mut buf = vec![0; buf_size];
         // Buffered writer for stdout to improve performance
-        let mut writer = tokio::io::BufWriter::new(tokio::io::stdout());
+        let mut writer = tokio::io::BufWriter::with_capacity(buf_size, output);
 
         // Continuous loop to receive data
         loop {
             match recv.read(&mut buf).await {
-                // No data received, continue waiting
+                // Server finished its side of the stream
                 Ok(None) => {
-                    continue;
+                    debug!("[client] quic server finished the stream");
+                    return SessionEnd::Finished;
                 }
                 // Data received successfully
                 Ok(Some(n)) => {
@@ -212,14 +1384,14 @@ pub async fn run(options: Opt) -> Result<(), Box<dyn Error>> {
                         Ok(_) => (),
                         Err(e) => {
                             error!("[client] write to stdout error: {}", e);
-                            return;  // Exit thread on write error
+                            return SessionEnd::LocalClosed;  // Exit thread on write error
                         }
                     }
                 }
                 // Error receiving data
                 Err(err) => {
                     error!("[client] recv data from quic server error: {}", err);
-                    return;  // Exit thread on read error
+                    return SessionEnd::Lost;  // Exit thread on read error
                 }
             }
             // Flush buffered output to ensure data is displayed
@@ -230,33 +1402,38 @@ pub async fn run(options: Opt) -> Result<(), Box<dyn Error>> {
     };
 
     // Task to handle reading data from stdin and sending to QUIC server
+    // The stream is borrowed so it can still be finished once the task is dropped
+    let stream_send = &mut send;
     let write_thread = async move {
-        // Buffer for stdin data (2KB)
-        let mut buf = [0; 2048];
-        // Buffered reader for stdin to improve performance
-        let mut reader = tokio::io::BufReader::new(tokio::io::stdin());
+        // Buffer for stdin data
+        let mut buf = vec![0; buf_size];
 
         // Continuous loop to read from stdin
         loop {
             match reader.read(&mut buf).await {
                 // Data read successfully
                 Ok(n) => {
-                    // Skip empty reads
+                    // End of stdin, finish our side and keep receiving
                     if n == 0 {
-                        continue;
+                        debug!("[client] stdin closed, finishing the stream");
+                        if let Err(e) = stream_send.finish().await {
+                            info!("[client] finish quic stream error: {}", e);
+                            return SessionEnd::Lost;
+                        }
+                        std::future::pending::<()>().await;
                     }
                     debug!("[client] recv data from stdin {} bytes", n);
                     
                     // Send data to QUIC server
-                    if send.write_all(&buf[..n]).await.is_err() {
+                    if stream_send.write_all(&buf[..n]).await.is_err() {
                         info!("[client] send data to quic server error");
-                        return;  // Exit thread on send error
+                        return SessionEnd::Lost;  // Exit thread on send error
                     }
                 }
                 // Error reading from stdin
                 Err(err) => {
                     info!("[client] recv data from stdin error: {}", err);
-                    return;  // Exit thread on read error
+                    return SessionEnd::LocalClosed;  // Exit thread on read error
                 }
             }
         }
@@ -266,51 +1443,512 @@ pub async fn run(options: Opt) -> Result<(), Box<dyn Error>> {
     let signal_thread = create_signal_thread();
 
     // Run all threads concurrently, exit when any completes
-    tokio::select! {
-        _ = recv_thread => (),     // Exit if recv thread terminates
-        _ = write_thread => (),    // Exit if write thread terminates  
-        _ = signal_thread => connection.close(0u32.into(), b"signal HUP"), // Exit on signal
+    let end = tokio::select! {
+        end = recv_thread => end,   // Exit if recv thread terminates
+        end = write_thread => end,  // Exit if write thread terminates
+        _ = signal_thread => {      // Exit on signal
+            connection.close(
+                options.shutdown_error_code.into(),
+                options.shutdown_reason.as_bytes(),
+            );
+            SessionEnd::Signal
+        }
+    };
+
+    // Close our side too once the server reached EOF, it may already be
+    // finished if stdin was closed first
+    if end == SessionEnd::Finished {
+        let _ = send.finish().await;
     }
 
-    // Log client shutdown
-    info!("[client] exit client");
+    // The server may have refused the client right after the handshake
+    if end == SessionEnd::Lost {
+        if let Some(reason) = connection.close_reason().filter(|reason| !is_transient(reason)) {
+            return Err(reason.into());
+        }
+    }
+
+    Ok(end)
+}
+
+// Log the maximum datagram size each time MTU discovery changes it
+//
+// Quinn does not expose the path MTU, the datagram size is derived from it.
+// Stops once the connection is closed.
+async fn log_path_mtu(connection: Connection) {
+    let mut last = connection.max_datagram_size();
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        tokio::select! {
+            _ = connection.closed() => return,
+            _ = ticker.tick() => {}
+        }
+        let size = connection.max_datagram_size();
+        if size != last {
+            if let Some(size) = size {
+                info!("[client] path MTU probed, max datagram size {} bytes", size);
+            }
+            last = size;
+        }
+    }
+}
+
+// Connect to the server with 0-RTT when a session ticket of an earlier
+// connection to it is cached, otherwise wait for the handshake as connect() does
+//
+// With 0-RTT the connection is returned right away, along with the future
+// telling whether the server accepted the data sent before the handshake completed.
+pub async fn connect_0rtt(
+    endpoint: &Endpoint,
+    client_cfg: &ClientConfig,
+    remote: SocketAddr,
+    sni: &str,
+) -> Result<(Connection, Option<ZeroRttAccepted>), Box<dyn Error + Send + Sync>> {
+    match endpoint.connect_with(client_cfg.clone(), remote, sni)?.into_0rtt() {
+        Ok((connection, accepted)) => Ok((connection, Some(accepted))),
+        Err(connecting) => Ok((connecting.await?, None)),
+    }
+}
+
+// Open the stream of a session resumed with 0-RTT and send PREAMBLE on it,
+// followed by what stdin provides before the handshake completes
+//
+// The server can reject the 0-RTT data, e.g. after a restart lost its session
+// tickets; the data is then sent again on a new stream. SSH clients start
+// with their version string and key exchange offer, which are safe to replay.
+pub async fn open_stream_0rtt(
+    connection: &Connection,
+    accepted: ZeroRttAccepted,
+    reader: &mut (impl AsyncRead + Unpin),
+    preamble: &[u8],
+    buf_size: usize,
+) -> Result<(SendStream, RecvStream), Box<dyn Error + Send + Sync>> {
+    let (mut send, recv) = connection
+        .open_bi()
+        .await
+        .map_err(StreamOpenError)?;
+    let mut early = preamble.to_vec();
+    // Writes fail once the server rejected the data, it is sent again below
+    let _ = send.write_all(preamble).await;
+
+    // A single read, so the data kept for a retry stays below BUF_SIZE
+    let mut accepted = std::pin::pin!(accepted);
+    let mut buf = vec![0; buf_size];
+    let accepted = tokio::select! {
+        accepted = &mut accepted => accepted,
+        read = reader.read(&mut buf) => {
+            let n = read?;
+            debug!("[client] sending {} bytes of stdin as 0-RTT data", n);
+            early.extend_from_slice(&buf[..n]);
+            let _ = send.write_all(&buf[..n]).await;
+            accepted.await
+        }
+    };
+    if accepted {
+        debug!("[client] server accepted the 0-RTT data");
+        return Ok((send, recv));
+    }
+
+    info!("[client] server rejected the 0-RTT data, sending it again");
+    let (mut send, recv) = connection
+        .open_bi()
+        .await
+        .map_err(StreamOpenError)?;
+    send.write_all(&early).await?;
+    Ok((send, recv))
+}
+
+// Connect to the server, trying each configured QUIC version in order
+// Quinn reports a version mismatch when the server's Version Negotiation
+// packet lists none of the versions offered, so fall back to the next one
+pub async fn connect(
+    endpoint: &Endpoint,
+    client_cfg: &ClientConfig,
+    versions: &[u32],
+    remote: SocketAddr,
+    sni: &str,
+) -> Result<Connection, Box<dyn Error + Send + Sync>> {
+    // Let Quinn pick its default version when none are configured
+    if versions.is_empty() {
+        return Ok(endpoint.connect(remote, sni)?.await?);
+    }
+
+    for version in versions {
+        // Offer a single version per attempt
+        let mut config = client_cfg.clone();
+        config.version(*version);
+        info!("[client] trying QUIC version {:#x}", version);
+
+        match endpoint.connect_with(config, remote, sni)?.await {
+            Ok(connection) => return Ok(connection),
+            Err(ConnectionError::VersionMismatch) => {
+                warn!("[client] server does not support QUIC version {:#x}", version);
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Err(format!(
+        "server supports none of the QUIC versions: {}",
+        format_quic_versions(versions)
+    )
+    .into())
+}
+
+// Connect to a server and print the SHA-256 fingerprint of its certificate
+// The value can be passed to --server-fingerprint to pin the certificate
+#[tokio::main]
+pub async fn print_fingerprint(options: FingerprintOpt, format: OutputFormat) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (remote, sni) = resolve_url(&options.url)?;
+    let bind_addr = options
+        .bind_addr
+        .unwrap_or_else(|| default_bind_addr(&remote));
+    check_bind_addr(bind_addr)?;
 
+    // Accept any certificate, it is only inspected
+    let transport = TransportOpt::default();
+    let client_cfg = configure_client(&transport, &ClientTlsOpt::default())?;
+    let endpoint = make_client_endpoint(bind_addr, &transport, client_cfg.clone())?;
+    let connection = connect(&endpoint, &client_cfg, &[], remote, &sni).await?;
+
+    let cert = connection
+        .peer_identity()
+        .and_then(|identity| identity.downcast::<Vec<rustls::Certificate>>().ok())
+        .and_then(|chain| chain.first().cloned())
+        .ok_or("server presented no certificate")?;
+    let fingerprint = hex::encode(tls::cert_digest(&cert));
+    match format {
+        OutputFormat::Json => output::print_json(&json!({ "fingerprint": fingerprint }))?,
+        OutputFormat::Text => println!("{}", fingerprint),
+    }
+
+    connection.close(0u32.into(), b"done");
+    endpoint.wait_idle().await;
     Ok(())
 }
 
-// Windows-specific signal handler for Ctrl-C
-#[cfg(windows)]
-fn create_signal_thread() -> impl core::future::Future<Output = ()> {
-    async move {
-        // Create Ctrl-C signal stream
-        let mut stream = match ctrl_c() {
-            Ok(s) => s,
-            Err(e) => {
-                error!("[client] create signal stream error: {}", e);
-                return;
+// Perform a QUIC handshake with a server and report how it went, like ping
+// Nothing is sent over the connection, which is closed right away
+#[tokio::main]
+pub async fn test_connection(options: TestOpt, format: OutputFormat) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (remote, sni) = resolve_url(&options.url)?;
+    let bind_addr = options
+        .bind_addr
+        .unwrap_or_else(|| default_bind_addr(&remote));
+    check_bind_addr(bind_addr)?;
+
+    // The handshake fails when the certificate does not match --fingerprint
+    let transport = TransportOpt::default();
+    let tls = ClientTlsOpt {
+        server_fingerprint: options.fingerprint,
+        ..ClientTlsOpt::default()
+    };
+    let client_cfg = configure_client(&transport, &tls)?;
+    let endpoint = make_client_endpoint(bind_addr, &transport, client_cfg)?;
+
+    let start = Instant::now();
+    let timeout = Duration::from_millis(options.timeout_ms);
+    let connection = tokio::time::timeout(timeout, endpoint.connect(remote, &sni)?)
+        .await
+        .map_err(|_| format!("no answer from {} within {} ms", remote, options.timeout_ms))?
+        .map_err(|e| format!("handshake with {} failed: {}", remote, e))?;
+    let handshake = start.elapsed();
+
+    let alpn = connection
+        .handshake_data()
+        .and_then(|data| data.downcast::<quinn::crypto::rustls::HandshakeData>().ok())
+        .and_then(|data| data.protocol)
+        .map(|protocol| String::from_utf8_lossy(&protocol).into_owned());
+    let fingerprint = connection
+        .peer_identity()
+        .and_then(|identity| identity.downcast::<Vec<rustls::Certificate>>().ok())
+        .and_then(|chain| chain.first().map(tls::cert_digest))
+        .map(hex::encode);
+    let rtt = connection.rtt();
+    // Give MTU discovery a few round trips to probe larger datagrams
+    tokio::time::sleep((rtt * 4).min(Duration::from_secs(1))).await;
+    let max_datagram = connection.max_datagram_size();
+    connection.close(0u32.into(), b"done");
+
+    // Quinn uses the first version it implements when none is configured
+    let quic_version = format_quic_versions(&QUIC_VERSIONS[..1]);
+    if format == OutputFormat::Json {
+        output::print_json(&json!({
+            "url": options.url.as_str(),
+            "remote": remote,
+            "handshake_ms": handshake.as_secs_f64() * 1000.0,
+            "rtt_ms": rtt.as_secs_f64() * 1000.0,
+            "alpn": alpn,
+            "fingerprint": fingerprint,
+            "quic_version": quic_version,
+            "max_idle_timeout_secs": transport.idle_timeout_secs,
+            "max_datagram_size": max_datagram,
+        }))?;
+        endpoint.wait_idle().await;
+        return Ok(());
+    }
+    println!("connected to {} ({})", options.url, remote);
+    println!("  handshake time:   {:.1} ms", handshake.as_secs_f64() * 1000.0);
+    println!("  rtt:              {:.1} ms", rtt.as_secs_f64() * 1000.0);
+    println!("  alpn:             {}", alpn.as_deref().unwrap_or("none"));
+    println!("  fingerprint:      {}", fingerprint.as_deref().unwrap_or("none"));
+    println!("  quic version:     {}", quic_version);
+    println!("  max idle timeout: {} s (client)", transport.idle_timeout_secs);
+    // Quinn does not expose the path MTU, the datagram size is derived from it
+    let max_datagram = max_datagram.map_or("n/a".to_string(), |size| format!("{} bytes", size));
+    println!("  max datagram:     {}", max_datagram);
+
+    endpoint.wait_idle().await;
+    Ok(())
+}
+
+// Connect to a server and print the certificate chain it presents
+#[tokio::main]
+pub async fn show_cert(options: ShowCertOpt, format: OutputFormat) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (remote, sni) = resolve_url(&options.url)?;
+    let bind_addr = options
+        .bind_addr
+        .unwrap_or_else(|| default_bind_addr(&remote));
+    check_bind_addr(bind_addr)?;
+
+    let transport = TransportOpt::default();
+    let verifier = Arc::new(RecordingVerifier::default());
+    let client_cfg = configure_client_with_verifier(&transport, &ClientTlsOpt::default(), verifier.clone())?;
+    let endpoint = make_client_endpoint(bind_addr, &transport, client_cfg)?;
+
+    // The certificates are recorded before the handshake completes, so a
+    // server requiring a client certificate still shows its own
+    let timeout = Duration::from_millis(options.timeout_ms);
+    let result = tokio::time::timeout(timeout, endpoint.connect(remote, &sni)?).await;
+    if let Ok(Ok(connection)) = &result {
+        connection.close(0u32.into(), b"done");
+    }
+    let chain = verifier.chain();
+    if chain.is_empty() {
+        return Err(match result {
+            Err(_) => format!("no answer from {} within {} ms", remote, options.timeout_ms),
+            Ok(Err(e)) => format!("handshake with {} failed: {}", remote, e),
+            Ok(Ok(_)) => "server presented no certificate".to_string(),
+        }
+        .into());
+    }
+
+    let certs = chain.iter().map(tls::cert_info).collect::<Result<Vec<_>, _>>()?;
+    match (format, options.format) {
+        (OutputFormat::Json, _) => {
+            output::print_json(&json!({ "url": options.url.as_str(), "certificates": certs }))?
+        }
+        (_, CertOutputFormat::Json) => println!("{}", serde_json::to_string_pretty(&certs)?),
+        (_, CertOutputFormat::Text) => {
+            for (i, cert) in certs.iter().enumerate() {
+                if i > 0 {
+                    println!();
+                }
+                println!("certificate {}:", i);
+                println!("  subject:     {}", cert.subject);
+                println!("  issuer:      {}", cert.issuer);
+                println!("  sans:        {}", cert.sans.join(", "));
+                println!("  not before:  {}", cert.not_before);
+                println!("  not after:   {}", cert.not_after);
+                println!("  fingerprint: {}", cert.fingerprint);
             }
-        };
+        }
+    }
+
+    endpoint.wait_idle().await;
+    Ok(())
+}
+
+// Builder of a client connection for programs embedding the client instead
+// of relaying stdin and stdout, e.g.
+// ClientBuilder::new(url).keep_alive_secs(5).connect().await
+pub struct ClientBuilder {
+    url: Url,
+    bind_addr: Option<SocketAddr>,
+    // Hex-encoded, checked by connect()
+    server_fingerprint: Option<String>,
+    versions: Vec<u32>,
+    transport: TransportOpt,
+    tls: ClientTlsOpt,
+}
+
+impl ClientBuilder {
+    // Connect to URL with the default options
+    pub fn new(url: Url) -> Self {
+        ClientBuilder {
+            url,
+            bind_addr: None,
+            server_fingerprint: None,
+            versions: Vec::new(),
+            transport: TransportOpt::default(),
+            tls: ClientTlsOpt::default(),
+        }
+    }
+
+    // Connect with the URL and options of profile NAME of the profiles file
+    // (~/.config/quicssh/profiles.toml or $QUICSSH_CONFIG), as client --profile
+    pub fn from_profile(name: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let argv: Vec<OsString> = vec!["client".into(), "--profile".into(), name.into()];
+        let matches = Opt::command().try_get_matches_from(&argv)?;
+        let extra = profile_args(&matches)?;
+        let options = Opt::try_parse_from(argv.into_iter().chain(extra))?;
+        Ok(ClientBuilder {
+            url: options.url,
+            bind_addr: options.bind_addr,
+            server_fingerprint: None,
+            versions: options.quic_supported_versions,
+            transport: options.transport,
+            tls: options.tls,
+        })
+    }
+
+    // Local address to bind the client to
+    pub fn bind_addr(mut self, addr: SocketAddr) -> Self {
+        self.bind_addr = Some(addr);
+        self
+    }
+
+    // Hex-encoded SHA-256 fingerprint the server certificate must match
+    pub fn server_fingerprint(mut self, hex: &str) -> Self {
+        self.server_fingerprint = Some(hex.to_string());
+        self
+    }
+
+    // Interval between keep-alive packets
+    pub fn keep_alive_secs(mut self, secs: u64) -> Self {
+        self.transport.keep_alive_secs = secs;
+        self
+    }
+
+    // Connect to the server and open the stream relayed to its SSH backend
+    pub async fn connect(mut self) -> Result<QuicSshClient, Box<dyn Error + Send + Sync>> {
+        if let Some(hex) = &self.server_fingerprint {
+            self.tls.server_fingerprint = Some(tls::parse_cert_fingerprint(hex)?);
+        }
+        let (remote, sni) = resolve_url(&self.url)?;
+        let bind_addr = self.bind_addr.unwrap_or_else(|| default_bind_addr(&remote));
+        let client_cfg = configure_client(&self.transport, &self.tls)?;
+        let endpoint = make_client_endpoint(bind_addr, &self.transport, client_cfg.clone())?;
+        let connection = connect(&endpoint, &client_cfg, &self.versions, remote, &sni).await?;
+        let (send, recv) = connection.open_bi().await?;
+        info!("[client] connected to {} ({})", self.url, remote);
+        Ok(QuicSshClient {
+            endpoint,
+            connection,
+            send,
+            recv,
+        })
+    }
+}
+
+// Client connection opened by ClientBuilder::connect()
+//
+// Bytes written to the send stream reach the SSH server and its replies are
+// read from the receive stream, as ssh would through stdin and stdout.
+pub struct QuicSshClient {
+    endpoint: Endpoint,
+    connection: Connection,
+    send: SendStream,
+    recv: RecvStream,
+}
 
-        // Wait for Ctrl-C signal
-        stream.recv().await;
-        info!("[client] got signal Ctrl-C");
+impl QuicSshClient {
+    // Stream to the SSH server
+    pub fn send_stream(&mut self) -> &mut SendStream {
+        &mut self.send
     }
+
+    // Stream from the SSH server
+    pub fn recv_stream(&mut self) -> &mut RecvStream {
+        &mut self.recv
+    }
+
+    // Underlying QUIC connection, e.g. for its statistics
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    // Finish the send stream and close the connection, waiting until the
+    // server was told
+    pub async fn close(mut self) {
+        let _ = self.send.finish().await;
+        self.connection.close(0u32.into(), b"done");
+        self.endpoint.wait_idle().await;
+    }
+}
+
+// Windows-specific signal handler for Ctrl-C
+#[cfg(windows)]
+async fn create_signal_thread() {
+    // Create Ctrl-C signal stream
+    let mut stream = match ctrl_c() {
+        Ok(s) => s,
+        Err(e) => {
+            error!("[client] create signal stream error: {}", e);
+            return;
+        }
+    };
+
+    // Wait for Ctrl-C signal
+    stream.recv().await;
+    info!("[client] got signal Ctrl-C");
 }
 // Unix-specific signal handler for SIGHUP
 #[cfg(not(windows))]
-fn create_signal_thread() -> impl core::future::Future<Output = ()> {
-    async move {
-        // Create SIGHUP signal stream
-        let mut stream = match signal(SignalKind::hangup()) {
-            Ok(s) => s,
-            Err(e) => {
-                error!("[client] create signal stream error: {}", e);
-                return;
-            }
-        };
+async fn create_signal_thread() {
+    // Create SIGHUP signal stream
+    let mut stream = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("[client] create signal stream error: {}", e);
+            return;
+        }
+    };
+
+    // Wait for SIGHUP signal
+    stream.recv().await;
+    info!("[client] got signal HUP");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quinn::ApplicationClose;
+
+    // Connection closed by the server with CODE
+    fn closed_by_server(code: u32) -> ConnectionError {
+        ConnectionError::ApplicationClosed(ApplicationClose {
+            error_code: code.into(),
+            reason: Default::default(),
+        })
+    }
+
+    #[test]
+    fn lost_connections_are_transient() {
+        assert!(is_transient(&ConnectionError::TimedOut));
+        assert!(is_transient(&ConnectionError::Reset));
+        // Server shutting down or restarting
+        assert!(is_transient(&closed_by_server(0)));
+        // Errors wrapping a lost connection, as returned by streams and timeouts
+        assert!(is_transient(&StreamOpenError(ConnectionError::TimedOut)));
+        assert!(is_transient(&quinn::WriteError::ConnectionLost(ConnectionError::Reset)));
+        assert!(is_transient(&std::io::Error::from(ConnectionError::TimedOut)));
+        assert!(is_transient(&std::io::Error::new(std::io::ErrorKind::TimedOut, "QUIC handshake timed out")));
+    }
+
+    #[test]
+    fn refusals_and_mistakes_are_final() {
+        for code in REJECTED_CODES {
+            assert!(!is_transient(&closed_by_server(code)), "close code {:#x} retried", code);
+            assert!(!is_transient(&StreamOpenError(closed_by_server(code))));
+            assert!(!is_transient(&std::io::Error::from(closed_by_server(code))));
+        }
+        assert!(!is_transient(&ConnectionError::VersionMismatch));
+        assert!(!is_transient(&ConnectionError::LocallyClosed));
 
-        // Wait for SIGHUP signal
-        stream.recv().await;
-        info!("[client] got signal HUP");
+        let unresolvable = Url::parse("quic://quicssh.invalid:4433").unwrap();
+        assert!(!is_transient(resolve_url(&unresolvable).unwrap_err().as_ref()));
+        let in_use = std::io::Error::from(std::io::ErrorKind::AddrInUse);
+        assert!(!is_transient(bind_error("127.0.0.1:4433".parse().unwrap(), in_use).as_ref()));
     }
 }
@@ -0,0 +1,50 @@
+// Import log4rs encoder traits and the pattern encoder the colors wrap
+use log4rs::encode::pattern::PatternEncoder;
+use log4rs::encode::{Encode, Write};
+// Import log types to read the level of a record
+use log::{Level, Record};
+
+// ANSI sequence restoring the default color
+const RESET: &[u8] = b"\x1b[0m";
+
+// Default pattern of log4rs without its line ending, which follows the reset
+const PATTERN: &str = "{d} {l} {t} - {m}";
+
+// Encoder coloring each record by level with ANSI escape codes
+//
+// The line itself is formatted by the wrapped pattern encoder; only meant for
+// terminals, log files never get the escape codes.
+#[derive(Debug)]
+pub struct ColorEncoder {
+    inner: PatternEncoder,
+}
+
+impl Default for ColorEncoder {
+    fn default() -> Self {
+        ColorEncoder {
+            inner: PatternEncoder::new(PATTERN),
+        }
+    }
+}
+
+impl Encode for ColorEncoder {
+    fn encode(&self, w: &mut dyn Write, record: &Record) -> anyhow::Result<()> {
+        w.write_all(level_color(record.level()))?;
+        let result = self.inner.encode(w, record);
+        // Reset even when the record could not be formatted
+        w.write_all(RESET)?;
+        w.write_all(b"\n")?;
+        result
+    }
+}
+
+// ANSI sequence setting the color of a level
+fn level_color(level: Level) -> &'static [u8] {
+    match level {
+        Level::Error => b"\x1b[31m",
+        Level::Warn => b"\x1b[33m",
+        Level::Info => b"\x1b[32m",
+        Level::Debug => b"\x1b[36m",
+        Level::Trace => b"\x1b[37m",
+    }
+}
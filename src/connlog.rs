@@ -0,0 +1,447 @@
+// Import clap for the format selection enum
+use clap::ValueEnum;
+// Import serde for JSON serialization of connection records
+use serde::Serialize;
+// Import the channel serializing access log writes
+use tokio::sync::mpsc;
+// Import standard library formatting, file and synchronization utilities
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+// Import the server-wide byte counters
+use crate::metrics;
+
+// Output format of the per-connection log record
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionLogFormat {
+    // Space-separated key=value pairs written to the debug log
+    Text,
+    // Single-line JSON object written to the debug log
+    Json,
+    // Comma-separated values appended to the --connection-log file
+    Csv,
+}
+
+// Byte counters updated by the proxy tasks of a single connection
+// Updates also go to the server-wide metrics
+#[derive(Debug, Default)]
+pub struct TrafficCounters {
+    // Bytes received from the QUIC client and written to the backend
+    pub bytes_in: AtomicU64,
+    // Bytes received from the backend and written to the QUIC client
+    pub bytes_out: AtomicU64,
+}
+
+impl TrafficCounters {
+    // Record bytes forwarded from the client to the backend
+    pub fn add_in(&self, n: usize) {
+        self.bytes_in.fetch_add(n as u64, Ordering::Relaxed);
+        metrics::add_bytes_to_ssh(n);
+    }
+
+    // Record bytes forwarded from the backend to the client
+    pub fn add_out(&self, n: usize) {
+        self.bytes_out.fetch_add(n as u64, Ordering::Relaxed);
+        metrics::add_bytes_to_quic(n);
+    }
+}
+
+// Summary of a finished connection
+#[derive(Serialize, Debug, Clone)]
+pub struct ConnectionLogRecord {
+    // Time the connection was closed (RFC 3339)
+    pub timestamp: String,
+    // Quinn stable connection ID
+    pub connection_id: usize,
+    // Client address as seen by the server
+    pub remote_addr: String,
+    // Server name requested by the client (or its IP without SNI)
+    pub sni: String,
+    // Backend the connection was proxied to
+    pub backend: String,
+    // Connection lifetime in milliseconds
+    pub duration_ms: u128,
+    // Bytes forwarded from the client to the backend
+    pub bytes_in: u64,
+    // Bytes forwarded from the backend to the client
+    pub bytes_out: u64,
+    // Last RTT estimate of the QUIC path in milliseconds
+    pub rtt_ms: u128,
+    // QUIC packets declared lost, and so retransmitted
+    pub lost_packets: u64,
+    // Deployment environment given by --environment
+    pub environment: String,
+}
+
+// Column names of the CSV connection log, in field order
+const CSV_HEADER: &str =
+    "timestamp,connection_id,remote_addr,sni,backend,duration_ms,bytes_in,bytes_out,environment,rtt_ms,lost_packets";
+
+// Text format: space-separated key=value pairs
+impl fmt::Display for ConnectionLogRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "timestamp={} connection_id={} remote_addr={} sni={} backend={} duration_ms={} bytes_in={} bytes_out={} rtt_ms={} lost_packets={} environment={}",
+            self.timestamp,
+            self.connection_id,
+            self.remote_addr,
+            self.sni,
+            self.backend,
+            self.duration_ms,
+            self.bytes_in,
+            self.bytes_out,
+            self.rtt_ms,
+            self.lost_packets,
+            self.environment
+        )
+    }
+}
+
+// CSV format wrapper for a connection record
+pub struct Csv<'a>(pub &'a ConnectionLogRecord);
+
+// Quote a CSV field when it contains a separator, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+impl fmt::Display for Csv<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let r = self.0;
+        write!(
+            f,
+            "{},{},{},{},{},{},{},{},{},{},{}",
+            csv_field(&r.timestamp),
+            r.connection_id,
+            csv_field(&r.remote_addr),
+            csv_field(&r.sni),
+            csv_field(&r.backend),
+            r.duration_ms,
+            r.bytes_in,
+            r.bytes_out,
+            csv_field(&r.environment),
+            r.rtt_ms,
+            r.lost_packets
+        )
+    }
+}
+
+// Writes connection records in the selected format
+pub struct ConnectionLogger {
+    format: ConnectionLogFormat,
+    // CSV output file, only used in CSV mode
+    csv_file: Option<Mutex<File>>,
+}
+
+impl ConnectionLogger {
+    // Create a logger, opening the CSV file in append mode when needed
+    pub fn new(format: ConnectionLogFormat, path: Option<&Path>) -> std::io::Result<Self> {
+        let csv_file = match (format, path) {
+            (ConnectionLogFormat::Csv, Some(path)) => {
+                let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+                // Write the header only when starting a new file
+                if file.metadata()?.len() == 0 {
+                    writeln!(file, "{}", CSV_HEADER)?;
+                }
+                Some(Mutex::new(file))
+            }
+            _ => None,
+        };
+
+        Ok(ConnectionLogger { format, csv_file })
+    }
+
+    // Emit a record for a finished connection
+    pub fn log(&self, record: &ConnectionLogRecord) {
+        match self.format {
+            ConnectionLogFormat::Text => {
+                log::info!(
+                    session_id = record.connection_id,
+                    remote_addr = record.remote_addr.as_str(),
+                    backend = record.backend.as_str();
+                    "[server] connection closed: {}",
+                    record
+                );
+            }
+            ConnectionLogFormat::Json => match serde_json::to_string(record) {
+                Ok(json) => log::info!(
+                    session_id = record.connection_id,
+                    remote_addr = record.remote_addr.as_str(),
+                    backend = record.backend.as_str();
+                    "[server] connection closed: {}",
+                    json
+                ),
+                Err(e) => log::error!("[server] serialize connection record error: {}", e),
+            },
+            ConnectionLogFormat::Csv => {
+                if let Some(file) = &self.csv_file {
+                    let mut file = file.lock().unwrap();
+                    if let Err(e) = writeln!(file, "{}", Csv(record)) {
+                        log::error!("[server] write connection log error: {}", e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Field of an access log line
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum AccessLogField {
+    // Text copied as is
+    Literal(String),
+    // %t: time the connection was closed (RFC 3339)
+    Timestamp,
+    // %r: client IP and port
+    RemoteAddr,
+    // %s: server name requested by the client
+    Sni,
+    // %b: backend the connection was proxied to
+    Backend,
+    // %I: bytes from the client to the backend
+    BytesIn,
+    // %O: bytes from the backend to the client
+    BytesOut,
+    // %D: connection lifetime in milliseconds
+    Duration,
+}
+
+// Access log line format, parsed once from a string such as "%t %r %s"
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccessLogFormat(Vec<AccessLogField>);
+
+// Format of the access log when --access-log-format is not given
+pub const DEFAULT_ACCESS_LOG_FORMAT: &str = "%t %r %s %I %O %D";
+
+// Parse an access log format; %% is a literal percent sign
+pub fn parse_access_log_format(s: &str) -> Result<AccessLogFormat, String> {
+    let mut fields = Vec::new();
+    let mut literal = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            literal.push(c);
+            continue;
+        }
+        let field = match chars.next() {
+            Some('%') => {
+                literal.push('%');
+                continue;
+            }
+            Some('t') => AccessLogField::Timestamp,
+            Some('r') => AccessLogField::RemoteAddr,
+            Some('s') => AccessLogField::Sni,
+            Some('b') => AccessLogField::Backend,
+            Some('I') => AccessLogField::BytesIn,
+            Some('O') => AccessLogField::BytesOut,
+            Some('D') => AccessLogField::Duration,
+            Some(other) => return Err(format!("unknown access log field %{}", other)),
+            None => return Err("access log format ends with a lone %".to_string()),
+        };
+        if !literal.is_empty() {
+            fields.push(AccessLogField::Literal(std::mem::take(&mut literal)));
+        }
+        fields.push(field);
+    }
+    if !literal.is_empty() {
+        fields.push(AccessLogField::Literal(literal));
+    }
+    Ok(AccessLogFormat(fields))
+}
+
+// Access log line of a connection record
+struct AccessLogLine<'a>(&'a AccessLogFormat, &'a ConnectionLogRecord);
+
+impl fmt::Display for AccessLogLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let r = self.1;
+        for field in &self.0 .0 {
+            match field {
+                AccessLogField::Literal(text) => f.write_str(text)?,
+                AccessLogField::Timestamp => f.write_str(&r.timestamp)?,
+                AccessLogField::RemoteAddr => f.write_str(&r.remote_addr)?,
+                AccessLogField::Sni => f.write_str(&r.sni)?,
+                AccessLogField::Backend => f.write_str(&r.backend)?,
+                AccessLogField::BytesIn => write!(f, "{}", r.bytes_in)?,
+                AccessLogField::BytesOut => write!(f, "{}", r.bytes_out)?,
+                AccessLogField::Duration => write!(f, "{}", r.duration_ms)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// Request to the access log writer task
+enum AccessLogMessage {
+    Line(String),
+    // Open the file again, after it was moved away by log rotation
+    Reopen,
+}
+
+// Access log appending one line per finished connection
+//
+// Lines are written by a single task, so lines of concurrent connections
+// never interleave.
+pub struct AccessLog {
+    format: AccessLogFormat,
+    // Write each record as a JSON object instead of FORMAT
+    json: bool,
+    sender: mpsc::UnboundedSender<AccessLogMessage>,
+}
+
+impl AccessLog {
+    // Open PATH in append mode and start the writer task
+    pub fn open(path: &Path, format: AccessLogFormat, json: bool) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(write_access_log(path.to_path_buf(), file, receiver));
+        Ok(AccessLog {
+            format,
+            json,
+            sender,
+        })
+    }
+
+    // Queue the line of a finished connection
+    pub fn log(&self, record: &ConnectionLogRecord) {
+        let line = match self.json {
+            true => serde_json::to_string(record).unwrap_or_default(),
+            false => AccessLogLine(&self.format, record).to_string(),
+        };
+        let _ = self.sender.send(AccessLogMessage::Line(line));
+    }
+
+    // Reopen the file, e.g. on SIGHUP after logrotate moved it
+    pub fn reopen(&self) {
+        let _ = self.sender.send(AccessLogMessage::Reopen);
+    }
+}
+
+// Write the queued access log lines until the access log is dropped
+async fn write_access_log(
+    path: PathBuf,
+    mut file: File,
+    mut receiver: mpsc::UnboundedReceiver<AccessLogMessage>,
+) {
+    while let Some(message) = receiver.recv().await {
+        match message {
+            AccessLogMessage::Line(line) => {
+                if let Err(e) = writeln!(file, "{}", line) {
+                    log::error!("[server] write access log error: {}", e);
+                }
+            }
+            AccessLogMessage::Reopen => {
+                match OpenOptions::new().create(true).append(true).open(&path) {
+                    Ok(new_file) => {
+                        file = new_file;
+                        log::info!("[server] reopened access log {}", path.display());
+                    }
+                    Err(e) => {
+                        log::error!("[server] reopen access log {} error: {}", path.display(), e)
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record() -> ConnectionLogRecord {
+        ConnectionLogRecord {
+            timestamp: "2024-05-01T12:00:00+00:00".to_string(),
+            connection_id: 7,
+            remote_addr: "192.0.2.1:50000".to_string(),
+            sni: "host.example.com".to_string(),
+            backend: "10.0.0.1:22".to_string(),
+            duration_ms: 1500,
+            bytes_in: 100,
+            bytes_out: 2000,
+            rtt_ms: 12,
+            lost_packets: 3,
+            environment: "prod".to_string(),
+        }
+    }
+
+    #[test]
+    fn text_record() {
+        assert_eq!(
+            record().to_string(),
+            "timestamp=2024-05-01T12:00:00+00:00 connection_id=7 remote_addr=192.0.2.1:50000 \
+             sni=host.example.com backend=10.0.0.1:22 duration_ms=1500 bytes_in=100 bytes_out=2000 \
+             rtt_ms=12 lost_packets=3 environment=prod"
+        );
+    }
+
+    #[test]
+    fn json_record() {
+        let json: serde_json::Value =
+            serde_json::from_str(&serde_json::to_string(&record()).unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "timestamp": "2024-05-01T12:00:00+00:00",
+                "connection_id": 7,
+                "remote_addr": "192.0.2.1:50000",
+                "sni": "host.example.com",
+                "backend": "10.0.0.1:22",
+                "duration_ms": 1500,
+                "bytes_in": 100,
+                "bytes_out": 2000,
+                "rtt_ms": 12,
+                "lost_packets": 3,
+                "environment": "prod",
+            })
+        );
+    }
+
+    #[test]
+    fn csv_record_follows_header() {
+        let line = Csv(&record()).to_string();
+        assert_eq!(
+            line,
+            "2024-05-01T12:00:00+00:00,7,192.0.2.1:50000,host.example.com,10.0.0.1:22,1500,100,2000,prod,12,3"
+        );
+        assert_eq!(line.split(',').count(), CSV_HEADER.split(',').count());
+    }
+
+    #[test]
+    fn csv_fields_quoted() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("line\nbreak"), "\"line\nbreak\"");
+        assert_eq!(csv_field("line\rbreak"), "\"line\rbreak\"");
+
+        let mut record = record();
+        record.sni = "evil\r\nsni,\"x\"".to_string();
+        assert!(Csv(&record)
+            .to_string()
+            .contains(",\"evil\r\nsni,\"\"x\"\"\","));
+    }
+
+    #[test]
+    fn csv_log_written_after_header() {
+        let path =
+            std::env::temp_dir().join(format!("quicssh-connlog-test-{}.csv", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        for _ in 0..2 {
+            let logger = ConnectionLogger::new(ConnectionLogFormat::Csv, Some(&path)).unwrap();
+            logger.log(&record());
+        }
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let line = Csv(&record()).to_string();
+        assert_eq!(content, format!("{}\n{}\n{}\n", CSV_HEADER, line, line));
+    }
+}
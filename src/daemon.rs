@@ -0,0 +1,86 @@
+// Import nix process control to fork into the background
+use nix::unistd::{dup2, fork, pipe, setsid, ForkResult};
+// Import standard library error handling, file and descriptor utilities
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// Write end of the pipe the parent waits on, until the server is listening
+static READY: Mutex<Option<File>> = Mutex::new(None);
+
+// PID file of the running server, removed when the server exits
+pub struct PidFile {
+    path: PathBuf,
+    // Process that wrote the file, forked workers leave it alone
+    pid: u32,
+}
+
+impl PidFile {
+    // Write the PID of this process to PATH
+    pub fn create(path: &Path) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let pid = std::process::id();
+        fs::write(path, format!("{}\n", pid))
+            .map_err(|e| format!("cannot write PID file {}: {}", path.display(), e))?;
+        Ok(PidFile {
+            path: path.to_path_buf(),
+            pid,
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if std::process::id() == self.pid {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+// Fork into the background, detached from the terminal
+//
+// Must be called before any thread is started, so before the tokio runtime.
+// Only the child returns: the parent waits until the child calls
+// notify_ready() and exits with 0, or with 1 when the child exits first (e.g.
+// because the listen address is in use). Standard input and output go to
+// /dev/null, log files opened before forking stay open in the child.
+pub fn daemonize() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (read_fd, write_fd) = pipe()?;
+    // SAFETY: the process is still single-threaded
+    match unsafe { fork() }? {
+        ForkResult::Parent { child } => {
+            drop(write_fd);
+            let mut status = [0u8; 1];
+            let started = matches!(File::from(read_fd).read(&mut status), Ok(1));
+            if !started {
+                eprintln!("quicssh-rs server failed to start, see its log for the error");
+                std::process::exit(1);
+            }
+            println!("quicssh-rs server started in the background, pid {}", child);
+            std::process::exit(0);
+        }
+        ForkResult::Child => {
+            drop(read_fd);
+            // Leave the session of the terminal, so its hangup does not reach us
+            setsid()?;
+            let null = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open("/dev/null")?;
+            for fd in 0..=2 {
+                dup2(null.as_raw_fd(), fd)?;
+            }
+            *READY.lock().unwrap() = Some(File::from(write_fd));
+            Ok(())
+        }
+    }
+}
+
+// Let the waiting parent exit successfully, does nothing when not daemonized
+pub fn notify_ready() {
+    if let Some(mut ready) = READY.lock().unwrap().take() {
+        let _ = ready.write_all(b"1");
+    }
+}
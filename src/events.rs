@@ -0,0 +1,79 @@
+// Import async-trait so handlers can be used as trait objects
+use async_trait::async_trait;
+// Import standard library error, networking and time types
+use std::error::Error;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+// Import logging macros
+use log::info;
+
+// Connection served by the server
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ConnectionInfo {
+    // Quinn stable connection ID
+    pub connection_id: usize,
+    // Client address as seen by the server
+    pub remote_addr: SocketAddr,
+    // Server name requested by the client (or its IP without SNI)
+    pub sni: String,
+    // First backend the connection is routed to
+    pub backend: String,
+}
+
+// Traffic of a finished connection
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ConnectionStats {
+    pub duration: Duration,
+    // Bytes forwarded from the client to the backend
+    pub bytes_in: u64,
+    // Bytes forwarded from the backend to the client
+    pub bytes_out: u64,
+    // Last RTT estimate of the QUIC path
+    pub rtt: Duration,
+    // QUIC packets declared lost, and so retransmitted
+    pub lost_packets: u64,
+}
+
+// Callbacks of the server at the lifecycle points of each connection, e.g.
+// for audit logs, metrics or alerts
+//
+// on_connect is called once the connection is routed, on_error when its
+// handler fails (the error is logged too) and on_disconnect once it is
+// finished, after on_error if any. The callbacks of a connection delay its
+// cleanup, not other connections. The default methods log at info level.
+#[async_trait]
+pub trait ConnectionHandler: Send + Sync {
+    async fn on_connect(&self, info: &ConnectionInfo) {
+        info!(
+            "[server] connection {} from {} opened",
+            info.connection_id, info.remote_addr
+        );
+    }
+
+    async fn on_disconnect(&self, info: &ConnectionInfo, stats: &ConnectionStats) {
+        info!(
+            "[server] connection {} from {} closed after {} ms, {} bytes in, {} bytes out",
+            info.connection_id,
+            info.remote_addr,
+            stats.duration.as_millis(),
+            stats.bytes_in,
+            stats.bytes_out
+        );
+    }
+
+    async fn on_error(&self, info: &ConnectionInfo, err: &(dyn Error + Send + Sync)) {
+        info!(
+            "[server] connection {} from {} failed: {}",
+            info.connection_id, info.remote_addr, err
+        );
+    }
+}
+
+// Handler keeping the default methods, logging each event
+#[derive(Debug, Default)]
+pub struct LogConnectionHandler;
+
+impl ConnectionHandler for LogConnectionHandler {}
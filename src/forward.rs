@@ -0,0 +1,444 @@
+// Import standard library error handling, formatting and I/O
+use std::error::Error;
+use std::fmt;
+use std::io;
+// Import tokio TCP streams relayed over QUIC streams and header reading
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+// Import URL parsing functionality
+use url::Url;
+
+// Magic bytes identifying a forwarding header at the start of a stream
+// SSH streams always begin with "SSH-" so the two can never be confused
+pub const FORWARD_MAGIC: &[u8; 4] = b"QSFW";
+
+// Magic bytes identifying a TCP connect header, used by port forwarding
+pub const CONNECT_MAGIC: &[u8; 4] = b"QSTC";
+
+// Magic bytes identifying a trace context header
+pub const TRACE_MAGIC: &[u8; 4] = b"QSTP";
+
+// Maximum number of hops a forwarded stream may traverse
+pub const DEFAULT_FORWARD_TTL: u8 = 8;
+
+// Header sent by the client (or an intermediate server) to ask the
+// receiving server to forward the stream to the next quicssh-rs server
+//
+// Wire format (all integers big-endian):
+//   magic "QSFW" | ttl: u8 | hop count: u8 | hop count x (len: u16, url bytes)
+//   | via count: u8 | via count x (len: u16, identifier bytes)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForwardHeader {
+    // Remaining hop budget, decremented by every intermediate server
+    pub ttl: u8,
+    // Servers still to be traversed, the last one connects to SSH
+    pub hops: Vec<Url>,
+    // Identifiers of the servers that already forwarded the stream, in order
+    pub via: Vec<String>,
+}
+
+impl ForwardHeader {
+    // Serialize the header including the leading magic bytes
+    //
+    // Fails when there are more than 255 hops or via entries, or when one of
+    // them is longer than 65535 bytes.
+    pub fn encode(&self) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        let mut buf = Vec::with_capacity(6);
+        buf.extend_from_slice(FORWARD_MAGIC);
+        buf.push(self.ttl);
+        buf.push(encode_count(self.hops.len(), "hops")?);
+        for hop in &self.hops {
+            encode_string(&mut buf, hop.as_str(), "hop URL")?;
+        }
+        buf.push(encode_count(self.via.len(), "via entries")?);
+        for via in &self.via {
+            encode_string(&mut buf, via, "via entry")?;
+        }
+        Ok(buf)
+    }
+
+    // Read the remainder of a header whose magic bytes were already consumed
+    pub async fn read(
+        recv: &mut (impl AsyncRead + Unpin),
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        // Read the TTL and the number of hops
+        let mut fixed = [0u8; 2];
+        recv.read_exact(&mut fixed).await?;
+        let [ttl, count] = fixed;
+
+        // Read each length-prefixed hop URL
+        let mut hops = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            hops.push(Url::parse(&read_string(recv).await?)?);
+        }
+
+        // Read the identifiers of the servers already traversed
+        let mut count = [0u8; 1];
+        recv.read_exact(&mut count).await?;
+        let mut via = Vec::with_capacity(count[0] as usize);
+        for _ in 0..count[0] {
+            via.push(read_string(recv).await?);
+        }
+
+        Ok(ForwardHeader { ttl, hops, via })
+    }
+}
+
+// Header sent by a port forwarding client to ask the server to connect the
+// stream to a TCP target instead of the SSH backend
+//
+// Wire format (all integers big-endian):
+//   magic "QSTC" | port: u16 | len: u16, host bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectHeader {
+    // Host name or IP address, resolved by the server
+    pub host: String,
+    pub port: u16,
+}
+
+impl ConnectHeader {
+    // Serialize the header including the leading magic bytes, failing when
+    // the host is longer than 65535 bytes
+    pub fn encode(&self) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        let mut buf = Vec::with_capacity(8 + self.host.len());
+        buf.extend_from_slice(CONNECT_MAGIC);
+        buf.extend_from_slice(&self.port.to_be_bytes());
+        encode_string(&mut buf, &self.host, "host")?;
+        Ok(buf)
+    }
+
+    // Read the remainder of a header whose magic bytes were already consumed
+    pub async fn read(
+        recv: &mut (impl AsyncRead + Unpin),
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut port = [0u8; 2];
+        recv.read_exact(&mut port).await?;
+        let host = read_string(recv).await?;
+        Ok(ConnectHeader {
+            host,
+            port: u16::from_be_bytes(port),
+        })
+    }
+}
+
+impl fmt::Display for ConnectHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Bracket IPv6 addresses so the port stays unambiguous
+        match self.host.contains(':') {
+            true => write!(f, "[{}]:{}", self.host, self.port),
+            false => write!(f, "{}:{}", self.host, self.port),
+        }
+    }
+}
+
+// Header sent first by a client started with --otel-endpoint so the server
+// span of the connection joins the client trace; the rest of the stream is
+// read as if it came first
+//
+// Wire format (all integers big-endian):
+//   magic "QSTP" | len: u16, W3C traceparent bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceHeader {
+    pub traceparent: String,
+}
+
+impl TraceHeader {
+    // Serialize the header including the leading magic bytes, failing when
+    // the traceparent is longer than 65535 bytes
+    pub fn encode(&self) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        let mut buf = Vec::with_capacity(6 + self.traceparent.len());
+        buf.extend_from_slice(TRACE_MAGIC);
+        encode_string(&mut buf, &self.traceparent, "traceparent")?;
+        Ok(buf)
+    }
+
+    // Read the remainder of a header whose magic bytes were already consumed
+    pub async fn read(
+        recv: &mut (impl AsyncRead + Unpin),
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        Ok(TraceHeader {
+            traceparent: read_string(recv).await?,
+        })
+    }
+}
+
+// Relay bytes between a TCP connection and a QUIC stream until both
+// directions are finished, half-closing each side on EOF
+//
+// Returns the number of bytes sent to the stream and received from it.
+pub async fn relay_tcp(
+    tcp: TcpStream,
+    mut send: quinn::SendStream,
+    mut recv: quinn::RecvStream,
+) -> io::Result<(u64, u64)> {
+    let (mut tcp_recv, mut tcp_send) = tcp.into_split();
+    let upstream = async {
+        let n = tokio::io::copy(&mut tcp_recv, &mut send).await?;
+        send.finish().await?;
+        Ok::<_, io::Error>(n)
+    };
+    let downstream = async {
+        let n = tokio::io::copy(&mut recv, &mut tcp_send).await?;
+        tcp_send.shutdown().await?;
+        Ok::<_, io::Error>(n)
+    };
+    tokio::try_join!(upstream, downstream)
+}
+
+// Number of entries of a list, sent as a u8
+fn encode_count(count: usize, what: &str) -> Result<u8, Box<dyn Error + Send + Sync>> {
+    u8::try_from(count)
+        .map_err(|_| format!("too many {}: {} (at most {})", what, count, u8::MAX).into())
+}
+
+// Append a u16 length-prefixed string
+fn encode_string(
+    buf: &mut Vec<u8>,
+    s: &str,
+    what: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let len = u16::try_from(s.len()).map_err(|_| {
+        format!(
+            "{} too long: {} bytes (at most {})",
+            what,
+            s.len(),
+            u16::MAX
+        )
+    })?;
+    buf.extend_from_slice(&len.to_be_bytes());
+    buf.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+// Read a u16 length-prefixed UTF-8 string
+async fn read_string(
+    recv: &mut (impl AsyncRead + Unpin),
+) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let mut len = [0u8; 2];
+    recv.read_exact(&mut len).await?;
+    let mut bytes = vec![0u8; u16::from_be_bytes(len) as usize];
+    recv.read_exact(&mut bytes).await?;
+    Ok(String::from_utf8(bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn forward_header() -> ForwardHeader {
+        ForwardHeader {
+            ttl: 7,
+            hops: vec![
+                Url::parse("quic://hop1.example.com:4433").unwrap(),
+                Url::parse("quic://[2001:db8::1]:4433").unwrap(),
+            ],
+            via: vec!["192.0.2.1:4433".to_string()],
+        }
+    }
+
+    // Check that every strict prefix of ENCODED, magic removed, fails to parse with READ
+    async fn check_truncated<T, F, Fut>(encoded: &[u8], read: F)
+    where
+        F: Fn(Vec<u8>) -> Fut,
+        Fut: std::future::Future<Output = Result<T, Box<dyn Error + Send + Sync>>>,
+    {
+        for len in 4..encoded.len() {
+            assert!(
+                read(encoded[4..len].to_vec()).await.is_err(),
+                "parsed {} of {} bytes",
+                len,
+                encoded.len()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn forward_header_round_trip() {
+        let header = forward_header();
+        let encoded = header.encode().unwrap();
+        assert_eq!(&encoded[..4], FORWARD_MAGIC);
+        assert_eq!(
+            ForwardHeader::read(&mut &encoded[4..]).await.unwrap(),
+            header
+        );
+
+        // No hops left and nothing traversed yet
+        let empty = ForwardHeader {
+            ttl: 0,
+            hops: Vec::new(),
+            via: Vec::new(),
+        };
+        assert_eq!(empty.encode().unwrap(), b"QSFW\0\0\0");
+        assert_eq!(
+            ForwardHeader::read(&mut &b"\0\0\0"[..]).await.unwrap(),
+            empty
+        );
+    }
+
+    #[tokio::test]
+    async fn forward_header_truncated() {
+        let encoded = forward_header().encode().unwrap();
+        check_truncated(&encoded, |data| async move {
+            ForwardHeader::read(&mut data.as_slice()).await
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn forward_header_invalid() {
+        // A hop that is not a URL, and a via entry that is not UTF-8
+        assert!(ForwardHeader::read(&mut &b"\x08\x01\x00\x03a b\x00"[..])
+            .await
+            .is_err());
+        assert!(
+            ForwardHeader::read(&mut &b"\x08\x00\x01\x00\x02\xff\xfe"[..])
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn forward_header_bounds() {
+        let hop = Url::parse("quic://hop.example.com:4433").unwrap();
+        let mut header = ForwardHeader {
+            ttl: 8,
+            hops: vec![hop.clone(); 255],
+            via: vec![String::new(); 255],
+        };
+        assert!(header.encode().is_ok());
+        header.hops.push(hop);
+        assert_eq!(
+            header.encode().unwrap_err().to_string(),
+            "too many hops: 256 (at most 255)"
+        );
+        header.hops.pop();
+        header.via.push(String::new());
+        assert_eq!(
+            header.encode().unwrap_err().to_string(),
+            "too many via entries: 256 (at most 255)"
+        );
+
+        let long = ForwardHeader {
+            ttl: 8,
+            hops: Vec::new(),
+            via: vec!["v".repeat(65536)],
+        };
+        assert_eq!(
+            long.encode().unwrap_err().to_string(),
+            "via entry too long: 65536 bytes (at most 65535)"
+        );
+    }
+
+    #[tokio::test]
+    async fn connect_header_round_trip() {
+        for header in [
+            ConnectHeader {
+                host: "db.internal".to_string(),
+                port: 5432,
+            },
+            ConnectHeader {
+                host: "2001:db8::1".to_string(),
+                port: 22,
+            },
+            ConnectHeader {
+                host: String::new(),
+                port: 0,
+            },
+        ] {
+            let encoded = header.encode().unwrap();
+            assert_eq!(&encoded[..4], CONNECT_MAGIC);
+            assert_eq!(
+                ConnectHeader::read(&mut &encoded[4..]).await.unwrap(),
+                header
+            );
+        }
+        assert_eq!(
+            ConnectHeader {
+                host: "db.internal".to_string(),
+                port: 5432
+            }
+            .encode()
+            .unwrap(),
+            b"QSTC\x15\x38\x00\x0bdb.internal"
+        );
+    }
+
+    #[tokio::test]
+    async fn connect_header_truncated() {
+        let encoded = ConnectHeader {
+            host: "db.internal".to_string(),
+            port: 5432,
+        }
+        .encode()
+        .unwrap();
+        check_truncated(&encoded, |data| async move {
+            ConnectHeader::read(&mut data.as_slice()).await
+        })
+        .await;
+        assert!(ConnectHeader::read(&mut &b"\x00\x16\x00\x01\xff"[..])
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn connect_header_bounds() {
+        let header = ConnectHeader {
+            host: "h".repeat(65535),
+            port: 22,
+        };
+        assert_eq!(header.encode().unwrap().len(), 4 + 2 + 2 + 65535);
+        let header = ConnectHeader {
+            host: "h".repeat(65536),
+            port: 22,
+        };
+        assert_eq!(
+            header.encode().unwrap_err().to_string(),
+            "host too long: 65536 bytes (at most 65535)"
+        );
+    }
+
+    #[tokio::test]
+    async fn trace_header_round_trip() {
+        let header = TraceHeader {
+            traceparent: "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".to_string(),
+        };
+        let encoded = header.encode().unwrap();
+        assert_eq!(&encoded[..6], b"QSTP\x00\x37");
+        assert_eq!(TraceHeader::read(&mut &encoded[4..]).await.unwrap(), header);
+        check_truncated(&encoded, |data| async move {
+            TraceHeader::read(&mut data.as_slice()).await
+        })
+        .await;
+    }
+
+    #[test]
+    fn trace_header_bounds() {
+        let header = TraceHeader {
+            traceparent: "0".repeat(65536),
+        };
+        assert_eq!(
+            header.encode().unwrap_err().to_string(),
+            "traceparent too long: 65536 bytes (at most 65535)"
+        );
+    }
+
+    #[test]
+    fn connect_header_display() {
+        assert_eq!(
+            ConnectHeader {
+                host: "2001:db8::1".to_string(),
+                port: 22
+            }
+            .to_string(),
+            "[2001:db8::1]:22"
+        );
+        assert_eq!(
+            ConnectHeader {
+                host: "db.internal".to_string(),
+                port: 22
+            }
+            .to_string(),
+            "db.internal:22"
+        );
+    }
+}
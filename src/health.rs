@@ -0,0 +1,134 @@
+// Import standard library error handling, networking and synchronization
+use std::error::Error;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+// Import the HTTP server answering the probes
+use tiny_http::{Header, Method, Response, Server};
+
+// Import logging macros
+use log::{error, info};
+
+// State of the server as seen by the probes
+pub struct HealthState {
+    // Set once the server stops accepting connections
+    pub draining: Arc<AtomicBool>,
+    // Number of QUIC connections currently open
+    pub connections: Box<dyn Fn() -> usize + Send>,
+    // Fraction of healthy backends below which readiness reports degraded
+    pub degraded_threshold: f64,
+    // Number of backends and the addresses of those failing their health check
+    pub backends: Box<dyn Fn() -> (usize, Vec<String>) + Send>,
+}
+
+// Serve the liveness and readiness probes on PORT of all interfaces
+//
+// GET /healthz answers 200 while connections are accepted and 503 once the
+// server drains them; GET /readyz also answers 503 when no backend is up, and
+// 206 listing the failed backends when too few of them are up.
+// Like the metrics, the probes are answered from a thread of their own.
+pub fn serve(port: u16, state: HealthState) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let addr = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port);
+    let server =
+        Server::http(addr).map_err(|e| format!("Cannot bind health checks to {}: {}", addr, e))?;
+    info!(
+        "[server] serving health checks on http://{}/healthz and /readyz",
+        addr
+    );
+
+    std::thread::Builder::new()
+        .name("health".to_string())
+        .spawn(move || {
+            for request in server.incoming_requests() {
+                let check = match (request.method(), request.url()) {
+                    (Method::Get, "/healthz") => Some(false),
+                    (Method::Get, "/readyz") => Some(true),
+                    _ => None,
+                };
+                let response = match check {
+                    Some(readiness) => status_response(&state, readiness),
+                    None => Response::from_string("not found").with_status_code(404),
+                };
+                if let Err(e) = request.respond(response) {
+                    error!("[server] sending health check error: {}", e);
+                }
+            }
+        })?;
+    Ok(())
+}
+
+// JSON status of the server, readiness also depending on the backends up
+fn status_response(state: &HealthState, readiness: bool) -> Response<std::io::Cursor<Vec<u8>>> {
+    let mut failed = Vec::new();
+    let (code, status) = if state.draining.load(Ordering::Relaxed) {
+        (503, "draining")
+    } else if readiness {
+        let total;
+        (total, failed) = (state.backends)();
+        let healthy = (total - failed.len()) as f64 / total.max(1) as f64;
+        if failed.len() == total {
+            (503, "no backend up")
+        } else if healthy < state.degraded_threshold {
+            (206, "degraded")
+        } else {
+            (200, "ok")
+        }
+    } else {
+        (200, "ok")
+    };
+    let mut body = serde_json::json!({ "status": status, "connections": (state.connections)() });
+    if !failed.is_empty() {
+        body["failed_backends"] = failed.into();
+    }
+    let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+    Response::from_string(body.to_string())
+        .with_status_code(code)
+        .with_header(content_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Readiness answer with TOTAL backends of which FAILED are down
+    fn readiness(total: usize, failed: &[&str]) -> (u16, serde_json::Value) {
+        let failed: Vec<String> = failed.iter().map(|addr| addr.to_string()).collect();
+        let state = HealthState {
+            draining: Arc::new(AtomicBool::new(false)),
+            connections: Box::new(|| 0),
+            degraded_threshold: 0.5,
+            backends: Box::new(move || (total, failed.clone())),
+        };
+        let response = status_response(&state, true);
+        let code = response.status_code().0;
+        let mut body = String::new();
+        std::io::Read::read_to_string(&mut response.into_reader(), &mut body).unwrap();
+        (code, serde_json::from_str(&body).unwrap())
+    }
+
+    #[test]
+    fn readiness_follows_backends_up() {
+        let (code, body) = readiness(4, &[]);
+        assert_eq!((code, body["status"].as_str()), (200, Some("ok")));
+        assert!(body.get("failed_backends").is_none());
+
+        // At the threshold the server is still ready, failed backends listed
+        let (code, body) = readiness(4, &["10.0.0.1:22", "10.0.0.2:22"]);
+        assert_eq!((code, body["status"].as_str()), (200, Some("ok")));
+        assert_eq!(
+            body["failed_backends"],
+            serde_json::json!(["10.0.0.1:22", "10.0.0.2:22"])
+        );
+
+        let (code, body) = readiness(4, &["10.0.0.1:22", "10.0.0.2:22", "10.0.0.3:22"]);
+        assert_eq!((code, body["status"].as_str()), (206, Some("degraded")));
+        assert_eq!(body["failed_backends"].as_array().unwrap().len(), 3);
+
+        let (code, body) = readiness(2, &["10.0.0.1:22", "10.0.0.2:22"]);
+        assert_eq!(
+            (code, body["status"].as_str()),
+            (503, Some("no backend up"))
+        );
+        assert_eq!(body["failed_backends"].as_array().unwrap().len(), 2);
+    }
+}
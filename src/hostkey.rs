@@ -0,0 +1,346 @@
+// Import base64 for OpenSSH style fingerprints
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use base64::Engine;
+// Import ring SHA-256 used to fingerprint host keys
+use ring::digest;
+
+// SSH message carrying the server host key (KEXDH_REPLY / KEX_ECDH_REPLY,
+// also KEX_DH_GEX_GROUP which is told apart by its content)
+const SSH_MSG_KEX_REPLY: u8 = 31;
+// SSH message carrying the server host key for Diffie-Hellman group exchange
+const SSH_MSG_KEX_DH_GEX_REPLY: u8 = 33;
+// SSH message after which packets are encrypted
+const SSH_MSG_NEWKEYS: u8 = 21;
+
+// Give up if the host key was not seen within this many bytes
+const MAX_INSPECTED_BYTES: usize = 256 * 1024;
+
+// Host key algorithm name prefixes, used to recognize a host key blob
+const HOST_KEY_TYPES: &[&str] = &["ssh-", "ecdsa-", "sk-", "rsa-"];
+
+// Parse an OpenSSH SHA-256 fingerprint, with or without the "SHA256:" prefix
+pub fn parse_fingerprint(s: &str) -> Result<String, String> {
+    let encoded = s.strip_prefix("SHA256:").unwrap_or(s).trim_end_matches('=');
+    let digest = STANDARD_NO_PAD
+        .decode(encoded)
+        .map_err(|e| format!("invalid fingerprint: {}", e))?;
+    if digest.len() != 32 {
+        return Err(format!(
+            "expected a SHA-256 digest, got {} bytes",
+            digest.len()
+        ));
+    }
+    Ok(format!("SHA256:{}", encoded))
+}
+
+// OpenSSH style fingerprint of a host key blob
+pub fn fingerprint(host_key: &[u8]) -> String {
+    let digest = digest::digest(&digest::SHA256, host_key);
+    format!("SHA256:{}", STANDARD_NO_PAD.encode(digest.as_ref()))
+}
+
+// Result of inspecting the data sent by the SSH server so far
+#[derive(Debug, PartialEq, Eq)]
+pub enum HostKeyStatus {
+    // More data is needed before the host key is known
+    Pending,
+    // The host key matches the expected fingerprint
+    Verified,
+    // The host key has a different fingerprint
+    Mismatch(String),
+    // The stream could not be parsed or ended the key exchange without a host key
+    Invalid(String),
+}
+
+// Passive parser of the server side of an SSH key exchange
+//
+// Packets exchanged before the first NEWKEYS are not encrypted, so the host
+// key can be read from the key exchange reply while the data is relayed.
+// Everything before the reply (banner, KEXINIT) must reach the client so it
+// can continue the key exchange; the reply itself is held back until verified.
+pub struct HostKeyVerifier {
+    expected: String,
+    buf: Vec<u8>,
+    // Bytes of buf already parsed and safe to relay
+    pos: usize,
+    // Bytes of buf already handed out for relaying
+    relayed: usize,
+    // Whether the version banner has been read
+    banner_seen: bool,
+}
+
+impl HostKeyVerifier {
+    // Create a verifier for a fingerprint returned by parse_fingerprint
+    pub fn new(expected: String) -> Self {
+        HostKeyVerifier {
+            expected,
+            buf: Vec::new(),
+            pos: 0,
+            relayed: 0,
+            banner_seen: false,
+        }
+    }
+
+    // Feed data received from the SSH server and report the verification state
+    pub fn feed(&mut self, data: &[u8]) -> HostKeyStatus {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() > MAX_INSPECTED_BYTES {
+            return HostKeyStatus::Invalid("no host key in the first 256 KiB".to_string());
+        }
+
+        // The server may send other lines before its "SSH-" version banner
+        while !self.banner_seen {
+            let line_end = match self.buf[self.pos..].iter().position(|&b| b == b'\n') {
+                Some(i) => self.pos + i + 1,
+                None => return HostKeyStatus::Pending,
+            };
+            self.banner_seen = self.buf[self.pos..].starts_with(b"SSH-");
+            self.pos = line_end;
+        }
+
+        // Binary packets: length u32 | padding length u8 | payload | padding
+        loop {
+            let rest = &self.buf[self.pos..];
+            if rest.len() < 5 {
+                return HostKeyStatus::Pending;
+            }
+            let packet_len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            let padding_len = rest[4] as usize;
+            if packet_len < padding_len + 2 || packet_len > MAX_INSPECTED_BYTES {
+                return HostKeyStatus::Invalid("malformed SSH packet".to_string());
+            }
+            if rest.len() < 4 + packet_len {
+                return HostKeyStatus::Pending;
+            }
+            let payload = &rest[5..4 + packet_len - padding_len];
+
+            match payload[0] {
+                SSH_MSG_KEX_REPLY | SSH_MSG_KEX_DH_GEX_REPLY => {
+                    if let Some(host_key) = read_host_key(&payload[1..]) {
+                        let actual = fingerprint(host_key);
+                        if actual != self.expected {
+                            return HostKeyStatus::Mismatch(actual);
+                        }
+                        // Everything received so far may now be relayed
+                        self.pos = self.buf.len();
+                        return HostKeyStatus::Verified;
+                    }
+                }
+                SSH_MSG_NEWKEYS => {
+                    return HostKeyStatus::Invalid(
+                        "key exchange finished without a host key".to_string(),
+                    );
+                }
+                _ => {}
+            }
+            self.pos += 4 + packet_len;
+        }
+    }
+
+    // Take the data that may be relayed to the client and was not taken yet
+    pub fn take_relayable(&mut self) -> Vec<u8> {
+        let data = self.buf[self.relayed..self.pos].to_vec();
+        self.relayed = self.pos;
+        data
+    }
+}
+
+// Read the leading string of a message if it is a host key blob
+fn read_host_key(data: &[u8]) -> Option<&[u8]> {
+    let blob = read_string(data)?;
+    let key_type = std::str::from_utf8(read_string(blob)?).ok()?;
+    if HOST_KEY_TYPES
+        .iter()
+        .any(|prefix| key_type.starts_with(prefix))
+    {
+        Some(blob)
+    } else {
+        None
+    }
+}
+
+// Read an SSH string (u32 length followed by the bytes)
+fn read_string(data: &[u8]) -> Option<&[u8]> {
+    let len = u32::from_be_bytes(data.get(..4)?.try_into().ok()?) as usize;
+    data.get(4..4 + len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // SSH string: u32 length followed by the bytes
+    fn ssh_string(data: &[u8]) -> Vec<u8> {
+        let mut out = (data.len() as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(data);
+        out
+    }
+
+    // Unencrypted binary packet carrying PAYLOAD, with 4 bytes of padding
+    fn packet(payload: &[u8]) -> Vec<u8> {
+        let mut out = ((1 + payload.len() + 4) as u32).to_be_bytes().to_vec();
+        out.push(4);
+        out.extend_from_slice(payload);
+        out.extend_from_slice(&[0; 4]);
+        out
+    }
+
+    // Ed25519 host key blob whose key bytes are all SEED
+    fn host_key(seed: u8) -> Vec<u8> {
+        let mut blob = ssh_string(b"ssh-ed25519");
+        blob.extend(ssh_string(&[seed; 32]));
+        blob
+    }
+
+    // Banner preceded by another line, then the server KEXINIT
+    fn banner_and_kexinit() -> Vec<u8> {
+        let mut out = b"Welcome\r\nSSH-2.0-OpenSSH_9.6\r\n".to_vec();
+        let mut kexinit = vec![20];
+        kexinit.extend_from_slice(&[0xaa; 16]);
+        kexinit.extend(ssh_string(b"curve25519-sha256"));
+        kexinit.extend(ssh_string(b"ssh-ed25519"));
+        out.extend(packet(&kexinit));
+        out
+    }
+
+    // KEX_ECDH_REPLY carrying HOST_KEY
+    fn kex_reply(host_key: &[u8]) -> Vec<u8> {
+        let mut reply = vec![SSH_MSG_KEX_REPLY];
+        reply.extend(ssh_string(host_key));
+        reply.extend(ssh_string(&[0x55; 32]));
+        reply.extend(ssh_string(b"signature"));
+        packet(&reply)
+    }
+
+    fn verifier() -> HostKeyVerifier {
+        HostKeyVerifier::new(fingerprint(&host_key(1)))
+    }
+
+    #[test]
+    fn fingerprint_round_trip() {
+        let fp = fingerprint(&host_key(1));
+        assert!(fp.starts_with("SHA256:"));
+        assert_eq!(parse_fingerprint(&fp), Ok(fp.clone()));
+        assert_eq!(
+            parse_fingerprint(fp.trim_start_matches("SHA256:")),
+            Ok(fp.clone())
+        );
+        assert_eq!(parse_fingerprint(&format!("{}=", fp)), Ok(fp));
+        assert!(parse_fingerprint("SHA256:AAAA").is_err());
+        assert!(parse_fingerprint("SHA256:not base64!").is_err());
+    }
+
+    #[test]
+    fn verified_in_one_feed() {
+        let mut data = banner_and_kexinit();
+        data.extend(kex_reply(&host_key(1)));
+        let mut verifier = verifier();
+        assert_eq!(verifier.feed(&data), HostKeyStatus::Verified);
+        assert_eq!(verifier.take_relayable(), data);
+        assert!(verifier.take_relayable().is_empty());
+    }
+
+    #[test]
+    fn reply_held_back_until_verified() {
+        let head = banner_and_kexinit();
+        let reply = kex_reply(&host_key(1));
+        let mut verifier = verifier();
+
+        // Banner and KEXINIT are relayed right away
+        assert_eq!(verifier.feed(&head), HostKeyStatus::Pending);
+        assert_eq!(verifier.take_relayable(), head);
+
+        // The reply split across feeds is released only once complete
+        let (first, second) = reply.split_at(reply.len() / 2);
+        assert_eq!(verifier.feed(first), HostKeyStatus::Pending);
+        assert!(verifier.take_relayable().is_empty());
+        assert_eq!(verifier.feed(second), HostKeyStatus::Verified);
+        assert_eq!(verifier.take_relayable(), reply);
+    }
+
+    #[test]
+    fn byte_by_byte_never_releases_reply_early() {
+        let head = banner_and_kexinit();
+        let mut data = head.clone();
+        data.extend(kex_reply(&host_key(1)));
+        let mut verifier = verifier();
+        let mut relayed = Vec::new();
+        for (i, byte) in data.iter().enumerate() {
+            let status = verifier.feed(&[*byte]);
+            relayed.extend(verifier.take_relayable());
+            if i + 1 < data.len() {
+                assert_eq!(status, HostKeyStatus::Pending, "at byte {}", i);
+                assert!(
+                    relayed.len() <= head.len(),
+                    "reply released before verification at byte {}",
+                    i
+                );
+            } else {
+                assert_eq!(status, HostKeyStatus::Verified);
+            }
+        }
+        assert_eq!(relayed, data);
+    }
+
+    #[test]
+    fn mismatch_reports_actual_fingerprint() {
+        let mut data = banner_and_kexinit();
+        data.extend(kex_reply(&host_key(2)));
+        let mut verifier = verifier();
+        assert_eq!(
+            verifier.feed(&data),
+            HostKeyStatus::Mismatch(fingerprint(&host_key(2)))
+        );
+        assert_eq!(verifier.take_relayable(), banner_and_kexinit());
+    }
+
+    #[test]
+    fn gex_group_skipped() {
+        // KEX_DH_GEX_GROUP shares the number of KEX_ECDH_REPLY, its prime is no host key
+        let mut data = banner_and_kexinit();
+        let mut group = vec![SSH_MSG_KEX_REPLY];
+        group.extend(ssh_string(&[0x00, 0xff, 0xff, 0xff, 0xff, 0xc9, 0x0f]));
+        group.extend(ssh_string(&[2]));
+        data.extend(packet(&group));
+        let mut reply = vec![SSH_MSG_KEX_DH_GEX_REPLY];
+        reply.extend(ssh_string(&host_key(1)));
+        data.extend(packet(&reply));
+        assert_eq!(verifier().feed(&data), HostKeyStatus::Verified);
+    }
+
+    #[test]
+    fn newkeys_without_host_key() {
+        let mut data = banner_and_kexinit();
+        data.extend(packet(&[SSH_MSG_NEWKEYS]));
+        assert_eq!(
+            verifier().feed(&data),
+            HostKeyStatus::Invalid("key exchange finished without a host key".to_string())
+        );
+    }
+
+    #[test]
+    fn malformed_packet() {
+        let mut data = b"SSH-2.0-OpenSSH_9.6\r\n".to_vec();
+        // Padding longer than the packet
+        data.extend_from_slice(&[0, 0, 0, 4, 8, 20, 0, 0]);
+        assert_eq!(
+            verifier().feed(&data),
+            HostKeyStatus::Invalid("malformed SSH packet".to_string())
+        );
+    }
+
+    #[test]
+    fn inspection_capped_at_256_kib() {
+        let mut verifier = verifier();
+        // Lines without an SSH banner
+        let line = vec![b'x'; 1023];
+        for _ in 0..256 {
+            assert_eq!(verifier.feed(&line), HostKeyStatus::Pending);
+            assert_eq!(verifier.feed(b"\n"), HostKeyStatus::Pending);
+        }
+        assert_eq!(
+            verifier.feed(b"x"),
+            HostKeyStatus::Invalid("no host key in the first 256 KiB".to_string())
+        );
+    }
+}
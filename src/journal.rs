@@ -0,0 +1,127 @@
+// Import log types implemented by the journal logger
+use log::kv::{self, Key, Value, VisitSource};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+// Import standard library I/O and Unix socket utilities
+use std::io::{self, Write};
+use std::os::unix::net::UnixDatagram;
+
+// Socket of the journald native protocol
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+// Identifier used to filter the records with journalctl
+const SYSLOG_IDENTIFIER: &str = "quicssh-rs";
+
+// Logger sending each record to the systemd journal as structured fields
+//
+// Key-value pairs attached to a record (e.g. `info!(session_id = id; ...)`)
+// are sent as QUICSSH_<KEY> fields, so they can be queried with journalctl.
+pub struct JournalLogger {
+    socket: UnixDatagram,
+    level: LevelFilter,
+    // Deployment environment sent as QUICSSH_ENVIRONMENT with every record
+    environment: String,
+}
+
+impl JournalLogger {
+    // Connect to the journal socket
+    pub fn new(level: LevelFilter, environment: &str) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(JOURNAL_SOCKET)?;
+        Ok(JournalLogger {
+            socket,
+            level,
+            environment: environment.to_string(),
+        })
+    }
+
+    // Install the logger as the global logger
+    pub fn init(self) -> Result<(), log::SetLoggerError> {
+        let level = self.level;
+        log::set_boxed_logger(Box::new(self))?;
+        log::set_max_level(level);
+        Ok(())
+    }
+}
+
+// Append a field using the journal native protocol serialization
+fn append_field(buf: &mut Vec<u8>, name: &str, value: &[u8]) {
+    buf.extend_from_slice(name.as_bytes());
+    if value.contains(&b'\n') {
+        // Multi-line values are sent with an explicit little-endian length
+        buf.push(b'\n');
+        buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        buf.push(b'=');
+    }
+    buf.extend_from_slice(value);
+    buf.push(b'\n');
+}
+
+// Syslog priority matching a log level
+fn priority(level: Level) -> &'static [u8] {
+    match level {
+        Level::Error => b"3",
+        Level::Warn => b"4",
+        Level::Info => b"6",
+        Level::Debug | Level::Trace => b"7",
+    }
+}
+
+// Collects the key-value pairs of a record as journal fields
+struct FieldVisitor<'a>(&'a mut Vec<u8>);
+
+impl<'kvs> VisitSource<'kvs> for FieldVisitor<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        // Journal field names only allow uppercase letters, digits and '_'
+        let name: String = key
+            .as_str()
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        append_field(
+            self.0,
+            &format!("QUICSSH_{}", name),
+            value.to_string().as_bytes(),
+        );
+        Ok(())
+    }
+}
+
+impl Log for JournalLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let mut buf = Vec::new();
+        append_field(&mut buf, "PRIORITY", priority(record.level()));
+        append_field(&mut buf, "MESSAGE", record.args().to_string().as_bytes());
+        append_field(&mut buf, "SYSLOG_IDENTIFIER", SYSLOG_IDENTIFIER.as_bytes());
+        append_field(&mut buf, "TARGET", record.target().as_bytes());
+        append_field(&mut buf, "QUICSSH_ENVIRONMENT", self.environment.as_bytes());
+        if let Some(file) = record.file() {
+            append_field(&mut buf, "CODE_FILE", file.as_bytes());
+        }
+        if let Some(line) = record.line() {
+            append_field(&mut buf, "CODE_LINE", line.to_string().as_bytes());
+        }
+        let _ = record.key_values().visit(&mut FieldVisitor(&mut buf));
+
+        // Logging must never fail the caller, report problems on stderr instead
+        if let Err(e) = self.socket.send(&buf) {
+            let _ = writeln!(io::stderr(), "journal log error: {}", e);
+        }
+    }
+
+    fn flush(&self) {}
+}
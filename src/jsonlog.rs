@@ -0,0 +1,52 @@
+// Import log4rs encoder traits implemented by the JSON encoder
+use log4rs::encode::{Encode, Write};
+// Import log types to read the key-value pairs of a record
+use log::kv::{self, Key, Value, VisitSource};
+use log::Record;
+// Import serde_json to build each line
+use serde_json::{Map, Value as JsonValue};
+
+// Encoder writing each record as a single JSON object per line
+//
+// Fields are timestamp (RFC 3339), level, target and message, followed by the
+// key-value pairs attached to the record (e.g. `info!(session_id = id; ...)`).
+#[derive(Debug, Default)]
+pub struct JsonEncoder;
+
+impl Encode for JsonEncoder {
+    fn encode(&self, w: &mut dyn Write, record: &Record) -> anyhow::Result<()> {
+        let mut fields = Map::new();
+        fields.insert("timestamp".into(), chrono::Local::now().to_rfc3339().into());
+        fields.insert("level".into(), record.level().as_str().into());
+        fields.insert("target".into(), record.target().into());
+        fields.insert("message".into(), record.args().to_string().into());
+        record.key_values().visit(&mut FieldVisitor(&mut fields))?;
+
+        serde_json::to_writer(&mut *w, &fields)?;
+        w.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+// Collects the key-value pairs of a record as JSON fields
+struct FieldVisitor<'a>(&'a mut Map<String, JsonValue>);
+
+impl<'kvs> VisitSource<'kvs> for FieldVisitor<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        // Numbers and booleans keep their JSON type, anything else is a string
+        let value = if let Some(n) = value.to_u64() {
+            n.into()
+        } else if let Some(n) = value.to_i64() {
+            n.into()
+        } else if let Some(b) = value.to_bool() {
+            b.into()
+        } else if let Some(n) = value.to_f64() {
+            n.into()
+        } else {
+            value.to_string().into()
+        };
+        // The standard fields are never overwritten
+        self.0.entry(key.as_str()).or_insert(value);
+        Ok(())
+    }
+}
@@ -0,0 +1,65 @@
+// QUIC proxy for SSH: the server and client behind the quicssh-rs binary
+//
+// Programs embedding a tunnel use the client and server modules directly;
+// the functions most of them need are re-exported below. Option structs are
+// non-exhaustive, build them by parsing arguments (e.g. with
+// clap::Parser::parse_from) so that new options do not break callers.
+
+// Import backend module containing SSH backend addresses
+pub mod backend;
+// Import chaos module containing the fault injection socket wrapper
+mod chaos;
+// Import client module containing QUIC client functionality
+pub mod client;
+// Import connlog module containing per-connection log records
+pub mod connlog;
+// Import daemon module forking the server into the background
+#[cfg(unix)]
+mod daemon;
+// Import events module containing the connection callbacks of embedding programs
+pub mod events;
+// Import forward module containing the proxy chain header
+mod forward;
+// Import health module containing the HTTP liveness and readiness probes
+mod health;
+// Import hostkey module containing the SSH host key verifier
+mod hostkey;
+// Import metrics module containing the Prometheus metrics endpoint
+mod metrics;
+// Import otel module containing the OpenTelemetry trace export
+mod otel;
+// Import optconf module converting config file options to command line arguments
+mod optconf;
+// Import output module containing the text or JSON output of the commands
+pub mod output;
+// Import privs module switching the server to an unprivileged user
+#[cfg(unix)]
+mod privs;
+// Import ratelimit module containing the per-IP connection rate limiter
+pub mod ratelimit;
+// Import registry module sharing client connections between sessions
+mod registry;
+// Import server module containing QUIC server functionality
+pub mod server;
+// Import socks5 module containing the SOCKS5 UDP relay socket
+mod socks5;
+// Import stdio module polling stdin and stdout for --proxycommand
+#[cfg(unix)]
+mod stdio;
+// Import systemd module taking activated sockets and notifying readiness
+#[cfg(unix)]
+mod systemd;
+// Import tls module containing certificate loading helpers
+pub mod tls;
+// Import workers module forking and supervising server processes
+#[cfg(unix)]
+mod workers;
+// Import transport module containing QUIC tuning options shared by both sides
+pub mod transport;
+
+// Client side: QUIC configuration and endpoint of a client
+pub use client::{configure_client, make_client_endpoint, ClientBuilder, QuicSshClient};
+// Server side: QUIC configuration, endpoints and connection handling
+pub use server::{configure_server, handle_connection, make_server_endpoints, ServerBuilder};
+// Connection callbacks of a ServerBuilder
+pub use events::{ConnectionHandler, ConnectionInfo, ConnectionStats};
@@ -1,19 +1,31 @@
-// Import client module containing QUIC client functionality
-mod client;
-// Import server module containing QUIC server functionality
-mod server;
+// Import colorlog module containing the ANSI color log encoder
+mod colorlog;
+// Import jsonlog module containing the JSON log encoder
+mod jsonlog;
+// Import journal module containing the systemd journal logger
+#[cfg(target_os = "linux")]
+mod journal;
+// Import manpage module containing the man page rendering
+mod manpage;
+
+// Import the server, client and output format from the library
+use quicssh::output::{self, OutputFormat};
+use quicssh::{client, server};
 
 // Import log4rs components for logging configuration
 use log4rs::append::console::{ConsoleAppender, Target};
 use log4rs::append::file::FileAppender;
 use log4rs::config::{Appender, Config, Root};
 use log4rs::encode::pattern::PatternEncoder;
+use log4rs::encode::Encode;
 
 // Import clap for command line argument parsing
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 // Import logging functionality
 use log::{error, LevelFilter};
 // Import standard library components
+use std::error::Error;
+use std::io::IsTerminal;
 use std::{path::PathBuf, str};
 
 // Define the main CLI structure using clap derive macros
@@ -28,23 +40,157 @@ struct Cli {
     #[clap(value_parser, long = "log")]
     log_file: Option<PathBuf>,
     // Optional log level parameter (defaults to Error)
-    #[clap(long)]
+    #[clap(long, env = "QUICSSH_LOG_LEVEL")]
     log_level: Option<LevelFilter>,
+    // Only log errors, whatever --log-level says (for scripts and CI)
+    #[clap(long = "quiet", short = 'q', conflicts_with = "verbose")]
+    quiet: bool,
+    // Log debug records too, whatever --log-level says; --quiet and
+    // --verbose are mutually exclusive
+    #[clap(long = "verbose", short = 'v')]
+    verbose: bool,
+    // Format of the records written to stderr or the log file
+    #[clap(long = "log-format", value_enum, default_value = "text", conflicts_with = "log_systemd_journal")]
+    log_format: LogFormat,
+    // Format of the results printed by the commands: text, or a single JSON
+    // object on stdout with errors as {"error": "..."} on stderr; also turns
+    // the server access log into JSON lines
+    #[clap(long = "output-format", value_enum, default_value = "text")]
+    output_format: OutputFormat,
+    // Color the records written to stderr by level; auto colors them when
+    // stderr is a terminal, log files are never colored
+    #[clap(long = "color", value_enum, default_value = "auto")]
+    color: ColorMode,
+    // Send logs to the systemd journal instead of stderr or a log file
+    #[clap(long = "log-systemd-journal", conflicts_with = "log_file")]
+    log_systemd_journal: bool,
+    // Deployment environment (e.g. dev, staging, prod) attached to connection records and journal entries
+    #[clap(
+        long = "environment",
+        env = "QUICSSH_ENVIRONMENT",
+        default_value_t = default_environment(),
+        value_parser = parse_environment
+    )]
+    environment: String,
+}
+
+// Format of the log records
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum LogFormat {
+    // Human-readable lines from the log4rs pattern encoder
+    Text,
+    // One JSON object per line, for log aggregators
+    Json,
+}
+
+// When the records written to stderr are colored
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ColorMode {
+    // Only when stderr is a terminal
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    // Whether records written to stderr get ANSI colors
+    fn enabled(self) -> bool {
+        match self {
+            ColorMode::Auto => std::io::stderr().is_terminal(),
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+        }
+    }
 }
 
 // Define the available subcommands
 #[derive(Subcommand, Debug)]
 enum Commands {
     // Server subcommand with server-specific options
-    Server(server::Opt),
+    Server(Box<server::Opt>),
     // Client subcommand with client-specific options
-    Client(client::Opt),
+    Client(Box<client::Opt>),
+    // Print the certificate fingerprint of a server, for --server-fingerprint
+    Fingerprint(client::FingerprintOpt),
+    // Check a server configuration file without starting the server
+    Validate(server::ValidateOpt),
+    // Perform a QUIC handshake with a server and report the round-trip time
+    Test(client::TestOpt),
+    // Print the certificate chain presented by a server
+    ShowCert(client::ShowCertOpt),
+    // List the client profiles and their server addresses
+    ListProfiles,
+    // Print the completion script of a shell, e.g.
+    // quicssh-rs completions bash > /etc/bash_completion.d/quicssh-rs
+    Completions {
+        // Shell the script is written for
+        shell: clap_complete::Shell,
+    },
+    // Print a commented server configuration file listing every key, e.g.
+    // quicssh-rs generate-config > /etc/quicssh/config.toml
+    GenerateConfig,
+    // Write the man pages of quicssh-rs and its server and client subcommands
+    // to a directory, e.g. quicssh-rs man /usr/local/share/man/man1
+    Man {
+        // Directory the pages are written to, created if missing
+        output_dir: PathBuf,
+    },
+}
+
+// Default environment taken from $ENVIRONMENT or $ENV, "unknown" otherwise
+fn default_environment() -> String {
+    ["ENVIRONMENT", "ENV"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| parse_environment(value).is_ok())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+// Accept environment names safe for every downstream log and metric system
+fn parse_environment(s: &str) -> Result<String, String> {
+    if !s.is_empty()
+        && s
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        Ok(s.to_string())
+    } else {
+        Err("environment may only contain [a-zA-Z0-9_-]".to_string())
+    }
+}
+
+// Parse the command line, filling server options from the [server] section
+// of the --conf file and client options from the --profile profile when they
+// are not given on the command line
+fn parse_args() -> Cli {
+    let matches = Cli::command().get_matches();
+    let mut argv: Vec<_> = std::env::args_os().collect();
+    let extra = match matches.subcommand() {
+        Some(("server", server_matches)) => server::config_file_args(server_matches),
+        Some(("client", client_matches)) => client::profile_args(client_matches),
+        _ => Ok(Vec::new()),
+    };
+    match extra {
+        // Server and client options are last on the command line, so append them
+        Ok(extra) => argv.extend(extra),
+        Err(e) => Cli::command()
+            .error(clap::error::ErrorKind::InvalidValue, e)
+            .exit(),
+    }
+    Cli::parse_from(argv)
 }
 
 // Main function - entry point of the application
 fn main() {
     // Parse command line arguments using clap
-    let args = Cli::parse();
+    let mut args = parse_args();
+
+    // --quiet and --verbose override --log-level
+    if args.quiet {
+        args.log_level = Some(LevelFilter::Error);
+    } else if args.verbose {
+        args.log_level = Some(LevelFilter::Debug);
+    }
 
     // Determine log level - use provided level or default to Error
     let level = match args.log_level {
@@ -56,9 +202,9 @@ fn main() {
     let config = match args.log_file {
         // If log file is specified, create file appender configuration
         Some(log_file) => {
-            // Create file appender with default pattern encoder
+            // Create file appender with the selected encoder
             let logfile = FileAppender::builder()
-                .encoder(Box::<PatternEncoder>::default())
+                .encoder(log_encoder(args.log_format))
                 .build(log_file)
                 .unwrap();
 
@@ -71,8 +217,13 @@ fn main() {
         // If no log file specified, use stderr console appender
         None => {
             // Create console appender targeting stderr
+            // JSON records stay plain so they remain parseable
+            let encoder = match args.log_format {
+                LogFormat::Text if args.color.enabled() => Box::<colorlog::ColorEncoder>::default(),
+                format => log_encoder(format),
+            };
             let stderr = ConsoleAppender::builder()
-                .encoder(Box::<PatternEncoder>::default())
+                .encoder(encoder)
                 .target(Target::Stderr)
                 .build();
             
@@ -85,31 +236,133 @@ fn main() {
     };
 
     // Initialize the logging system with the configured settings
-    log4rs::init_config(config).unwrap();
+    if args.log_systemd_journal {
+        init_journal_logger(level, &args.environment);
+    } else {
+        log4rs::init_config(config).unwrap();
+    }
 
     // Execute the appropriate command based on user input
+    let format = args.output_format;
     match args.command {
         // Run server with the provided server options
         Commands::Server(server) => {
-            let err = server::run(server);
+            let err = server::run(*server, args.environment, format);
             match err {
                 Ok(_) => {}
                 // Log any errors that occur during server execution
                 Err(e) => {
-                    error!("Error: {:#?}", e);
+                    report_error(format, &*e, || error!("Error: {:#?}", e));
+                    std::process::exit(1);
                 }
             }
         }
         // Run client with the provided client options
         Commands::Client(client) => {
-            let err = client::run(client);
+            let err = client::run(*client);
             match err {
                 Ok(_) => {}
                 // Log any errors that occur during client execution
                 Err(e) => {
-                    error!("Error: {:#?}", e);
+                    report_error(format, &*e, || error!("Error: {:#?}", e));
+                }
+            }
+        }
+        // Print the server certificate fingerprint
+        Commands::Fingerprint(fingerprint) => {
+            if let Err(e) = client::print_fingerprint(fingerprint, format) {
+                report_error(format, &*e, || error!("Error: {:#?}", e));
+            }
+        }
+        // Test the connection to a server, failing if the handshake fails
+        Commands::Test(test) => {
+            if let Err(e) = client::test_connection(test, format) {
+                report_error(format, &*e, || eprintln!("connection test failed: {}", e));
+                std::process::exit(1);
+            }
+        }
+        // Print the server certificates
+        Commands::ShowCert(show_cert) => {
+            if let Err(e) = client::show_cert(show_cert, format) {
+                report_error(format, &*e, || eprintln!("show-cert failed: {}", e));
+                std::process::exit(1);
+            }
+        }
+        // Print the client profiles
+        Commands::ListProfiles => {
+            if let Err(e) = client::list_profiles(format) {
+                report_error(format, &*e, || error!("Error: {:#?}", e));
+                std::process::exit(1);
+            }
+        }
+        // Print the shell completion script to stdout
+        Commands::Completions { shell } => {
+            let mut command = Cli::command();
+            let name = command.get_name().to_string();
+            clap_complete::generate(shell, &mut command, name, &mut std::io::stdout());
+        }
+        // Print the example configuration file
+        Commands::GenerateConfig => print!("{}", server::example_config()),
+        // Write the man pages and list them
+        Commands::Man { output_dir } => {
+            let result = manpage::generate(Cli::command(), &output_dir).and_then(|paths| match format {
+                OutputFormat::Json => output::print_json(&serde_json::json!({ "files": paths })),
+                OutputFormat::Text => {
+                    for path in paths {
+                        println!("{}", path.display());
+                    }
+                    Ok(())
                 }
+            });
+            if let Err(e) = result {
+                report_error(format, &*e, || eprintln!("man failed: {}", e));
+                std::process::exit(1);
             }
         }
+        // Check the configuration file, failing if any check fails
+        Commands::Validate(validate) => match server::validate(validate, format) {
+            Ok(true) => {}
+            Ok(false) => std::process::exit(1),
+            Err(e) => {
+                report_error(format, &*e, || error!("Error: {:#?}", e));
+                std::process::exit(1);
+            }
+        },
     }
 }
+
+// Report the error of a command: as {"error": "..."} on stderr with
+// --output-format json, through REPORT_TEXT otherwise
+fn report_error(format: OutputFormat, e: &(dyn Error + Send + Sync), report_text: impl FnOnce()) {
+    match format {
+        OutputFormat::Json => output::print_error(e),
+        OutputFormat::Text => report_text(),
+    }
+}
+
+// Encoder of the log records for the given format
+fn log_encoder(format: LogFormat) -> Box<dyn Encode> {
+    match format {
+        LogFormat::Text => Box::<PatternEncoder>::default(),
+        LogFormat::Json => Box::<jsonlog::JsonEncoder>::default(),
+    }
+}
+
+// Install the systemd journal logger, exiting if the journal is unavailable
+#[cfg(target_os = "linux")]
+fn init_journal_logger(level: LevelFilter, environment: &str) {
+    match journal::JournalLogger::new(level, environment) {
+        Ok(logger) => logger.init().unwrap(),
+        Err(e) => {
+            eprintln!("failed to connect to the systemd journal: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// The systemd journal only exists on Linux
+#[cfg(not(target_os = "linux"))]
+fn init_journal_logger(_level: LevelFilter, _environment: &str) {
+    eprintln!("--log-systemd-journal is only supported on Linux");
+    std::process::exit(1);
+}
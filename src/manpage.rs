@@ -0,0 +1,125 @@
+// Import clap_mangen to render the pages and roff to write the extra sections
+use clap::Command;
+use clap_mangen::roff::{bold, roman, Roff};
+use clap_mangen::Man;
+// Import standard library error handling, file and path utilities
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// Examples of the page of quicssh-rs
+const MAIN_EXAMPLES: &[(&str, &str)] = &[
+    (
+        "Accept QUIC connections on port 4433 and relay them to the local SSH server:",
+        "quicssh-rs server --listen 0.0.0.0:4433",
+    ),
+    (
+        "Log to a file at the info level:",
+        "quicssh-rs --log /var/log/quicssh.log --log-level info server",
+    ),
+    (
+        "Install the bash completion script:",
+        "quicssh-rs completions bash > /etc/bash_completion.d/quicssh-rs",
+    ),
+];
+
+// Examples of the page of quicssh-rs server
+const SERVER_EXAMPLES: &[(&str, &str)] = &[
+    (
+        "Listen on port 4433 and relay connections to an SSH server on port 2222:",
+        "quicssh-rs server --listen 0.0.0.0:4433 --proxy-to 127.0.0.1:2222",
+    ),
+    (
+        "Read the options from the [server] section of a configuration file:",
+        "quicssh-rs server --conf /etc/quicssh/config.toml",
+    ),
+    (
+        "Check a configuration file without starting the server:",
+        "quicssh-rs validate /etc/quicssh/config.toml",
+    ),
+];
+
+// Examples of the page of quicssh-rs client
+const CLIENT_EXAMPLES: &[(&str, &str)] = &[
+    (
+        "Use the client as the ProxyCommand of ssh:",
+        "ssh -o ProxyCommand=\"quicssh-rs client --proxycommand quic://%h:4433\" user@host",
+    ),
+    (
+        "Share one QUIC connection between the SSH sessions made to a local port:",
+        "quicssh-rs client --multiplex 127.0.0.1:2222 quic://hostname:4433",
+    ),
+    (
+        "Check that a server answers before connecting:",
+        "quicssh-rs test quic://hostname:4433",
+    ),
+];
+
+// Render the pages of quicssh-rs and of its server and client subcommands to
+// DIR, returning the paths of the written files
+pub fn generate(
+    command: Command,
+    dir: &Path,
+) -> Result<Vec<PathBuf>, Box<dyn Error + Send + Sync>> {
+    fs::create_dir_all(dir).map_err(|e| format!("cannot create {}: {}", dir.display(), e))?;
+    // Building the command names the subcommands e.g. quicssh-rs-server
+    let mut command = command.disable_help_subcommand(true);
+    command.build();
+
+    let mut paths = vec![write_page(command.clone(), MAIN_EXAMPLES, dir)?];
+    for (name, examples) in [("server", SERVER_EXAMPLES), ("client", CLIENT_EXAMPLES)] {
+        let subcommand = command
+            .find_subcommand(name)
+            .ok_or_else(|| format!("no {} subcommand", name))?;
+        paths.push(write_page(subcommand.clone(), examples, dir)?);
+    }
+    Ok(paths)
+}
+
+// Write the page of COMMAND with its EXAMPLES section to DIR
+//
+// Same sections as Man::render(), with EXAMPLES before VERSION and AUTHORS.
+fn write_page(
+    command: Command,
+    examples: &[(&str, &str)],
+    dir: &Path,
+) -> Result<PathBuf, Box<dyn Error + Send + Sync>> {
+    let has_subcommands = command.get_subcommands().next().is_some();
+    let has_author = command.get_author().is_some();
+    let man = Man::new(command);
+    let path = dir.join(man.get_filename());
+    let write = |file: &mut File| -> std::io::Result<()> {
+        man.render_title(file)?;
+        man.render_name_section(file)?;
+        man.render_synopsis_section(file)?;
+        man.render_description_section(file)?;
+        man.render_options_section(file)?;
+        if has_subcommands {
+            man.render_subcommands_section(file)?;
+        }
+        examples_section(examples).to_writer(file)?;
+        man.render_version_section(file)?;
+        if has_author {
+            man.render_authors_section(file)?;
+        }
+        file.flush()
+    };
+    File::create(&path)
+        .and_then(|mut file| write(&mut file))
+        .map_err(|e| format!("cannot write {}: {}", path.display(), e))?;
+    Ok(path)
+}
+
+// EXAMPLES section, each description followed by its indented command
+fn examples_section(examples: &[(&str, &str)]) -> Roff {
+    let mut roff = Roff::default();
+    roff.control("SH", ["EXAMPLES"]);
+    for (description, example) in examples {
+        roff.control("PP", []).text([roman(*description)]);
+        roff.control("RS", ["4"])
+            .text([bold(*example)])
+            .control("RE", []);
+    }
+    roff
+}
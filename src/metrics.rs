@@ -0,0 +1,229 @@
+// Import Prometheus metric types and the text exposition format
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+// Import standard library error handling, networking and synchronization
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::LazyLock;
+use std::time::Duration;
+// Import the HTTP server serving the metrics
+use tiny_http::{Header, Method, Response, Server};
+
+// Import logging macros
+use log::{error, info};
+
+// Server metrics, updated from the connection handlers
+struct Metrics {
+    connections_total: IntCounterVec,
+    connections_active: IntGauge,
+    bytes_to_ssh_total: IntCounter,
+    bytes_to_quic_total: IntCounter,
+    backend_errors_total: IntCounterVec,
+    panics_total: IntCounter,
+    failovers_total: IntCounterVec,
+    rtt_seconds: Histogram,
+}
+
+// Metrics are process wide, like the counters of the Prometheus default registry
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+// Upper bounds of the RTT histogram buckets, from LAN to satellite links
+const RTT_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5];
+
+impl Metrics {
+    fn new() -> Self {
+        let connections_total = IntCounterVec::new(
+            Opts::new("quicssh_connections_total", "QUIC connections accepted"),
+            &["sni"],
+        )
+        .unwrap();
+        let connections_active = IntGauge::new(
+            "quicssh_connections_active",
+            "QUIC connections currently open",
+        )
+        .unwrap();
+        let bytes_to_ssh_total = IntCounter::new(
+            "quicssh_bytes_to_ssh_total",
+            "Bytes forwarded from QUIC clients to SSH backends",
+        )
+        .unwrap();
+        let bytes_to_quic_total = IntCounter::new(
+            "quicssh_bytes_to_quic_total",
+            "Bytes forwarded from SSH backends to QUIC clients",
+        )
+        .unwrap();
+        let backend_errors_total = IntCounterVec::new(
+            Opts::new(
+                "quicssh_backend_errors_total",
+                "Failed connections to and reads from SSH backends",
+            ),
+            &["backend"],
+        )
+        .unwrap();
+        let panics_total = IntCounter::new(
+            "quicssh_panics_total",
+            "Connection handlers that panicked, dropping their connection",
+        )
+        .unwrap();
+        let failovers_total = IntCounterVec::new(
+            Opts::new(
+                "quicssh_failovers_total",
+                "Failovers to a lower priority backend",
+            ),
+            &["sni"],
+        )
+        .unwrap();
+        let rtt_seconds = Histogram::with_opts(
+            HistogramOpts::new(
+                "quicssh_rtt_seconds",
+                "Round-trip time of QUIC connections when they close",
+            )
+            .buckets(RTT_BUCKETS.to_vec()),
+        )
+        .unwrap();
+
+        Metrics {
+            connections_total,
+            connections_active,
+            bytes_to_ssh_total,
+            bytes_to_quic_total,
+            backend_errors_total,
+            panics_total,
+            failovers_total,
+            rtt_seconds,
+        }
+    }
+}
+
+// Registry exposing the metrics with LABELS attached to every series
+pub(crate) fn registry(labels: HashMap<String, String>) -> Result<Registry, prometheus::Error> {
+    let registry = Registry::new_custom(None, Some(labels))?;
+    registry.register(Box::new(METRICS.connections_total.clone()))?;
+    registry.register(Box::new(METRICS.connections_active.clone()))?;
+    registry.register(Box::new(METRICS.bytes_to_ssh_total.clone()))?;
+    registry.register(Box::new(METRICS.bytes_to_quic_total.clone()))?;
+    registry.register(Box::new(METRICS.backend_errors_total.clone()))?;
+    registry.register(Box::new(METRICS.panics_total.clone()))?;
+    registry.register(Box::new(METRICS.failovers_total.clone()))?;
+    registry.register(Box::new(METRICS.rtt_seconds.clone()))?;
+    Ok(registry)
+}
+
+// Record an accepted connection for the route ROUTE
+pub fn connection_opened(route: &str) {
+    METRICS.connections_total.with_label_values(&[route]).inc();
+    METRICS.connections_active.inc();
+}
+
+// Record a closed connection and its last RTT estimate
+pub fn connection_closed(rtt: Duration) {
+    METRICS.connections_active.dec();
+    METRICS.rtt_seconds.observe(rtt.as_secs_f64());
+}
+
+// Record bytes forwarded from a client to its backend
+pub fn add_bytes_to_ssh(n: usize) {
+    METRICS.bytes_to_ssh_total.inc_by(n as u64);
+}
+
+// Record bytes forwarded from a backend to its client
+pub fn add_bytes_to_quic(n: usize) {
+    METRICS.bytes_to_quic_total.inc_by(n as u64);
+}
+
+// Record a failed connection to or read from a backend
+pub fn backend_error(backend: &str) {
+    METRICS
+        .backend_errors_total
+        .with_label_values(&[backend])
+        .inc();
+}
+
+// Record a failover to a lower priority backend for the route ROUTE
+pub fn failover(route: &str) {
+    METRICS.failovers_total.with_label_values(&[route]).inc();
+}
+
+// Record a connection handler panic, returns the number of panics so far
+pub fn handler_panicked() -> u64 {
+    METRICS.panics_total.inc();
+    METRICS.panics_total.get()
+}
+
+// Render the metrics of REGISTRY in the Prometheus text format
+pub(crate) fn render(registry: &Registry) -> Vec<u8> {
+    let mut body = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&registry.gather(), &mut body) {
+        error!("[server] encoding metrics error: {}", e);
+    }
+    body
+}
+
+// Serve GET /metrics in the Prometheus text format on ADDR, with LABELS
+// attached to every series
+//
+// The HTTP server runs on a thread of its own, so scrapes never wait on the
+// runtime proxying the connections.
+pub fn serve(
+    addr: SocketAddr,
+    labels: HashMap<String, String>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let registry = registry(labels).map_err(|e| format!("Invalid metric labels: {}", e))?;
+    let server =
+        Server::http(addr).map_err(|e| format!("Cannot bind metrics to {}: {}", addr, e))?;
+    info!("[server] serving metrics on http://{}/metrics", addr);
+
+    std::thread::Builder::new()
+        .name("metrics".to_string())
+        .spawn(move || {
+            for request in server.incoming_requests() {
+                let response = if *request.method() == Method::Get && request.url() == "/metrics" {
+                    let content_type =
+                        Header::from_bytes("Content-Type", TextEncoder::new().format_type())
+                            .unwrap();
+                    Response::from_data(render(&registry)).with_header(content_type)
+                } else {
+                    Response::from_string("not found").with_status_code(404)
+                };
+                if let Err(e) = request.respond(response) {
+                    error!("[server] sending metrics error: {}", e);
+                }
+            }
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_attached_to_every_series() {
+        let labels = HashMap::from([
+            ("environment".to_string(), "staging".to_string()),
+            ("region".to_string(), "eu-west".to_string()),
+        ]);
+        let registry = registry(labels).unwrap();
+        backend_error("127.0.0.1:22");
+        let body = String::from_utf8(render(&registry)).unwrap();
+        let series: Vec<_> = body.lines().filter(|line| !line.starts_with('#')).collect();
+        assert!(series
+            .iter()
+            .any(|line| line.starts_with("quicssh_backend_errors_total{")));
+        for line in series {
+            assert!(
+                line.contains(r#"environment="staging""#),
+                "no environment label: {}",
+                line
+            );
+            assert!(
+                line.contains(r#"region="eu-west""#),
+                "no region label: {}",
+                line
+            );
+        }
+    }
+}
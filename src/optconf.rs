@@ -0,0 +1,309 @@
+// Import clap types to look up the options of a command
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+// Import standard library error handling, OS strings and type IDs
+use std::any::TypeId;
+use std::error::Error;
+use std::ffi::OsString;
+use std::fmt::Write;
+
+// Turn options read from a configuration file into command line arguments
+//
+// Keys are long flag names, with dashes or underscores; CONTEXT names where
+// they come from in error messages and EXCLUDE is a flag that cannot be set
+// this way. Options given on the command line or through an environment
+// variable are skipped, so the precedence is: CLI flag > environment >
+// config file > default.
+pub fn option_args(
+    command: &Command,
+    matches: &ArgMatches,
+    options: toml::Table,
+    context: &str,
+    exclude: &str,
+) -> Result<Vec<OsString>, Box<dyn Error + Send + Sync>> {
+    let mut args = Vec::new();
+    for (key, value) in options {
+        let name = key.replace('_', "-");
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(name.as_str()) && name != exclude)
+            .ok_or_else(|| format!("unknown option in {}: {}", context, key))?;
+
+        // Command line and environment values take precedence
+        if matches!(
+            matches.value_source(arg.get_id().as_str()),
+            Some(ValueSource::CommandLine) | Some(ValueSource::EnvVariable)
+        ) {
+            continue;
+        }
+
+        let flag = OsString::from(format!("--{}", name));
+        let values = match value {
+            toml::Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            match value {
+                // Switches take no value
+                toml::Value::Boolean(set) if !arg.get_action().takes_values() => {
+                    if set {
+                        args.push(flag.clone());
+                    }
+                    continue;
+                }
+                toml::Value::String(s) => args.extend([flag.clone(), s.into()]),
+                toml::Value::Integer(_) | toml::Value::Float(_) | toml::Value::Boolean(_) => {
+                    args.extend([flag.clone(), value.to_string().into()])
+                }
+                _ => return Err(format!("unsupported value for {} in {}", key, context).into()),
+            }
+        }
+    }
+    Ok(args)
+}
+
+// Commented-out example of every option of COMMAND that option_args accepts
+//
+// Each key gets a comment with its flag, type, default value, allowed values
+// and environment variable, taken from the clap definition so new options
+// show up without further changes. Options without a default are shown with
+// an empty value of their type.
+pub fn example_options(command: &Command, exclude: &str) -> String {
+    let mut out = String::new();
+    for arg in command.get_arguments() {
+        let Some(name) = arg.get_long() else { continue };
+        let builtin = matches!(arg.get_action(), ArgAction::Help | ArgAction::Version);
+        if name == exclude || builtin || arg.is_hide_set() {
+            continue;
+        }
+        let kind = value_kind(arg);
+        let list = matches!(arg.get_action(), ArgAction::Append);
+        let defaults: Vec<toml::Value> = arg
+            .get_default_values()
+            .iter()
+            .map(|value| kind.value(&value.to_string_lossy()))
+            .collect();
+
+        let example = match (list, defaults.len()) {
+            (true, _) => toml::Value::Array(defaults.clone()),
+            (false, 0) => kind.value(""),
+            (false, _) => defaults[0].clone(),
+        };
+
+        let mut comment = format!("--{}: {}", name, kind.name(list));
+        if !defaults.is_empty() {
+            let _ = write!(comment, ", default {}", example);
+        }
+        let values: Vec<String> = arg
+            .get_possible_values()
+            .iter()
+            .map(|value| value.get_name().to_string())
+            .collect();
+        if !values.is_empty() && kind != ValueKind::Bool {
+            let _ = write!(comment, ", one of {}", values.join(", "));
+        }
+        if let Some(env) = arg.get_env() {
+            let _ = write!(comment, ", environment {}", env.to_string_lossy());
+        }
+        let _ = writeln!(out, "# {}\n# {} = {}", comment, name, example);
+    }
+    out
+}
+
+// TOML type of the value of an option
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ValueKind {
+    Bool,
+    Integer,
+    Float,
+    String,
+}
+
+impl ValueKind {
+    // Name of the type, or of a list of values of the type
+    fn name(self, list: bool) -> &'static str {
+        match (self, list) {
+            (ValueKind::Bool, _) => "boolean",
+            (ValueKind::Integer, false) => "integer",
+            (ValueKind::Integer, true) => "list of integers",
+            (ValueKind::Float, false) => "float",
+            (ValueKind::Float, true) => "list of floats",
+            (ValueKind::String, false) => "string",
+            (ValueKind::String, true) => "list of strings",
+        }
+    }
+
+    // TOML value of a command line value, empty values give false, 0 or ""
+    fn value(self, s: &str) -> toml::Value {
+        match self {
+            ValueKind::Bool => toml::Value::Boolean(s == "true"),
+            ValueKind::Integer => toml::Value::Integer(s.parse().unwrap_or(0)),
+            ValueKind::Float => toml::Value::Float(s.parse().unwrap_or(0.0)),
+            ValueKind::String => toml::Value::String(s.to_string()),
+        }
+    }
+}
+
+// Type of the values of ARG, from its action and value parser
+fn value_kind(arg: &Arg) -> ValueKind {
+    if !arg.get_action().takes_values() {
+        return ValueKind::Bool;
+    }
+    let id = arg.get_value_parser().type_id();
+    let integers = [
+        TypeId::of::<u8>(),
+        TypeId::of::<u16>(),
+        TypeId::of::<u32>(),
+        TypeId::of::<u64>(),
+        TypeId::of::<usize>(),
+        TypeId::of::<i32>(),
+        TypeId::of::<i64>(),
+    ];
+    if integers.iter().any(|integer| id == *integer) {
+        ValueKind::Integer
+    } else if id == TypeId::of::<f64>() {
+        ValueKind::Float
+    } else {
+        ValueKind::String
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Command with the kinds of options found in the [server] section
+    fn command() -> Command {
+        Command::new("server")
+            .arg(Arg::new("listen").short('l').long("listen"))
+            .arg(
+                Arg::new("proxy_to")
+                    .long("proxy-to")
+                    .env("QUICSSH_OPTCONF_TEST_PROXY_TO"),
+            )
+            .arg(
+                Arg::new("debug_echo")
+                    .long("debug-echo")
+                    .action(ArgAction::SetTrue),
+            )
+            .arg(
+                Arg::new("allow_ip")
+                    .long("allow-ip")
+                    .action(ArgAction::Append),
+            )
+            .arg(Arg::new("max_connections").long("max-connections"))
+            .arg(Arg::new("conf_path").long("conf"))
+    }
+
+    // Arguments option_args gives for the TOML CONF when run with CLI
+    fn args_for(cli: &[&str], conf: &str) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        let command = command();
+        let matches = command.clone().try_get_matches_from(cli)?;
+        let args = option_args(
+            &command,
+            &matches,
+            toml::from_str(conf)?,
+            "[server] section",
+            "conf",
+        )?;
+        Ok(args
+            .into_iter()
+            .map(|arg| arg.into_string().unwrap())
+            .collect())
+    }
+
+    #[test]
+    fn file_value_used_without_flag() {
+        let args = args_for(&["server"], r#"listen = "0.0.0.0:5555""#).unwrap();
+        assert_eq!(args, ["--listen", "0.0.0.0:5555"]);
+    }
+
+    #[test]
+    fn cli_value_wins() {
+        let args = args_for(
+            &["server", "-l", "127.0.0.1:4433"],
+            r#"listen = "0.0.0.0:5555""#,
+        )
+        .unwrap();
+        assert!(args.is_empty());
+    }
+
+    #[test]
+    fn env_value_wins() {
+        std::env::set_var("QUICSSH_OPTCONF_TEST_PROXY_TO", "10.0.0.1:22");
+        let args = args_for(&["server"], r#"proxy_to = "10.0.0.2:22""#);
+        std::env::remove_var("QUICSSH_OPTCONF_TEST_PROXY_TO");
+        assert!(args.unwrap().is_empty());
+    }
+
+    #[test]
+    fn underscore_key_and_integer_value() {
+        let args = args_for(&["server"], "max_connections = 5").unwrap();
+        assert_eq!(args, ["--max-connections", "5"]);
+    }
+
+    #[test]
+    fn unknown_key_is_an_error() {
+        let e = args_for(&["server"], "lisen = \"0.0.0.0:5555\"").unwrap_err();
+        assert_eq!(e.to_string(), "unknown option in [server] section: lisen");
+    }
+
+    #[test]
+    fn excluded_key_is_an_error() {
+        let e = args_for(&["server"], r#"conf = "other.toml""#).unwrap_err();
+        assert_eq!(e.to_string(), "unknown option in [server] section: conf");
+    }
+
+    #[test]
+    fn switch_expands_to_flag() {
+        assert_eq!(
+            args_for(&["server"], "debug_echo = true").unwrap(),
+            ["--debug-echo"]
+        );
+        assert!(args_for(&["server"], "debug_echo = false")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn array_repeats_flag() {
+        let args = args_for(
+            &["server"],
+            r#"allow_ip = ["10.0.0.0/8", "192.168.0.0/16"]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            args,
+            ["--allow-ip", "10.0.0.0/8", "--allow-ip", "192.168.0.0/16"]
+        );
+    }
+
+    #[test]
+    fn table_value_is_an_error() {
+        let e = args_for(&["server"], "listen = { port = 1 }").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "unsupported value for listen in [server] section"
+        );
+    }
+
+    #[test]
+    fn server_listen_from_config() {
+        use crate::server::Opt;
+        use clap::CommandFactory;
+        use std::net::SocketAddr;
+
+        // The [server] listen address is used when -l is absent
+        let matches = Opt::command().get_matches_from(["server"]);
+        let table = toml::from_str(r#"listen = "0.0.0.0:5555""#).unwrap();
+        let extra =
+            option_args(&Opt::command(), &matches, table, "[server] section", "conf").unwrap();
+        let mut args = vec![OsString::from("server")];
+        args.extend(extra);
+        let matches = Opt::command().get_matches_from(args);
+        assert_eq!(
+            matches.get_one::<SocketAddr>("listen"),
+            Some(&"0.0.0.0:5555".parse().unwrap())
+        );
+    }
+}
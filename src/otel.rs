@@ -0,0 +1,70 @@
+// Import OpenTelemetry context propagation and the OTLP exporter
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::TracerProvider;
+use opentelemetry_sdk::{runtime, Resource};
+// Import the bridge from tracing spans to OpenTelemetry spans
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::layer::SubscriberExt;
+// Import standard library collections and error handling
+use std::collections::HashMap;
+use std::error::Error;
+
+// Import logging macros
+use log::{error, info};
+
+// W3C trace context key carrying the parent span
+const TRACEPARENT: &str = "traceparent";
+
+// Exporter of the spans of this process, flushed by shutdown()
+pub struct Otel {
+    provider: TracerProvider,
+}
+
+// Export tracing spans to the OTLP gRPC collector at ENDPOINT
+//
+// Must be called from within the tokio runtime, which runs the batch exporter.
+// Only spans are exported; log records still go to the configured logger.
+pub fn init(endpoint: &str) -> Result<Otel, Box<dyn Error + Send + Sync>> {
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint)
+        .build()
+        .map_err(|e| format!("invalid OTLP endpoint {}: {}", endpoint, e))?;
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_resource(Resource::new([KeyValue::new("service.name", "quicssh-rs")]))
+        .build();
+
+    let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer("quicssh-rs"));
+    tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))?;
+    info!("exporting traces to {}", endpoint);
+    Ok(Otel { provider })
+}
+
+impl Otel {
+    // Export the spans still buffered
+    pub async fn shutdown(self) {
+        // Shutting down blocks until the batch exporter, a runtime task, is done
+        let result = tokio::task::spawn_blocking(move || self.provider.shutdown()).await;
+        if let Ok(Err(e)) = result {
+            error!("exporting traces error: {}", e);
+        }
+    }
+}
+
+// W3C traceparent value identifying SPAN, if it is being exported
+pub fn traceparent(span: &tracing::Span) -> Option<String> {
+    let mut carrier = HashMap::new();
+    TraceContextPropagator::new().inject_context(&span.context(), &mut carrier);
+    carrier.remove(TRACEPARENT)
+}
+
+// Make SPAN a child of the span identified by a W3C traceparent value
+pub fn set_parent(span: &tracing::Span, traceparent: &str) {
+    let carrier = HashMap::from([(TRACEPARENT.to_string(), traceparent.to_string())]);
+    span.set_parent(TraceContextPropagator::new().extract(&carrier));
+}
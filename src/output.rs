@@ -0,0 +1,28 @@
+// Import clap for the format selection enum
+use clap::ValueEnum;
+// Import serde to write the command results as JSON
+use serde::Serialize;
+use serde_json::json;
+// Import standard library error handling
+use std::error::Error;
+
+// Format of what the commands print, selected by --output-format
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    // Human-readable text
+    #[default]
+    Text,
+    // A single JSON object on stdout, errors as {"error": "..."} on stderr
+    Json,
+}
+
+// Write the result of a command to stdout as one JSON object
+pub fn print_json<T: Serialize>(value: &T) -> Result<(), Box<dyn Error + Send + Sync>> {
+    println!("{}", serde_json::to_string(value)?);
+    Ok(())
+}
+
+// Write an error to stderr as {"error": "..."}
+pub fn print_error(e: &(dyn Error + Send + Sync)) {
+    eprintln!("{}", json!({ "error": e.to_string() }));
+}
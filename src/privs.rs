@@ -0,0 +1,35 @@
+// Import nix user lookup and credential changes
+use nix::unistd::{geteuid, setgid, setuid, User};
+// Import standard library error handling
+use std::error::Error;
+
+// Import logging macros
+use log::info;
+
+// Look up the user the server switches to, before any port is bound
+pub fn lookup_user(name: &str) -> Result<User, Box<dyn Error + Send + Sync>> {
+    match User::from_name(name) {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => Err(format!("--drop-privs-user: no user named {}", name).into()),
+        Err(e) => Err(format!("--drop-privs-user: looking up {} error: {}", name, e).into()),
+    }
+}
+
+// Switch to USER for good, once the sockets that needed privileges are bound
+//
+// The group is changed first, as it can no longer be changed once the user is
+// unprivileged, and supplementary groups are reduced to that group.
+pub fn drop_to(user: &User) -> Result<(), Box<dyn Error + Send + Sync>> {
+    info!(
+        "[server] running as uid {}, switching to user {}",
+        geteuid(),
+        user.name
+    );
+    #[cfg(not(target_vendor = "apple"))]
+    nix::unistd::setgroups(&[user.gid])
+        .map_err(|e| format!("cannot drop supplementary groups: {}", e))?;
+    setgid(user.gid).map_err(|e| format!("cannot switch to group {}: {}", user.gid, e))?;
+    setuid(user.uid).map_err(|e| format!("cannot switch to user {}: {}", user.name, e))?;
+    info!("[server] now running as uid {}", geteuid());
+    Ok(())
+}
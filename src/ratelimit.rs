@@ -0,0 +1,315 @@
+// Import DashMap for the per-IP bucket table
+use dashmap::DashMap;
+// Import tokio I/O traits and timers for the throttled reader; buckets use
+// the tokio clock so tests can pause it
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::time::{Instant, Sleep};
+// Import standard library networking, async and time utilities
+use std::future::Future;
+use std::io;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+// Token bucket holding up to `rate` tokens, refilled at `rate` tokens per second
+#[derive(Debug)]
+pub struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    // Create a full bucket
+    pub fn new(rate: f64) -> Self {
+        TokenBucket {
+            tokens: rate,
+            last_refill: Instant::now(),
+        }
+    }
+
+    // Add the tokens accumulated since the last refill
+    fn refill(&mut self, rate: f64) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(rate);
+        self.last_refill = now;
+    }
+
+    // Take one token if available
+    pub fn try_take(&mut self, rate: f64) -> bool {
+        self.refill(rate);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    // Take N tokens, going into debt when there are not enough
+    // Returns the time until the debt is paid back
+    pub fn take(&mut self, n: f64, rate: f64) -> Option<Duration> {
+        self.refill(rate);
+        self.tokens -= n;
+        (self.tokens < 0.0).then(|| Duration::from_secs_f64(-self.tokens / rate))
+    }
+
+    // Time since a token was last requested
+    pub fn idle_for(&self) -> Duration {
+        self.last_refill.elapsed()
+    }
+}
+
+// Limit of new connections per source IP per second
+#[derive(Debug)]
+pub struct ConnectionRateLimiter {
+    rate: f64,
+    buckets: DashMap<IpAddr, TokenBucket>,
+}
+
+impl ConnectionRateLimiter {
+    // Allow `rate` new connections per second from each source IP
+    pub fn new(rate: u32) -> Self {
+        ConnectionRateLimiter {
+            rate: rate as f64,
+            buckets: DashMap::new(),
+        }
+    }
+
+    // Record a new connection from an IP, returns false when it exceeds the rate
+    pub fn allow(&self, ip: IpAddr) -> bool {
+        self.buckets
+            .entry(ip)
+            .or_insert_with(|| TokenBucket::new(self.rate))
+            .try_take(self.rate)
+    }
+
+    // Drop the buckets of IPs that made no connection for the given time
+    // An idle bucket is full again, so dropping it changes nothing
+    pub fn prune(&self, idle: Duration) {
+        self.buckets.retain(|_, bucket| bucket.idle_for() <= idle);
+    }
+}
+
+// Parse a positive bandwidth in megabits per second
+pub fn parse_mbps(s: &str) -> Result<f64, String> {
+    let mbps: f64 = s.parse().map_err(|e| format!("invalid bandwidth: {}", e))?;
+    if !(mbps.is_finite() && mbps > 0.0) {
+        return Err("bandwidth must be a positive number of Mbit/s".to_string());
+    }
+    Ok(mbps)
+}
+
+// Bandwidth limit of one direction of a connection
+//
+// Bytes are paid for after they are transferred: a transfer that overdraws
+// the bucket (one second worth of bytes) delays the next one until the debt
+// is paid back, so the average rate stays at the limit.
+#[derive(Debug)]
+pub struct Throttle {
+    // Bytes per second
+    rate: f64,
+    bucket: TokenBucket,
+}
+
+impl Throttle {
+    // Allow MBPS megabits per second
+    pub fn from_mbps(mbps: f64) -> Self {
+        let rate = mbps * 1e6 / 8.0;
+        Throttle {
+            rate,
+            bucket: TokenBucket::new(rate),
+        }
+    }
+
+    // Record N transferred bytes, returning how long to wait before the next transfer
+    pub fn consume(&mut self, n: usize) -> Option<Duration> {
+        self.bucket.take(n as f64, self.rate)
+    }
+
+    // Record N transferred bytes and wait until the next transfer is allowed
+    pub async fn wait(&mut self, n: usize) {
+        if let Some(delay) = self.consume(n) {
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+// Bandwidth limit shared by every connection of the server
+//
+// Writers pay for their bytes before writing, and wait while the shared
+// bucket is overdrawn, so concurrent connections share the rate.
+#[derive(Debug)]
+pub struct SharedThrottle {
+    throttle: Mutex<Throttle>,
+}
+
+impl SharedThrottle {
+    // Allow MBPS megabits per second in total
+    pub fn from_mbps(mbps: f64) -> Self {
+        SharedThrottle {
+            throttle: Mutex::new(Throttle::from_mbps(mbps)),
+        }
+    }
+
+    // Wait until N more bytes may be written
+    pub async fn acquire(&self, n: usize) {
+        let delay = self.throttle.lock().unwrap().consume(n);
+        if let Some(delay) = delay {
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+// Reader whose reads are held back to the rate of its throttle, if any
+pub struct ThrottledReader<R> {
+    inner: R,
+    throttle: Option<Throttle>,
+    // Wait owed by the previous read
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl<R> ThrottledReader<R> {
+    pub fn new(inner: R, throttle: Option<Throttle>) -> Self {
+        ThrottledReader {
+            inner,
+            throttle,
+            delay: None,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ThrottledReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if let Some(delay) = self.delay.as_mut() {
+            if delay.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.delay = None;
+        }
+
+        let filled = buf.filled().len();
+        let this = &mut *self;
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let (Poll::Ready(Ok(())), Some(throttle)) = (&result, this.throttle.as_mut()) {
+            let n = buf.filled().len() - filled;
+            this.delay = throttle
+                .consume(n)
+                .map(|delay| Box::pin(tokio::time::sleep(delay)));
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    // Assert that ELAPSED is DURATION, give or take the timer resolution
+    fn assert_elapsed(elapsed: Duration, secs: f64) {
+        let elapsed = elapsed.as_secs_f64();
+        assert!(
+            (secs..secs + 0.05).contains(&elapsed),
+            "took {}s instead of {}s",
+            elapsed,
+            secs
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bucket_debt_paid_back_at_rate() {
+        let mut bucket = TokenBucket::new(1000.0);
+        assert_eq!(bucket.take(500.0, 1000.0), None);
+        // Overdrawing by 500 tokens costs half a second
+        assert_eq!(
+            bucket.take(1000.0, 1000.0),
+            Some(Duration::from_millis(500))
+        );
+        tokio::time::advance(Duration::from_millis(250)).await;
+        assert_eq!(bucket.take(0.0, 1000.0), Some(Duration::from_millis(250)));
+        tokio::time::advance(Duration::from_millis(250)).await;
+        assert_eq!(bucket.take(0.0, 1000.0), None);
+
+        // The bucket never holds more than one second worth of tokens
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert_eq!(
+            bucket.take(1500.0, 1000.0),
+            Some(Duration::from_millis(500))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn throttle_holds_average_rate() {
+        // 8 Mbit/s is 1 MB/s, the first second worth of bytes goes at once
+        let mut throttle = Throttle::from_mbps(8.0);
+        let start = Instant::now();
+        for _ in 0..10 {
+            throttle.wait(500_000).await;
+        }
+        assert_elapsed(start.elapsed(), 4.0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shared_throttle_caps_total_rate() {
+        let throttle = std::sync::Arc::new(SharedThrottle::from_mbps(8.0));
+        let start = Instant::now();
+        let writers: Vec<_> = (0..2)
+            .map(|_| {
+                let throttle = throttle.clone();
+                tokio::spawn(async move {
+                    for _ in 0..10 {
+                        throttle.acquire(250_000).await;
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.await.unwrap();
+        }
+        // 5 MB in total, 1 MB of it without waiting
+        assert_elapsed(start.elapsed(), 4.0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn throttled_reader_waits_after_overdraw() {
+        let data = vec![0u8; 1_500_000];
+        let mut reader = ThrottledReader::new(&data[..], Some(Throttle::from_mbps(8.0)));
+        let mut buf = vec![0u8; 1_500_000];
+        let start = Instant::now();
+        assert_eq!(reader.read(&mut buf).await.unwrap(), 1_500_000);
+        assert_elapsed(start.elapsed(), 0.0);
+        // The read after overdrawing by 0.5 MB waits half a second
+        assert_eq!(reader.read(&mut buf).await.unwrap(), 0);
+        assert_elapsed(start.elapsed(), 0.5);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn throttled_reader_holds_average_rate() {
+        let data = vec![0u8; 3_000_000];
+        let mut reader = ThrottledReader::new(&data[..], Some(Throttle::from_mbps(8.0)));
+        let mut received = Vec::new();
+        let start = Instant::now();
+        let mut buf = vec![0u8; 100_000];
+        loop {
+            match reader.read(&mut buf).await.unwrap() {
+                0 => break,
+                n => received.extend_from_slice(&buf[..n]),
+            }
+        }
+        assert_eq!(received.len(), data.len());
+        assert_elapsed(start.elapsed(), 2.0);
+
+        // Without a throttle reads never wait
+        let mut reader = ThrottledReader::new(&data[..], None);
+        let start = Instant::now();
+        reader.read_to_end(&mut Vec::new()).await.unwrap();
+        assert_elapsed(start.elapsed(), 0.0);
+    }
+}
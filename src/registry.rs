@@ -0,0 +1,45 @@
+// Import the concurrent map holding the connections
+use dashmap::DashMap;
+// Import QUIC connection handles
+use quinn::{Connection, VarInt};
+// Import standard library networking and synchronization
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+// QUIC connections of the client keyed by server address, shared by the
+// sessions of --multiplex so that each server gets a single connection
+//
+// The server closes a connection once its last session ended, so a session
+// started while that close is under way fails like a lost connection does.
+#[derive(Clone, Default)]
+pub struct ConnectionRegistry {
+    connections: Arc<DashMap<(IpAddr, u16), Connection>>,
+}
+
+impl ConnectionRegistry {
+    // Live connection to SERVER, if any; a closed one is forgotten
+    pub fn get(&self, server: SocketAddr) -> Option<Connection> {
+        let key = (server.ip(), server.port());
+        let connection = self.connections.get(&key)?.clone();
+        if connection.close_reason().is_some() {
+            self.connections
+                .remove_if(&key, |_, known| known.stable_id() == connection.stable_id());
+            return None;
+        }
+        Some(connection)
+    }
+
+    // Reuse CONNECTION for the next sessions to SERVER
+    pub fn insert(&self, server: SocketAddr, connection: Connection) {
+        self.connections
+            .insert((server.ip(), server.port()), connection);
+    }
+
+    // Close every connection, e.g. on a signal
+    pub fn close_all(&self, code: VarInt, reason: &[u8]) {
+        for entry in self.connections.iter() {
+            entry.value().close(code, reason);
+        }
+        self.connections.clear();
+    }
+}
@@ -1,146 +1,1525 @@
 // Import clap for command line argument parsing
-use clap::Parser;
+use clap::{ArgMatches, CommandFactory, Parser, ValueEnum};
 // Import Quinn QUIC library components
-use quinn::{crypto, Endpoint, ServerConfig, VarInt};
+use quinn::{crypto, ConfigError, Endpoint, EndpointConfig, ServerConfig};
+
+// Import client helpers used for QUIC versions and proxy chain hops
+use crate::client::{self, format_quic_versions, parse_quic_version};
+// Import forwarding header used for proxy chains
+use crate::forward::{
+    relay_tcp, ConnectHeader, ForwardHeader, TraceHeader, CONNECT_MAGIC, FORWARD_MAGIC, TRACE_MAGIC,
+};
+// Import QUIC transport options shared with the client
+use crate::transport::TransportOpt;
+// Import TLS options of the server and those used when connecting to the next hop
+use crate::tls::{
+    cert_digest, cert_not_after, load_certs_as, load_private_key_as, self_signed_identity,
+    subject_common_name, CertFormat, ClientTlsOpt, KeyLogFile, ServerTlsOpt, SniCertResolver,
+    SniCerts,
+};
+// Import the conversion of config file options to command line arguments
+use crate::optconf::{example_options, option_args};
+// Import the output format of the commands
+use crate::output::{self, OutputFormat};
+// Import the connection event callbacks of embedding programs
+use crate::events::{ConnectionHandler, ConnectionInfo, ConnectionStats};
+// Import the Prometheus metrics and the OpenTelemetry trace export
+use crate::{metrics, otel};
+// Import the HTTP health probes
+use crate::health::{self, HealthState};
+// Import the background mode, PID file and privilege dropping
+#[cfg(unix)]
+use crate::daemon::{self, PidFile};
+#[cfg(unix)]
+use crate::privs;
+// Import systemd socket activation and readiness notification
+#[cfg(unix)]
+use crate::systemd;
+// Import the supervisor of the worker processes
+#[cfg(unix)]
+use crate::workers;
+// Import tracing to record the spans of each connection
+use tracing::Instrument;
+// Import SSH backend addresses and streams
+use crate::backend::{BackendAddr, BackendStream};
+// Import the passive SSH host key verifier
+use crate::hostkey::{parse_fingerprint, HostKeyStatus, HostKeyVerifier};
+// Import the per-IP connection rate limiter
+use crate::ratelimit::{parse_mbps, ConnectionRateLimiter, SharedThrottle, Throttle, ThrottledReader};
+// Import per-connection log records and traffic counters
+use crate::connlog::{
+    parse_access_log_format, AccessLog, AccessLogFormat, ConnectionLogFormat, ConnectionLogRecord,
+    ConnectionLogger, TrafficCounters, DEFAULT_ACCESS_LOG_FORMAT,
+};
+
+// Import ring HMAC used to derive stateless reset tokens
+use ring::hmac;
 
 // Import logging macros
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
+// Import futures stream combinators to accept from several endpoints
+use futures::future;
+use futures::stream::{self, BoxStream, SelectAll, StreamExt};
+// Import socket2 to bind IPv6-only sockets
+use socket2::{Domain, Protocol, Socket, Type};
+// Import IP networks for the access lists
+use ipnet::IpNet;
+// Import DashMap for the per-IP reconnect table
+use dashmap::DashMap;
 // Import serde for configuration deserialization
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
+use serde_json::json;
 // Import standard library collections and utilities
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::error::Error;
-use std::net::Ipv4Addr;
-use std::path::PathBuf;
+use std::ffi::OsString;
+use std::fmt;
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
 use std::{net::SocketAddr, sync::Arc};
 // Import tokio async file operations
 use tokio::fs::read_to_string;
 // Import tokio async I/O traits
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-// Import tokio TCP stream for SSH connections
-use tokio::net::TcpStream;
+// Import tokio address lookup and TCP streams for forwarded connections
+use tokio::net::{lookup_host, TcpStream};
+// Import tokio semaphore bounding the streams handled per connection
+// and the broadcast channel announcing shutdown
+use tokio::sync::{broadcast, OwnedSemaphorePermit, Semaphore};
 
 // Define command line options structure for the server
 #[derive(Parser, Debug)]
 #[clap(name = "server")]
+#[non_exhaustive]
 pub struct Opt {
-    // Socket address to bind the QUIC server to (default: 0.0.0.0:4433)
-    #[clap(long = "listen", short = 'l', default_value = "0.0.0.0:4433")]
-    listen: SocketAddr,
-    // Optional default SSH server address to proxy connections to
-    #[clap(long = "proxy-to", short = 'p')]
-    proxy_to: Option<SocketAddr>,
-    // Optional path to TOML configuration file
+    // Comma-separated socket addresses to bind the QUIC server to (default: 0.0.0.0:4433,[::]:4433)
+    // With several addresses, IPv6 sockets are bound IPv6-only so that an
+    // IPv4 and an IPv6 wildcard address can share the same port
+    #[clap(
+        long = "listen",
+        short = 'l',
+        env = "QUICSSH_LISTEN",
+        value_delimiter = ',',
+        default_values = ["0.0.0.0:4433", "[::]:4433"]
+    )]
+    listen: Vec<SocketAddr>,
+    // Optional default SSH server address (ip:port or unix:/path) to proxy connections to
+    #[clap(long = "proxy-to", short = 'p', env = "QUICSSH_PROXY_TO")]
+    proxy_to: Option<BackendAddr>,
+    // Optional path to TOML or YAML configuration file
     #[clap(long = "conf", short = 'F')]
     conf_path: Option<PathBuf>,
+    // Syntax of the --conf file
+    #[clap(long = "conf-format", value_enum, default_value = "auto")]
+    conf_format: ConfFormat,
+    // Optional file listing connection ID patterns to close on SIGTERM
+    // SIGTERM then drains the matching connections instead of shutting down
+    #[clap(long = "drain-connections-file")]
+    drain_connections_file: Option<PathBuf>,
+    // Seconds to wait for active connections to finish on SIGTERM before closing them
+    #[clap(long = "shutdown-timeout", default_value = "30")]
+    shutdown_timeout: u64,
+    // Optional comma-separated QUIC versions to advertise in Version Negotiation packets
+    #[clap(long = "quic-supported-versions", value_delimiter = ',', value_parser = parse_quic_version)]
+    quic_supported_versions: Vec<u32>,
+    // Allow acting as an intermediate hop that forwards to another quicssh-rs server
+    #[clap(long = "allow-forward")]
+    allow_forward: bool,
+    // Allow clients to forward TCP connections (client --local-forward) to any
+    // host and port reachable from this server
+    #[clap(long = "allow-tcp-forward")]
+    allow_tcp_forward: bool,
+    // Format of the record logged when a connection closes
+    #[clap(long = "connection-log-format", value_enum, default_value = "text")]
+    connection_log_format: ConnectionLogFormat,
+    // CSV file that connection records are appended to in csv format
+    #[clap(long = "connection-log", required_if_eq("connection_log_format", "csv"))]
+    connection_log: Option<PathBuf>,
+    // File appended with one line per finished connection, reopened on SIGHUP
+    #[clap(long = "access-log", value_name = "PATH")]
+    access_log: Option<PathBuf>,
+    // Fields of the access log lines: %t time, %r client address, %s SNI,
+    // %b backend, %I bytes in, %O bytes out, %D duration in ms, %% percent sign
+    #[clap(
+        long = "access-log-format",
+        default_value = DEFAULT_ACCESS_LOG_FORMAT,
+        value_parser = parse_access_log_format
+    )]
+    access_log_format: AccessLogFormat,
+    // Number of times a refused or timed out SSH backend connection is retried
+    #[clap(long = "backend-connect-retry", default_value = "2")]
+    backend_connect_retry: u32,
+    // Initial delay between backend connection retries, doubled after each attempt
+    #[clap(long = "backend-connect-retry-delay-ms", default_value = "200")]
+    backend_connect_retry_delay_ms: u64,
+    // Milliseconds a failover route waits for a backend, retries included,
+    // before trying the next one
+    #[clap(long = "failover-timeout-ms", default_value = "3000", value_parser = clap::value_parser!(u64).range(1..))]
+    failover_timeout_ms: u64,
+    // Timeout for each TCP connection attempt to the SSH backend
+    #[clap(long = "tcp-connect-timeout-ms", default_value = "5000")]
+    tcp_connect_timeout_ms: u64,
+    // Hex-encoded 32-byte secret used to derive stateless reset tokens
+    // Servers sharing a port (SO_REUSEPORT) must use the same secret so that a
+    // reset sent by one process is recognized for connections of another.
+    // Anyone knowing the secret can forge resets and tear down connections,
+    // so keep it private and prefer passing it from a protected source.
+    #[clap(long = "stateless-reset-secret", value_parser = parse_reset_secret)]
+    stateless_reset_secret: Option<[u8; 32]>,
+    // Interval in seconds between debug logs of per-connection QUIC path statistics
+    #[clap(long = "quic-stats-interval-secs", value_parser = clap::value_parser!(u64).range(1..))]
+    quic_stats_interval_secs: Option<u64>,
+    // Maximum number of connections handled at once; further connections wait in
+    // the QUIC backlog (see --quic-connection-backlog) until one finishes
+    #[clap(long = "max-connections", value_parser = clap::value_parser!(u32).range(1..))]
+    max_connections: Option<u32>,
+    // Maximum number of connections the endpoint holds, including those still
+    // handshaking; further attempts are refused with CONNECTION_REFUSED
+    #[clap(long = "quic-connection-backlog", value_parser = clap::value_parser!(u32).range(1..))]
+    quic_connection_backlog: Option<u32>,
+    // Echo stream data back to the client instead of proxying to SSH (testing only)
+    #[clap(long = "debug-echo", conflicts_with = "proxy_to")]
+    debug_echo: bool,
+    // Load the configuration and certificates and check that every backend
+    // accepts a TCP connection, then exit without binding any port; exit
+    // code 1 tells what would fail
+    #[clap(long = "dry-run")]
+    dry_run: bool,
+    // Expected SHA-256 fingerprint of the backend SSH host key (as printed by ssh-keygen -l)
+    #[clap(long = "ssh-host-key-fingerprint", value_parser = parse_fingerprint)]
+    ssh_host_key_fingerprint: Option<String>,
+    // Maximum number of servers a forwarded stream may traverse before a loop is assumed
+    #[clap(long = "max-proxy-hops", default_value = "3")]
+    max_proxy_hops: usize,
+    // Maximum new connections per source IP per second, further ones are closed
+    #[clap(long = "rate-limit-conns", value_parser = clap::value_parser!(u32).range(1..))]
+    rate_limit_conns: Option<u32>,
+    // Bandwidth limit of each direction of a proxied connection, in Mbit/s
+    #[clap(long = "max-bw-per-conn-mbps", value_name = "MBPS", value_parser = parse_mbps)]
+    max_bw_per_conn_mbps: Option<f64>,
+    // Bandwidth limit of all proxied connections together, in Mbit/s; writes
+    // in either direction wait while it is exceeded
+    #[clap(long = "max-total-bw-mbps", value_name = "MBPS", value_parser = parse_mbps)]
+    max_total_bw_mbps: Option<f64>,
+    // Source networks allowed to connect (CIDR); when any is given, others are rejected
+    #[clap(long = "allow-ip", value_name = "CIDR")]
+    allow_ip: Vec<IpNet>,
+    // Source networks rejected (CIDR), checked before --allow-ip
+    #[clap(long = "deny-ip", value_name = "CIDR")]
+    deny_ip: Vec<IpNet>,
+    // Seconds between TCP health checks of the backends in the configuration file
+    #[clap(long = "health-check-interval", default_value = "10", value_parser = clap::value_parser!(u64).range(1..))]
+    health_check_interval: u64,
+    // Milliseconds to wait for a backend to accept the health check connection
+    #[clap(long = "health-check-timeout-ms", default_value = "2000", value_parser = clap::value_parser!(u64).range(1..))]
+    health_check_timeout_ms: u64,
+    // Window in seconds over which connection attempts per source IP are counted
+    #[clap(long = "rate-limit-reconnect-window-secs", default_value = "10", value_parser = clap::value_parser!(u64).range(1..))]
+    rate_limit_reconnect_window_secs: u64,
+    // Maximum connection attempts per source IP within the window, at least 1
+    #[clap(long = "rate-limit-reconnect-max", default_value = "5", value_parser = clap::value_parser!(u64).range(1..))]
+    rate_limit_reconnect_max: u64,
+    // QUIC application error code sent when the connection ends because of an SSH backend error
+    #[clap(long = "backend-error-code", default_value = "2")]
+    backend_error_code: u32,
+    // Maximum bidirectional streams a client may have open; further stream
+    // opens wait on QUIC flow control until a handler finishes
+    #[clap(long = "max-pending-streams", default_value = "10", value_parser = clap::value_parser!(u32).range(1..))]
+    max_pending_streams: u32,
+    // Size in bytes of the buffers relaying data in each direction; values above
+    // the OS socket buffer sizes give diminishing returns
+    #[clap(long = "buf-size", default_value = "65536", value_parser = clap::value_parser!(u32).range(1..))]
+    buf_size: u32,
+    // Serve Prometheus metrics on http://ADDR/metrics
+    #[clap(long = "metrics-addr", value_name = "ADDR")]
+    metrics_addr: Option<SocketAddr>,
+    // Label attached to every metric series along with the environment, as
+    // KEY=VALUE; may be repeated
+    #[clap(long = "metric-label", value_name = "KEY=VALUE", value_parser = parse_metric_label)]
+    metric_label: Vec<(String, String)>,
+    // Serve HTTP liveness (/healthz) and readiness (/readyz) probes on this
+    // port of all interfaces
+    #[clap(long = "health-port", value_name = "PORT")]
+    health_port: Option<u16>,
+    // Fraction of healthy backends below which /readyz answers 206 with the
+    // failed backends (0.0-1.0); it answers 503 once all of them are down
+    #[clap(
+        long = "health-degraded-threshold",
+        value_name = "FRACTION",
+        default_value = "0.5",
+        value_parser = parse_fraction
+    )]
+    health_degraded_threshold: f64,
+    // Export connection traces to this OTLP gRPC collector, e.g. http://localhost:4317;
+    // clients started with --otel-endpoint then add their trace context
+    #[clap(long = "otel-endpoint", value_name = "URL")]
+    otel_endpoint: Option<String>,
+    // Run in the background, detached from the terminal; logs only reach --log
+    // or the journal since standard error goes to /dev/null
+    #[cfg(unix)]
+    #[clap(long = "daemonize")]
+    daemonize: bool,
+    // Write the server PID to this file, removed when the server exits
+    #[cfg(unix)]
+    #[clap(long = "pid-file", value_name = "PATH")]
+    pid_file: Option<PathBuf>,
+    // Switch to this user once the listen, metrics and health ports are bound,
+    // so ports below 1024 can be used without running as root (Unix only)
+    #[clap(long = "drop-privs-user", value_name = "USER")]
+    drop_privs_user: Option<String>,
+    // Run as a systemd Type=notify service: listen on the UDP sockets of the
+    // socket unit instead of --listen when it passes any, and report readiness
+    #[cfg(unix)]
+    #[clap(long = "systemd", conflicts_with = "daemonize")]
+    systemd: bool,
+    // Number of server processes sharing the listen ports (SO_REUSEPORT); the
+    // kernel spreads clients over them and dead workers are restarted. The
+    // limits and counters of the other options apply per worker.
+    #[cfg(unix)]
+    #[clap(
+        long = "workers",
+        default_value = "1",
+        value_parser = clap::value_parser!(u32).range(1..),
+        conflicts_with_all = ["metrics_addr", "health_port"]
+    )]
+    workers: u32,
+    // TLS certificate options
+    #[clap(flatten)]
+    tls: ServerTlsOpt,
+    // QUIC transport tuning options
+    #[clap(flatten)]
+    transport: TransportOpt,
 }
 
-// Configure QUIC server with self-signed certificate and transport settings
+// How often the drain connections file is checked for modifications
+const DRAIN_FILE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// QUIC application error code used when a forwarding request is refused
+pub(crate) const FORWARD_REJECTED_CODE: u32 = 0x03;
+// QUIC application error code used when the SSH backend connection times out
+const BACKEND_CONNECT_TIMEOUT_CODE: u32 = 0x08;
+
+// QUIC application error code used when a forwarded stream traversed too many servers
+pub(crate) const PROXY_LOOP_CODE: u32 = 0x0A;
+
+// QUIC application error code used when a source IP reconnects too often
+pub(crate) const TOO_MANY_RECONNECTS_CODE: u32 = 0x0B;
+
+// QUIC application error code used when a source IP opens connections too fast
+const RATE_LIMITED_CODE: u32 = 0x0E;
+
+// Per-IP rate limiter buckets idle for this long are dropped
+const RATE_LIMIT_IDLE: Duration = Duration::from_secs(60);
+
+// QUIC application error code used when the source IP is not allowed to connect
+pub(crate) const ACCESS_DENIED_CODE: u32 = 0x0F;
+
+// QUIC application error code used when the backend host key does not match
+pub(crate) const HOST_KEY_MISMATCH_CODE: u32 = 0x0C;
+
+// QUIC application error code used when mTLS is enabled and no client certificate was presented
+pub(crate) const CLIENT_CERT_REQUIRED_CODE: u32 = 0x0D;
+
+// QUIC application error code used when a connection handler panics
+const HANDLER_PANIC_CODE: u32 = 0x01;
+
+// Certificates expiring within this period are reported at startup and on each check
+const CERT_EXPIRY_WARNING: Duration = Duration::from_secs(30 * 24 * 3600);
+
+// Server configuration file, replaced as a whole when reloaded
+type SharedConf = Arc<RwLock<Arc<ServerConf>>>;
+
+// Backends that failed their last health check
+//
+// Backends are healthy until checked, so routes added by a reload are usable
+// right away.
+type DownBackends = Arc<RwLock<HashSet<BackendAddr>>>;
+
+// Number of failovers to a lower priority backend indexed by SNI name
+pub type FailoverCounts = Arc<DashMap<String, AtomicU64>>;
+
+// Active QUIC connections indexed by their stable ID
+type ConnectionTable = Arc<Mutex<HashMap<usize, quinn::Connection>>>;
+
+// Recent connection attempt times indexed by source IP
+type ReconnectTable = Arc<DashMap<IpAddr, VecDeque<Instant>>>;
+
+// Configure QUIC server with the operator certificate (or a self-signed one) and transport settings
 // Returns server configuration and certificate data
-fn configure_server() -> Result<(ServerConfig, Vec<u8>), Box<dyn Error>> {
-    // Generate self-signed certificate for localhost
-    let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
-    
-    // Serialize certificate to DER format
-    let cert_der = cert.serialize_der().unwrap();
-    
-    // Extract and wrap private key
-    let priv_key = cert.serialize_private_key_der();
-    let priv_key = rustls::PrivateKey(priv_key);
-    
-    // Create certificate chain with single self-signed certificate
-    let cert_chain = vec![rustls::Certificate(cert_der.clone())];
+pub fn configure_server(
+    transport: &TransportOpt,
+    tls: &ServerTlsOpt,
+    sni_certs: &SniCerts,
+) -> Result<(ServerConfig, Vec<u8>), Box<dyn Error + Send + Sync>> {
+    let (cert_chain, priv_key) = match tls.server_identity()? {
+        Some(identity) => {
+            if let Some(cn) = subject_common_name(&identity.0[0]) {
+                info!("[server] using TLS certificate for {}", cn);
+            }
+            check_cert_expiry(&identity.0[0]);
+            identity
+        }
+        None => {
+            // Generate a self-signed certificate for the configured names
+            let identity = self_signed_identity(&tls.cert_san)?;
+            info!(
+                "[server] generated self-signed certificate for {}, fingerprint {}",
+                tls.cert_san.join(", "),
+                hex::encode(cert_digest(&identity.0[0]))
+            );
+            identity
+        }
+    };
+    let cert_der = cert_chain[0].0.clone();
+
+    // Same TLS settings as ServerConfig::with_single_cert, plus optional
+    // client authentication and per-SNI certificates
+    let builder = rustls::ServerConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&rustls::version::TLS13])?;
+    let builder = match tls.client_verifier()? {
+        Some(verifier) => builder.with_client_cert_verifier(verifier),
+        None => builder.with_no_client_auth(),
+    };
+    let mut crypto = if sni_certs.is_empty() {
+        builder.with_single_cert(cert_chain, priv_key)?
+    } else {
+        // Names not listed in [certs] get the default certificate
+        let resolver = SniCertResolver::new(sni_certs, (cert_chain, priv_key))?;
+        for name in sni_certs.keys() {
+            info!("[server] using TLS certificate of [certs] for SNI {}", name);
+        }
+        builder.with_cert_resolver(Arc::new(resolver))
+    };
+    crypto.max_early_data_size = u32::MAX;
+    if let Some(path) = &tls.keylog {
+        crypto.key_log = KeyLogFile::open(path)?;
+    }
+
+    // Create Quinn server configuration with the TLS configuration
+    let mut server_config = ServerConfig::with_crypto(Arc::new(crypto));
 
-    // Create Quinn server configuration with TLS certificate
-    let mut server_config = ServerConfig::with_single_cert(cert_chain, priv_key)?;
-    
     // Configure transport layer settings
     let transport_config = Arc::get_mut(&mut server_config.transport).unwrap();
     
     // Disable unidirectional streams (only bidirectional streams allowed)
     transport_config.max_concurrent_uni_streams(0_u8.into());
     
-    // Set maximum idle timeout to 60 seconds
-    transport_config.max_idle_timeout(Some(VarInt::from_u32(60_000).into()));
-    
-    // Send keep-alive packets every 1 second
-    transport_config.keep_alive_interval(Some(std::time::Duration::from_secs(1)));
-    
-    // Enable MTU discovery on supported platforms
-    #[cfg(any(windows, os = "linux"))]
-    transport_config.mtu_discovery_config(Some(quinn::MtuDiscoveryConfig::default()));
+    // Set the keep-alive interval and idle timeout, enable MTU discovery on
+    // supported platforms and apply tuning options
+    transport.apply(transport_config)?;
 
     Ok((server_config, cert_der))
 }
 
-// Create a QUIC server endpoint bound to the specified address
-// Returns the endpoint and certificate data
-#[allow(unused)]
-pub fn make_server_endpoint(bind_addr: SocketAddr) -> Result<(Endpoint, Vec<u8>), Box<dyn Error>> {
+// Warn when a certificate expires within CERT_EXPIRY_WARNING, error once it expired
+fn check_cert_expiry(cert: &rustls::Certificate) {
+    let not_after = match cert_not_after(cert) {
+        Some(not_after) => not_after,
+        None => {
+            warn!("[server] could not read the certificate expiry date");
+            return;
+        }
+    };
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    let expires_at = chrono::DateTime::from_timestamp(not_after, 0)
+        .map_or(not_after.to_string(), |t| t.to_rfc3339());
+
+    if not_after <= now {
+        error!("[server] TLS certificate expired at {}", expires_at);
+    } else if not_after - now <= CERT_EXPIRY_WARNING.as_secs() as i64 {
+        warn!(
+            "[server] TLS certificate expires in {} days, at {}",
+            (not_after - now) / 86400,
+            expires_at
+        );
+    }
+}
+
+// Build endpoint-wide settings shared by all connections
+fn configure_endpoint(options: &Opt) -> Result<EndpointConfig, ConfigError> {
+    // Derive stateless reset tokens from the shared secret when one is given,
+    // otherwise Quinn uses a random per-process key
+    let mut endpoint_config = match &options.stateless_reset_secret {
+        Some(secret) => {
+            let reset_key = hmac::Key::new(hmac::HMAC_SHA256, secret);
+            let mut endpoint_config = EndpointConfig::new(Arc::new(reset_key));
+            options.transport.apply_endpoint(&mut endpoint_config)?;
+            endpoint_config
+        }
+        None => options.transport.endpoint_config()?,
+    };
+
+    // Restrict the versions listed in Version Negotiation packets
+    if !options.quic_supported_versions.is_empty() {
+        info!(
+            "[server] supported QUIC versions: {}",
+            format_quic_versions(&options.quic_supported_versions)
+        );
+        endpoint_config.supported_versions(options.quic_supported_versions.clone());
+    }
+
+    Ok(endpoint_config)
+}
+
+// Parse a stateless reset secret given as 64 hexadecimal characters
+fn parse_reset_secret(s: &str) -> Result<[u8; 32], String> {
+    let bytes = hex::decode(s.trim()).map_err(|e| format!("invalid hex: {}", e))?;
+    bytes
+        .try_into()
+        .map_err(|bytes: Vec<u8>| format!("expected 32 bytes, got {}", bytes.len()))
+}
+
+// Parse a fraction between 0.0 and 1.0
+fn parse_fraction(s: &str) -> Result<f64, String> {
+    let fraction: f64 = s.parse().map_err(|e| format!("invalid fraction: {}", e))?;
+    if !(0.0..=1.0).contains(&fraction) {
+        return Err("fraction must be between 0.0 and 1.0".to_string());
+    }
+    Ok(fraction)
+}
+
+// Parse a metric label given as KEY=VALUE, KEY being a Prometheus label name
+fn parse_metric_label(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s.split_once('=').ok_or("expected KEY=VALUE")?;
+    let mut chars = key.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == ':')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':');
+    if !valid {
+        return Err(format!("invalid label name {:?}, expected [a-zA-Z_:][a-zA-Z0-9_:]*", key));
+    }
+    if key == "environment" {
+        return Err("the environment label is set by --environment".to_string());
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+// Build the complete server configuration from the options
+// Returns server configuration and certificate data
+fn build_server_config(
+    options: &Opt,
+    sni_certs: &SniCerts,
+) -> Result<(ServerConfig, Vec<u8>), Box<dyn Error + Send + Sync>> {
     // Get server configuration and certificate
-    let (server_config, server_cert) = configure_server()?;
-    
-    // Create server endpoint bound to the specified address
-    let endpoint = Endpoint::server(server_config, bind_addr)?;
-    
-    Ok((endpoint, server_cert))
+    let (mut server_config, server_cert) = configure_server(&options.transport, &options.tls, sni_certs)?;
+
+    // Refuse new connections at the QUIC layer once the backlog is full
+    if let Some(backlog) = options.quic_connection_backlog {
+        server_config.concurrent_connections(backlog);
+    }
+
+    // Let QUIC flow control hold back clients opening more streams than allowed
+    Arc::get_mut(&mut server_config.transport)
+        .unwrap()
+        .max_concurrent_bidi_streams(options.max_pending_streams.into());
+
+    Ok((server_config, server_cert))
+}
+
+// Create QUIC server endpoints bound to each listen address, sharing one configuration
+pub fn make_server_endpoints(
+    options: &Opt,
+    server_config: ServerConfig,
+    endpoint_config: EndpointConfig,
+) -> Result<Vec<Endpoint>, Box<dyn Error + Send + Sync>> {
+    if options.transport.chaos.enabled() {
+        warn!("[server] chaos mode enabled: {}", options.transport.chaos);
+    }
+
+    // Create a server endpoint on each socket
+    let mut endpoints = Vec::new();
+    for socket in listen_sockets(options)? {
+        endpoints.push(options.transport.make_endpoint(
+            endpoint_config.clone(),
+            Some(server_config.clone()),
+            socket,
+        )?);
+    }
+
+    Ok(endpoints)
+}
+
+// Sockets passed by systemd socket activation, or else bound to each listen address
+fn listen_sockets(options: &Opt) -> Result<Vec<std::net::UdpSocket>, Box<dyn Error + Send + Sync>> {
+    #[cfg(unix)]
+    if options.systemd {
+        let sockets = systemd::listen_sockets()?;
+        if !sockets.is_empty() {
+            info!("[server] using {} sockets passed by systemd", sockets.len());
+            return Ok(sockets);
+        }
+    }
+
+    let v6_only = options.listen.len() > 1;
+    #[cfg(unix)]
+    let reuse_port = options.workers > 1;
+    #[cfg(not(unix))]
+    let reuse_port = false;
+    let mut sockets = Vec::new();
+    for addr in &options.listen {
+        let socket = bind_udp_socket(*addr, v6_only, reuse_port)
+            .map_err(|e| client::bind_error(*addr, e))?;
+        sockets.push(socket);
+    }
+    Ok(sockets)
+}
+
+// Bind a UDP socket, optionally restricting IPv6 sockets to IPv6 traffic and
+// sharing the port with the other workers
+fn bind_udp_socket(
+    addr: SocketAddr,
+    v6_only: bool,
+    reuse_port: bool,
+) -> std::io::Result<std::net::UdpSocket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    if addr.is_ipv6() && v6_only {
+        socket.set_only_v6(true)?;
+    }
+    #[cfg(unix)]
+    if reuse_port {
+        socket.set_reuse_port(true)?;
+    }
+    #[cfg(not(unix))]
+    let _ = reuse_port;
+    socket.bind(&addr.into())?;
+    Ok(socket.into())
 }
 
 // Configuration structure for server proxy mappings
+// Server options in the [server] section are applied by config_file_args
 #[derive(Deserialize, Debug)]
 struct ServerConf {
     // Map of SNI hostnames to SSH server addresses
-    proxy: HashMap<String, SocketAddr>,
+    #[serde(default)]
+    proxy: HashMap<String, Backends>,
+    // Map of SNI hostnames to the certificate and key presented to them
+    #[serde(default)]
+    certs: SniCerts,
+    // Source IP access lists, added to --allow-ip and --deny-ip
+    #[serde(default)]
+    access: AccessList,
+}
+
+// A single value or a list of values in the configuration file
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T> From<OneOrMany<T>> for Vec<T> {
+    fn from(value: OneOrMany<T>) -> Self {
+        match value {
+            OneOrMany::One(value) => vec![value],
+            OneOrMany::Many(values) => values,
+        }
+    }
+}
+
+// Backends of a route as written in the configuration file
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum RouteConf<A> {
+    // One address, or a list used in turn by successive connections
+    Addrs(OneOrMany<A>),
+    // [[proxy."name".backends]] tables, tried by priority until one connects
+    Failover { backends: Vec<PrioritizedBackend<A>> },
+}
+
+// Backend of a failover route
+#[derive(Deserialize, Debug)]
+struct PrioritizedBackend<A> {
+    addr: A,
+    // Lower values are tried first, like DNS SRV records
+    #[serde(default)]
+    priority: u32,
+}
+
+impl<A> RouteConf<A> {
+    // Backend addresses in the order they are tried, and whether they fail over
+    fn into_addrs(self) -> (Vec<A>, bool) {
+        match self {
+            RouteConf::Addrs(addrs) => (addrs.into(), false),
+            RouteConf::Failover { mut backends } => {
+                backends.sort_by_key(|backend| backend.priority);
+                (backends.into_iter().map(|backend| backend.addr).collect(), true)
+            }
+        }
+    }
+}
+
+// SSH servers of a route
+#[derive(Deserialize, Debug)]
+#[serde(try_from = "RouteConf<BackendAddr>")]
+struct Backends {
+    addrs: Vec<BackendAddr>,
+    // Try the backends by priority instead of in turn
+    failover: bool,
+    // Connections routed so far, selects the next backend
+    next: AtomicUsize,
+}
+
+impl TryFrom<RouteConf<BackendAddr>> for Backends {
+    type Error = String;
+
+    fn try_from(value: RouteConf<BackendAddr>) -> Result<Self, Self::Error> {
+        let (addrs, failover) = value.into_addrs();
+        if addrs.is_empty() {
+            return Err("a route needs at least one backend address".to_string());
+        }
+        Ok(Backends {
+            addrs,
+            failover,
+            next: AtomicUsize::new(0),
+        })
+    }
+}
+
+impl Backends {
+    // Backends a new connection tries in order
+    //
+    // Failover routes list every backend by priority, the ones that are down
+    // last; other routes give a single backend chosen in turn.
+    fn select(&self, down: &HashSet<BackendAddr>) -> Vec<BackendAddr> {
+        if !self.failover {
+            return vec![self.pick(down)];
+        }
+        let (mut up, down): (Vec<_>, Vec<_>) =
+            self.addrs.iter().cloned().partition(|addr| !down.contains(addr));
+        up.extend(down);
+        up
+    }
+
+    // Round-robin selection of the backend for a new connection, skipping
+    // the backends that are down
+    //
+    // When all of them are down the usual pick is returned, so the connection
+    // attempt and its error are still logged.
+    fn pick(&self, down: &HashSet<BackendAddr>) -> BackendAddr {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        (0..self.addrs.len())
+            .map(|i| &self.addrs[(start + i) % self.addrs.len()])
+            .find(|addr| !down.contains(addr))
+            .unwrap_or(&self.addrs[start % self.addrs.len()])
+            .clone()
+    }
+}
+
+impl PartialEq for Backends {
+    fn eq(&self, other: &Self) -> bool {
+        self.addrs == other.addrs && self.failover == other.failover
+    }
+}
+
+impl fmt::Display for Backends {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, addr) in self.addrs.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", addr)?;
+        }
+        if self.failover {
+            write!(f, " (failover)")?;
+        }
+        Ok(())
+    }
+}
+
+// Source networks allowed or denied to connect
+#[derive(Deserialize, Debug, Default, Clone)]
+struct AccessList {
+    #[serde(default)]
+    allow: Vec<IpNet>,
+    #[serde(default)]
+    deny: Vec<IpNet>,
+}
+
+impl AccessList {
+    // Deny rules win, then the IP must match an allow rule unless there are none
+    fn permits(&self, ip: IpAddr) -> bool {
+        // IPv4 clients of a dual-stack socket appear as IPv4-mapped IPv6 addresses
+        let ip = ip.to_canonical();
+        if self.deny.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip))
+    }
+}
+
+// Syntax of the configuration file
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConfFormat {
+    Toml,
+    Yaml,
+    // YAML for .yaml and .yml files, TOML otherwise
+    #[default]
+    Auto,
+}
+
+impl ConfFormat {
+    // Resolve auto-detection from the file extension
+    fn detect(self, path: &Path) -> ConfFormat {
+        match self {
+            ConfFormat::Auto => match path.extension().and_then(|ext| ext.to_str()) {
+                Some(ext) if ext.eq_ignore_ascii_case("yaml") || ext.eq_ignore_ascii_case("yml") => {
+                    ConfFormat::Yaml
+                }
+                _ => ConfFormat::Toml,
+            },
+            format => format,
+        }
+    }
+}
+
+// Parse a configuration file, naming the parser used in errors
+fn parse_conf<T: DeserializeOwned>(
+    path: &Path,
+    content: &str,
+    format: ConfFormat,
+) -> Result<T, Box<dyn Error + Send + Sync>> {
+    let result = match format.detect(path) {
+        ConfFormat::Yaml => serde_yaml::from_str(content).map_err(|e| ("YAML", e.to_string())),
+        _ => toml::from_str(content).map_err(|e| ("TOML", e.to_string())),
+    };
+    result.map_err(|(name, e)| format!("invalid {} config file {}: {}", name, path.display(), e).into())
+}
+
+// Parse CONTENT as a TOML configuration file of the server, discarding it
+//
+// Entry point of the fuzz target of the configuration parser, ServerConf
+// itself is private.
+pub fn parse_server_conf(content: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    toml::from_str::<ServerConf>(content)
+        .map(|_| ())
+        .map_err(|e| format!("invalid TOML config: {}", e).into())
+}
+
+// Configuration file section holding server command line options
+#[derive(Deserialize, Debug)]
+struct ServerOptConf {
+    // Options keyed by their long flag name (dashes or underscores)
+    #[serde(default)]
+    server: toml::Table,
+}
+
+// Build extra command line arguments for the options set in the [server]
+// section of the --conf file
+//
+// Options given on the command line or through an environment variable are
+// skipped, see option_args.
+pub fn config_file_args(matches: &ArgMatches) -> Result<Vec<OsString>, Box<dyn Error + Send + Sync>> {
+    let path = match matches.get_one::<PathBuf>("conf_path") {
+        Some(path) => path,
+        None => return Ok(Vec::new()),
+    };
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    let format = matches
+        .get_one::<ConfFormat>("conf_format")
+        .copied()
+        .unwrap_or_default();
+    let conf: ServerOptConf = parse_conf(path, &content, format)?;
+
+    option_args(&Opt::command(), matches, conf.server, "[server] section", "conf")
+}
+
+// Example of the [proxy], [certs] and [access] sections read into ServerConf
+const EXAMPLE_SERVER_CONF: &str = r#"
+# SNI host names and the SSH servers their connections are relayed to, as
+# ip:port or unix:/path; "default" serves names without their own entry
+[proxy]
+# default = "127.0.0.1:22"
+# "hostname1" = "192.168.1.100:22"
+# Several backends are used in turn, skipping those failing the health check
+# "cluster" = ["192.168.1.110:22", "192.168.1.111:22"]
+# Failover backends are tried by priority, lowest first
+# [[proxy."db.example.com".backends]]
+# addr = "192.168.1.120:22"
+# priority = 1
+
+# SNI host names and the certificate and key presented to them, other names
+# get the default certificate
+[certs]
+# "hostname1" = { cert = "/etc/quicssh/hostname1.crt", key = "/etc/quicssh/hostname1.key" }
+
+# Source networks (CIDR) allowed or denied to connect, added to --allow-ip and
+# --deny-ip; deny wins, and with any allow entry other sources are rejected
+[access]
+allow = []
+deny = []
+"#;
+
+// Commented configuration file listing every supported key
+//
+// The [server] keys come from the server options, so the file always
+// matches them; values are commented out and leave the defaults in place.
+pub fn example_config() -> String {
+    let options = example_options(&Opt::command(), "conf");
+    format!(
+        "# quicssh-rs server configuration, used with server --conf\n\
+         \n\
+         # Server options keyed by their long flag name; command line flags and\n\
+         # environment variables take precedence over them\n\
+         [server]\n{}{}",
+        options, EXAMPLE_SERVER_CONF
+    )
 }
 
 impl ServerConf {
     // Create new empty server configuration
     fn new() -> Self {
         ServerConf {
-            proxy: HashMap::<String, SocketAddr>::new(),
+            proxy: HashMap::<String, Backends>::new(),
+            certs: SniCerts::new(),
+            access: AccessList::default(),
+        }
+    }
+
+    // Load the configuration file, if any, and add the command line access lists
+    async fn load(options: &Opt) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut conf: ServerConf = match &options.conf_path {
+            // Read and parse TOML or YAML configuration file
+            Some(path) => parse_conf(path, &(read_to_string(path).await?), options.conf_format)?,
+            // Use empty configuration if no file provided
+            None => ServerConf::new(),
+        };
+        conf.access.allow.extend_from_slice(&options.allow_ip);
+        conf.access.deny.extend_from_slice(&options.deny_ip);
+        Ok(conf)
+    }
+
+    // SSH servers for SNI names without their own entry
+    fn default_proxy(&self, options: &Opt, down: &HashSet<BackendAddr>) -> Vec<BackendAddr> {
+        match self.proxy.get("default") {
+            // Use "default" entry from config if available
+            Some(backends) => backends.select(down),
+            // Fall back to command line option or localhost:22
+            None => vec![options
+                .proxy_to
+                .clone()
+                .unwrap_or(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 22).into())],
         }
     }
+
+    // Number of backends connections can be routed to, and the sorted
+    // addresses of those marked down
+    fn backend_health(&self, options: &Opt, down: &HashSet<BackendAddr>) -> (usize, Vec<String>) {
+        let default_proxy = self.default_proxy(options, down);
+        let backends: HashSet<&BackendAddr> = self
+            .proxy
+            .values()
+            .flat_map(|backends| backends.addrs.iter())
+            .chain(&default_proxy)
+            .collect();
+        let mut failed: Vec<String> = backends
+            .iter()
+            .filter(|addr| down.contains(*addr))
+            .map(|addr| addr.to_string())
+            .collect();
+        failed.sort();
+        (backends.len(), failed)
+    }
+}
+
+// Options of the validate command
+#[derive(Parser, Debug)]
+#[non_exhaustive]
+pub struct ValidateOpt {
+    // Configuration file to check
+    path: PathBuf,
+    // Syntax of the configuration file
+    #[clap(long = "conf-format", value_enum, default_value = "auto")]
+    conf_format: ConfFormat,
+}
+
+// Routes of a configuration file, kept as text so each entry is checked separately
+#[derive(Deserialize, Debug)]
+struct RawRoutes {
+    #[serde(default)]
+    proxy: HashMap<String, RouteConf<String>>,
+}
+
+// Check a configuration file without starting the server
+//
+// Prints one row per check and returns whether all of them passed. Nothing is
+// bound, so this runs unprivileged, e.g. in CI before a deployment.
+#[tokio::main]
+pub async fn validate(opt: ValidateOpt, output_format: OutputFormat) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let content = read_to_string(&opt.path)
+        .await
+        .map_err(|e| format!("failed to read {}: {}", opt.path.display(), e))?;
+    let mut rows: Vec<(String, String, Result<String, String>)> = Vec::new();
+
+    // [server] options, parsed exactly as `server --conf` would
+    let format = opt.conf_format.to_possible_value().unwrap();
+    let argv: Vec<OsString> = vec![
+        "server".into(),
+        "--conf".into(),
+        opt.path.clone().into(),
+        "--conf-format".into(),
+        format.get_name().into(),
+    ];
+    let server_opt = Opt::command()
+        .try_get_matches_from(&argv)
+        .map_err(|e| e.to_string())
+        .and_then(|matches| config_file_args(&matches).map_err(|e| e.to_string()))
+        .and_then(|extra| Opt::try_parse_from(argv.into_iter().chain(extra)).map_err(|e| e.to_string()));
+    rows.push((
+        "options".to_string(),
+        "[server]".to_string(),
+        server_opt.as_ref().map(|_| "ok".to_string()).map_err(|e| e.trim().to_string()),
+    ));
+
+    // Routes must be socket addresses, host names are not resolved at runtime
+    match parse_conf::<RawRoutes>(&opt.path, &content, opt.conf_format) {
+        Ok(raw) => {
+            let mut routes: Vec<_> = raw.proxy.into_iter().collect();
+            routes.sort_by(|(a, _), (b, _)| a.cmp(b));
+            for (name, addrs) in routes {
+                let (addrs, _) = addrs.into_addrs();
+                let mut result = match addrs.is_empty() {
+                    true => Err("a route needs at least one backend address".to_string()),
+                    false => Ok(addrs.join(", ")),
+                };
+                for addr in &addrs {
+                    if let Err(e) = check_backend(addr).await {
+                        result = Err(e);
+                        break;
+                    }
+                }
+                rows.push(("route".to_string(), name, result));
+            }
+        }
+        Err(e) => rows.push(("routes".to_string(), "[proxy]".to_string(), Err(e.to_string()))),
+    }
+
+    // Whole file, including the [certs] and [access] sections
+    match parse_conf::<ServerConf>(&opt.path, &content, opt.conf_format) {
+        Ok(conf) => {
+            let mut certs: Vec<_> = conf.certs.iter().collect();
+            certs.sort_by_key(|(name, _)| name.as_str());
+            for (name, files) in certs {
+                let result = load_certs_as(&files.cert, CertFormat::Auto)
+                    .and_then(|_| load_private_key_as(&files.key, CertFormat::Auto))
+                    .map(|_| "ok".to_string())
+                    .map_err(|e| e.to_string());
+                rows.push(("cert".to_string(), name.clone(), result));
+            }
+            rows.push((
+                "access".to_string(),
+                "[access]".to_string(),
+                Ok(format!("{} allowed, {} denied", conf.access.allow.len(), conf.access.deny.len())),
+            ));
+        }
+        Err(e) => rows.push(("config".to_string(), opt.path.display().to_string(), Err(e.to_string()))),
+    }
+
+    // Certificate and key given in the [server] section
+    if let Ok(server_opt) = &server_opt {
+        if server_opt.tls.cert.is_some() || server_opt.tls.key.is_some() {
+            let result = server_opt
+                .tls
+                .server_identity()
+                .map(|_| "ok".to_string())
+                .map_err(|e| e.to_string());
+            rows.push(("cert".to_string(), "[server]".to_string(), result));
+        }
+    }
+
+    let passed = rows.iter().all(|(_, _, result)| result.is_ok());
+    if output_format == OutputFormat::Json {
+        let checks: Vec<_> = rows
+            .iter()
+            .map(|(kind, name, result)| match result {
+                Ok(detail) => json!({ "kind": kind, "name": name, "ok": true, "detail": detail }),
+                Err(e) => json!({ "kind": kind, "name": name, "ok": false, "error": e }),
+            })
+            .collect();
+        output::print_json(&json!({ "path": opt.path, "ok": passed, "checks": checks }))?;
+        return Ok(passed);
+    }
+
+    // Print the rows as aligned columns
+    let kind_width = rows.iter().map(|(kind, _, _)| kind.len()).max().unwrap_or(0);
+    let name_width = rows.iter().map(|(_, name, _)| name.len()).max().unwrap_or(0);
+    for (kind, name, result) in &rows {
+        let status = match result {
+            Ok(detail) => detail.clone(),
+            Err(e) => format!("error: {}", e),
+        };
+        println!("{:kind_width$}  {:name_width$}  {}", kind, name, status);
+    }
+    Ok(passed)
+}
+
+// Check that a backend is a socket address or an existing Unix socket, host
+// names are not resolved at runtime
+async fn check_backend(addr: &str) -> Result<(), String> {
+    match addr.parse::<BackendAddr>() {
+        Ok(BackendAddr::Tcp(_)) => Ok(()),
+        Ok(BackendAddr::Unix(path)) => tokio::fs::metadata(&path)
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("{}: {}", addr, e)),
+        Err(e) => match lookup_host(addr).await {
+            Ok(mut resolved) => Err(format!(
+                "{} is a host name, use a socket address{}",
+                addr,
+                resolved
+                    .next()
+                    .map(|a| format!(" such as {}", a))
+                    .unwrap_or_default()
+            )),
+            Err(_) => Err(e),
+        },
+    }
+}
+
+// Log the SNI routes added, changed or removed by a configuration reload
+fn log_route_changes(old: &HashMap<String, Backends>, new: &HashMap<String, Backends>) {
+    for (name, addr) in new {
+        match old.get(name) {
+            None => info!("[server] route added: {} -> {}", name, addr),
+            Some(old_addr) if old_addr != addr => {
+                info!("[server] route changed: {} -> {} (was {})", name, addr, old_addr)
+            }
+            Some(_) => {}
+        }
+    }
+    for (name, addr) in old {
+        if !new.contains_key(name) {
+            info!("[server] route removed: {} -> {}", name, addr);
+        }
+    }
+}
+
+// Server initialized by init(), ready to bind its ports in serve()
+pub struct ServerState {
+    options: Arc<Opt>,
+    conf: ServerConf,
+    server_config: ServerConfig,
+    endpoint_config: EndpointConfig,
+    // User to switch to once the ports are bound
+    #[cfg(unix)]
+    drop_user: Option<nix::unistd::User>,
+}
+
+// Builder of a server for programs embedding it, e.g.
+// ServerBuilder::new().listen(addr).proxy_to("127.0.0.1:22").run().await
+//
+// Options without a setter are given as command line arguments with arg().
+pub struct ServerBuilder {
+    args: Vec<OsString>,
+    environment: String,
+    events: Option<Arc<dyn ConnectionHandler>>,
+}
+
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        ServerBuilder {
+            args: vec!["server".into()],
+            environment: "unknown".to_string(),
+            events: None,
+        }
+    }
+}
+
+impl ServerBuilder {
+    // Server with the default options of quicssh-rs server
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Address to bind the server to, may be given several times
+    pub fn listen(self, addr: SocketAddr) -> Self {
+        self.arg("--listen").arg(addr.to_string())
+    }
+
+    // SSH server (ip:port or unix:/path) connections are relayed to
+    pub fn proxy_to(self, addr: &str) -> Self {
+        self.arg("--proxy-to").arg(addr)
+    }
+
+    // Any other server option, e.g. .arg("--conf").arg(path)
+    pub fn arg(mut self, arg: impl Into<OsString>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    // Deployment environment attached to the connection records
+    pub fn environment(mut self, environment: &str) -> Self {
+        self.environment = environment.to_string();
+        self
+    }
+
+    // Callbacks told about each connection
+    pub fn connection_handler(mut self, handler: Box<dyn ConnectionHandler>) -> Self {
+        self.events = Some(Arc::from(handler));
+        self
+    }
+
+    // Check the options, then serve connections until SIGTERM or SIGINT
+    pub async fn run(self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let options = Opt::try_parse_from(&self.args)?;
+        let state = init(options).await?;
+        serve(state, self.environment, OutputFormat::Text, self.events).await
+    }
+}
+
+// Run the QUIC server, in the background with --daemonize
+//
+// With --dry-run the server is only initialized and its backends checked.
+pub fn run(options: Opt, environment: String, format: OutputFormat) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if options.dry_run {
+        return dry_run(options, format);
+    }
+    // Fork before the runtime starts its threads
+    #[cfg(unix)]
+    let _pid_file = {
+        if options.daemonize {
+            daemon::daemonize()?;
+        }
+        options.pid_file.as_deref().map(PidFile::create).transpose()?
+    };
+    #[cfg(unix)]
+    if options.workers > 1 && !workers::supervise(options.workers)? {
+        return Ok(());
+    }
+    run_runtime(options, environment, format)
 }
 
 // Main async function to run the QUIC server
 #[tokio::main]
-pub async fn run(options: Opt) -> Result<(), Box<dyn Error>> {
-    // Load server configuration from file or create empty config
-    let conf: ServerConf = match options.conf_path {
-        Some(path) => {
-            info!("[server] importing conf file: {}", path.display());
-            // Read and parse TOML configuration file
-            toml::from_str(&(read_to_string(path).await?))?
-        }
-        // Use empty configuration if no file provided
-        None => ServerConf::new(),
+async fn run_runtime(
+    options: Opt,
+    environment: String,
+    format: OutputFormat,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // Export the spans of the connections until the server stops
+    let otel = match &options.otel_endpoint {
+        Some(endpoint) => Some(otel::init(endpoint)?),
+        None => None,
     };
+    let result = match init(options).await {
+        Ok(state) => serve(state, environment, format, None).await,
+        Err(e) => Err(e),
+    };
+    if let Some(otel) = otel {
+        otel.shutdown().await;
+    }
+    result
+}
+
+// Initialize the server and check its backends, without binding any port
+//
+// Fails when initialization fails or a backend does not accept a TCP
+// connection within --health-check-timeout-ms.
+#[tokio::main]
+async fn dry_run(options: Opt, format: OutputFormat) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let state = init(options).await?;
+    let options = &state.options;
 
-    // Determine default SSH server to proxy to
-    let default_proxy = match conf.proxy.get("default") {
-        // Use "default" entry from config if available
-        Some(sock) => sock.clone(),
-        // Fall back to command line option or localhost:22
-        None => options
-            .proxy_to
-            .unwrap_or(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 22)),
+    // Every backend a connection can be routed to, echo mode uses none
+    let mut addrs: HashSet<BackendAddr> = state
+        .conf
+        .proxy
+        .values()
+        .flat_map(|backends| backends.addrs.iter().cloned())
+        .collect();
+    if !options.debug_echo && !state.conf.proxy.contains_key("default") {
+        addrs.extend(state.conf.default_proxy(options, &HashSet::new()));
+    }
+    let timeout = Duration::from_millis(options.health_check_timeout_ms);
+    let mut results = probe_backends(addrs, timeout).await;
+    results.sort_by_key(|(addr, _)| addr.to_string());
+
+    let mut errors = Vec::new();
+    for (addr, result) in &results {
+        match result {
+            Ok(()) => info!("[server] dry run: backend {} is up", addr),
+            Err(e) => errors.push(format!("backend {} is down: {}", addr, e)),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors.join("; ").into());
+    }
+
+    match format {
+        OutputFormat::Json => output::print_json(&json!({
+            "ok": true,
+            "routes": state.conf.proxy.len(),
+            "backends": results.len(),
+        }))?,
+        OutputFormat::Text => println!(
+            "dry run ok: {} routes, {} backends up",
+            state.conf.proxy.len(),
+            results.len()
+        ),
+    }
+    Ok(())
+}
+
+// Load the configuration files and certificates and check the options
+//
+// Nothing is bound yet, see serve().
+pub async fn init(options: Opt) -> Result<ServerState, Box<dyn Error + Send + Sync>> {
+    // Share the options with every connection handler
+    let options = Arc::new(options);
+
+    // Load server configuration from file or create empty config
+    if let Some(path) = &options.conf_path {
+        info!("[server] importing conf file: {}", path.display());
+    }
+    let conf = ServerConf::load(&options).await?;
+    // Print the whole pool without advancing the round-robin counter
+    match conf.proxy.get("default") {
+        Some(backends) => info!("[server] default proxy aim: {}", backends),
+        None => info!("[server] default proxy aim: {}", conf.default_proxy(&options, &HashSet::new())[0]),
+    }
+    if !conf.access.allow.is_empty() || !conf.access.deny.is_empty() {
+        info!(
+            "[server] access lists: {} allowed, {} denied networks",
+            conf.access.allow.len(),
+            conf.access.deny.len()
+        );
+    }
+    if options.debug_echo {
+        warn!("[server] debug echo mode: streams are echoed back, no SSH backend is used");
+    }
+    if let Some(path) = &options.tls.keylog {
+        warn!(
+            "[server] writing TLS secrets to {}, anyone reading it can decrypt the \
+             captured traffic; do not use --keylog in production",
+            path.display()
+        );
+    }
+
+    // An unknown user fails before any port is claimed
+    #[cfg(unix)]
+    let drop_user = options.drop_privs_user.as_deref().map(privs::lookup_user).transpose()?;
+    #[cfg(not(unix))]
+    if options.drop_privs_user.is_some() {
+        warn!("[server] --drop-privs-user is only supported on Unix, ignoring it");
+    }
+
+    // Load the certificates and build the QUIC configuration
+    let (server_config, _) = build_server_config(&options, &conf.certs)?;
+    let endpoint_config = configure_endpoint(&options)?;
+
+    Ok(ServerState {
+        options,
+        conf,
+        server_config,
+        endpoint_config,
+        #[cfg(unix)]
+        drop_user,
+    })
+}
+
+// Bind the ports of an initialized server, then accept and proxy connections
+// until the server is shut down
+//
+// EVENTS, when given, is told about each connection.
+pub async fn serve(
+    state: ServerState,
+    environment: String,
+    format: OutputFormat,
+    events: Option<Arc<dyn ConnectionHandler>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let ServerState {
+        options,
+        conf,
+        server_config,
+        endpoint_config,
+        #[cfg(unix)]
+        drop_user,
+    } = state;
+
+    // Create and start QUIC server endpoints
+    let endpoints = make_server_endpoints(&options, server_config, endpoint_config)?;
+    let listen_addrs = endpoints
+        .iter()
+        .map(|endpoint| endpoint.local_addr())
+        .collect::<Result<Vec<_>, _>>()?;
+    for addr in &listen_addrs {
+        info!("[server] listening on: {}", addr);
+    }
+
+    // Open the access log before accepting any connection, JSON lines
+    // replace --access-log-format with --output-format json
+    let access_log = match &options.access_log {
+        Some(path) => Some(Arc::new(
+            AccessLog::open(path, options.access_log_format.clone(), format == OutputFormat::Json)
+                .map_err(|e| format!("cannot open access log {}: {}", path.display(), e))?,
+        )),
+        None => None,
     };
-    info!("[server] default proxy aim: {}", default_proxy);
 
-    // Create and start QUIC server endpoint
-    let (endpoint, _) = make_server_endpoint(options.listen).unwrap();
-    info!("[server] listening on: {}", options.listen);
-    
+    // Reload the configuration and certificate files on SIGHUP
+    let conf: SharedConf = Arc::new(RwLock::new(Arc::new(conf)));
+    tokio::spawn(reload_on_signal(
+        endpoints.clone(),
+        options.clone(),
+        conf.clone(),
+        access_log.clone(),
+    ));
+
+    // Connections from all endpoints, along with the address they were accepted on
+    let mut incoming: SelectAll<BoxStream<'static, (quinn::Connecting, SocketAddr)>> = endpoints
+        .iter()
+        .zip(listen_addrs)
+        .map(|(endpoint, addr)| {
+            stream::unfold(endpoint.clone(), move |endpoint| async move {
+                let connecting = endpoint.accept().await?;
+                Some(((connecting, addr), endpoint))
+            })
+            .boxed()
+        })
+        .collect();
+
+    // Keep an eye on the expiry of the operator certificate
+    if options.tls.cert.is_some() {
+        let interval = Duration::from_secs(options.tls.cert_check_interval_hours * 3600);
+        tokio::spawn(watch_cert_expiry(options.tls.clone(), interval));
+    }
+
+    // Open the connection log before accepting any connection
+    let conn_logger = Arc::new(ConnectionLogger::new(
+        options.connection_log_format,
+        options.connection_log.as_deref(),
+    )?);
+
+    // Track active connections so they can be drained selectively
+    let connections: ConnectionTable = Arc::new(Mutex::new(HashMap::new()));
+
+    // Watch the drain file and close matching connections on SIGTERM
+    if let Some(path) = options.drain_connections_file.clone() {
+        let patterns = Arc::new(RwLock::new(Vec::new()));
+        tokio::spawn(watch_drain_file(path, patterns.clone()));
+        tokio::spawn(drain_on_signal(patterns, connections.clone()));
+    }
+
+    if let Some(addr) = options.metrics_addr {
+        let mut labels: HashMap<_, _> = options.metric_label.iter().cloned().collect();
+        labels.insert("environment".to_string(), environment.clone());
+        metrics::serve(addr, labels)?;
+    }
+
+    // Skip backends failing their health check when routing connections
+    let down_backends: DownBackends = Arc::new(RwLock::new(HashSet::new()));
+    tokio::spawn(check_backend_health(
+        conf.clone(),
+        down_backends.clone(),
+        Duration::from_secs(options.health_check_interval),
+        Duration::from_millis(options.health_check_timeout_ms),
+    ));
+
+    // Count failovers per SNI name
+    let failovers: FailoverCounts = Arc::new(DashMap::new());
+
+    // Share the total bandwidth limit between all connections
+    let total_throttle = options
+        .max_total_bw_mbps
+        .map(|mbps| Arc::new(SharedThrottle::from_mbps(mbps)));
+
+    // Track recent connection attempts per source IP
+    let reconnect_window = Duration::from_secs(options.rate_limit_reconnect_window_secs);
+    let reconnects: ReconnectTable = Arc::new(DashMap::new());
+    tokio::spawn(expire_reconnects(reconnects.clone(), reconnect_window));
+
+    // Limit the rate of new connections per source IP
+    let rate_limiter = options.rate_limit_conns.map(|rate| {
+        let limiter = Arc::new(ConnectionRateLimiter::new(rate));
+        tokio::spawn(prune_rate_limiter(limiter.clone()));
+        limiter
+    });
+
+    // Stop accepting connections on SIGTERM, unless it is used to drain connections
+    let (shutdown_tx, mut shutdown_rx) = broadcast::channel(1);
+    if let Some(port) = options.health_port {
+        serve_health(port, &options, &conf, &connections, &down_backends, shutdown_tx.subscribe())?;
+    }
+    tokio::spawn(shutdown_on_signal(
+        shutdown_tx,
+        options.drain_connections_file.is_none(),
+    ));
+
+    // Each handled connection holds a permit when --max-connections is set
+    let connection_limit = options
+        .max_connections
+        .map(|n| Arc::new(Semaphore::new(n as usize)));
+
+    // Every privileged port is bound
+    #[cfg(unix)]
+    if let Some(user) = &drop_user {
+        privs::drop_to(user)?;
+    }
+
+    // Startup is complete, a --daemonize parent can exit and systemd can
+    // start the units waiting for this one
+    #[cfg(unix)]
+    daemon::notify_ready();
+    #[cfg(unix)]
+    if options.systemd {
+        if let Err(e) = systemd::notify("READY=1") {
+            warn!("[server] notifying systemd error: {}", e);
+        }
+    }
+
     // Main server loop - accept and handle connections
     loop {
-        // Wait for incoming connection
-        let incoming_conn = match endpoint.accept().await {
-            Some(conn) => conn,
-            None => {
-                continue;  // No connection, keep waiting
+        // Wait for a free connection slot before taking the next connection
+        let permit = match &connection_limit {
+            Some(limit) => {
+                if limit.available_permits() == 0 {
+                    warn!(
+                        "[server] connection limit of {} reached, new connections wait in the backlog",
+                        options.max_connections.unwrap_or_default()
+                    );
+                }
+                tokio::select! {
+                    permit = limit.clone().acquire_owned() => permit.ok(),
+                    _ = shutdown_rx.recv() => break,
+                }
             }
+            None => None,
         };
+
+        // Wait for incoming connection on any endpoint or shutdown
+        let (incoming_conn, listen_addr) = tokio::select! {
+            next = incoming.next() => match next {
+                Some(next) => next,
+                None => break,  // All endpoints were closed
+            },
+            _ = shutdown_rx.recv() => break,
+        };
+
+        // Count the attempt before the handshake, failed handshakes included
+        let allowed = record_reconnect(
+            &reconnects,
+            incoming_conn.remote_address().ip(),
+            reconnect_window,
+            options.rate_limit_reconnect_max,
+        );
         
+        // Take a token from the bucket of the source IP
+        let rate_allowed = rate_limiter
+            .as_ref()
+            .is_none_or(|limiter| limiter.allow(incoming_conn.remote_address().ip()));
+
         // Complete the connection handshake
         let conn = match incoming_conn.await {
             Ok(conn) => conn,
@@ -148,82 +1527,959 @@ pub async fn run(options: Opt) -> Result<(), Box<dyn Error>> {
                 error!("[server] accept connection error: {}", e);
                 continue;  // Skip failed connections
             }
-        };
+        };
+
+        // Take the configuration current at accept time, a reload does not
+        // affect connections already accepted
+        let conf = conf.read().unwrap().clone();
+
+        // Reject sources outside the access lists
+        if !conf.access.permits(conn.remote_address().ip()) {
+            warn!(
+                "[server] connection from {} denied by access lists",
+                conn.remote_address().ip()
+            );
+            conn.close(ACCESS_DENIED_CODE.into(), b"access denied");
+            continue;
+        }
+
+        // Reject sources that open connections too fast
+        if !rate_allowed {
+            warn!(
+                "[server] connection rate limit exceeded by {}, connection rejected",
+                conn.remote_address().ip()
+            );
+            conn.close(RATE_LIMITED_CODE.into(), b"rate limited");
+            continue;
+        }
+
+        // Reject sources that reconnect too often
+        if !allowed {
+            warn!(
+                "[server] too many reconnects from {}, connection rejected",
+                conn.remote_address().ip()
+            );
+            conn.close(TOO_MANY_RECONNECTS_CODE.into(), b"too many reconnects");
+            continue;
+        }
+
+        // The handshake already verified the client certificate chain, make
+        // sure one was actually presented before serving the connection
+        if options.tls.client_ca.is_some() {
+            let client_cert = conn
+                .peer_identity()
+                .and_then(|identity| identity.downcast::<Vec<rustls::Certificate>>().ok())
+                .and_then(|chain| chain.first().cloned());
+            match client_cert {
+                Some(cert) => info!(
+                    "[server] client {} authenticated as {}",
+                    conn.remote_address(),
+                    subject_common_name(&cert).unwrap_or_else(|| "<no CN>".to_string())
+                ),
+                None => {
+                    warn!(
+                        "[server] client {} presented no certificate, connection rejected",
+                        conn.remote_address()
+                    );
+                    conn.close(CLIENT_CERT_REQUIRED_CODE.into(), b"client certificate required");
+                    continue;
+                }
+            }
+        }
+
+        // Extract SNI (Server Name Indication) from TLS handshake
+        let sni = conn
+            .handshake_data()
+            .unwrap()
+            .downcast::<crypto::rustls::HandshakeData>()
+            .unwrap()
+            .server_name
+            .unwrap_or(conn.remote_address().ip().to_string());  // Fall back to IP if no SNI
+        
+        // Determine which SSH server to proxy to based on SNI
+        // Metrics are labeled by route rather than by the client-chosen SNI,
+        // which would let clients create any number of series
+        let (backends, route) = {
+            let down = down_backends.read().unwrap();
+            match conf.proxy.get(&sni) {
+                Some(backends) => (backends.select(&down), sni.clone()),
+                None => (conf.default_proxy(&options, &down), "default".to_string()),
+            }
+        };
+        let proxy_to = backends[0].clone();
+        
+        // Log connection details
+        info!(
+            session_id = conn.stable_id(),
+            remote_addr:% = conn.remote_address(),
+            backend:% = proxy_to;
+            "[server] connection accepted: ({}, {}, id {}) -> {}",
+            conn.remote_address(),
+            sni,
+            conn.stable_id(),
+            proxy_to
+        );
+
+        // Register the connection until its handler finishes
+        metrics::connection_opened(&route);
+        let id = conn.stable_id();
+        let active = {
+            let mut connections = connections.lock().unwrap();
+            connections.insert(id, conn.clone());
+            connections.len()
+        };
+
+        // Warn when the connection backlog is 80% full
+        if let Some(backlog) = options.quic_connection_backlog {
+            if active as u64 * 5 >= backlog as u64 * 4 {
+                warn!(
+                    "[server] connection backlog {}% full ({}/{})",
+                    active as u64 * 100 / backlog as u64,
+                    active,
+                    backlog
+                );
+            }
+        }
+        let connections = connections.clone();
+        let options = options.clone();
+        let conn_logger = conn_logger.clone();
+        let access_log = access_log.clone();
+        let failovers = failovers.clone();
+        let total_throttle = total_throttle.clone();
+        let environment = environment.clone();
+        let events = events.clone();
+        let remote_addr = conn.remote_address();
+        let started = Instant::now();
+        let info = ConnectionInfo {
+            connection_id: id,
+            remote_addr,
+            sni: sni.clone(),
+            backend: proxy_to.to_string(),
+        };
+        
+        // Periodically log path statistics while the connection is open
+        if let Some(secs) = options.quic_stats_interval_secs {
+            tokio::spawn(log_quic_stats(conn.clone(), Duration::from_secs(secs)));
+        }
+
+        // Root span of the connection, exported with --otel-endpoint
+        // Spans are at trace level: without a subscriber quinn makes tracing
+        // log them, and they would otherwise show up among the info records
+        let span = tracing::trace_span!(
+            "quicssh.connection",
+            remote_ip = %remote_addr.ip(),
+            sni = %sni,
+            backend = %proxy_to,
+        );
+
+        // Spawn async task to handle this connection
+        tokio::spawn(async move {
+            // Release the connection slot once the connection is finished
+            let _permit = permit;
+            let counters = Arc::new(TrafficCounters::default());
+            if let Some(events) = &events {
+                events.on_connect(&info).await;
+            }
+
+            let handler = handle_connection(
+                options,
+                sni.clone(),
+                backends,
+                listen_addr,
+                conn.clone(),
+                counters.clone(),
+                failovers,
+                total_throttle,
+            ).instrument(span.clone());
+            let failure = supervise_handler(handler, &conn).await;
+            if let (Some(events), Some(e)) = (&events, &failure) {
+                events.on_error(&info, e.as_ref()).await;
+            }
+            connections.lock().unwrap().remove(&id);
+            let path = conn.stats().path;
+            metrics::connection_closed(path.rtt);
+            span.in_scope(|| {
+                tracing::trace!(
+                    bytes_in = counters.bytes_in.load(Ordering::Relaxed),
+                    bytes_out = counters.bytes_out.load(Ordering::Relaxed),
+                    "connection closed"
+                )
+            });
+
+            // Log a summary record once the connection is finished
+            let record = ConnectionLogRecord {
+                timestamp: chrono::Utc::now().to_rfc3339(),
+                connection_id: id,
+                remote_addr: remote_addr.to_string(),
+                sni,
+                backend: proxy_to.to_string(),
+                duration_ms: started.elapsed().as_millis(),
+                bytes_in: counters.bytes_in.load(Ordering::Relaxed),
+                bytes_out: counters.bytes_out.load(Ordering::Relaxed),
+                rtt_ms: path.rtt.as_millis(),
+                lost_packets: path.lost_packets,
+                environment,
+            };
+            if let Some(access_log) = &access_log {
+                access_log.log(&record);
+            }
+            conn_logger.log(&record);
+
+            if let Some(events) = &events {
+                let stats = ConnectionStats {
+                    duration: started.elapsed(),
+                    bytes_in: record.bytes_in,
+                    bytes_out: record.bytes_out,
+                    rtt: path.rtt,
+                    lost_packets: path.lost_packets,
+                };
+                events.on_disconnect(&info, &stats).await;
+            }
+        });
+        
+        // Connection handling continues in background
+        // Server loop continues to accept new connections
+    }
+
+    // Refuse new connections and let the active ones finish
+    for endpoint in &endpoints {
+        endpoint.set_server_config(None);
+    }
+    let active = connections.lock().unwrap().len();
+    info!(
+        "[server] shutting down, waiting up to {}s for {} connections",
+        options.shutdown_timeout, active
+    );
+    let timeout = Duration::from_secs(options.shutdown_timeout);
+    let all_idle = future::join_all(endpoints.iter().map(|endpoint| endpoint.wait_idle()));
+    if tokio::time::timeout(timeout, all_idle).await.is_ok() {
+        info!("[server] all connections finished, exiting");
+        return Ok(());
+    }
+
+    // Close the remaining connections
+    let remaining = connections.lock().unwrap().len();
+    warn!("[server] shutdown timeout, closing {} connections", remaining);
+    for endpoint in &endpoints {
+        endpoint.close(0u32.into(), b"server shutdown");
+    }
+    future::join_all(endpoints.iter().map(|endpoint| endpoint.wait_idle())).await;
+    Err(format!(
+        "{} connections still active after {}s",
+        remaining, options.shutdown_timeout
+    )
+    .into())
+}
+
+// Record a connection attempt from a source IP
+// Returns false when the IP made more than max attempts within the window
+fn record_reconnect(reconnects: &ReconnectTable, ip: IpAddr, window: Duration, max: u64) -> bool {
+    let max = usize::try_from(max).unwrap_or(usize::MAX - 1);
+    let now = Instant::now();
+    let mut attempts = reconnects.entry(ip).or_default();
+    while attempts.front().is_some_and(|t| now.duration_since(*t) > window) {
+        attempts.pop_front();
+    }
+    attempts.push_back(now);
+    // Keep at most one attempt over the limit to bound memory under floods
+    if attempts.len() > max + 1 {
+        attempts.pop_front();
+    }
+    attempts.len() <= max
+}
+
+// Periodically drop source IPs without attempts in the last window
+async fn expire_reconnects(reconnects: ReconnectTable, window: Duration) {
+    let mut ticker = tokio::time::interval(window);
+    loop {
+        ticker.tick().await;
+        reconnects.retain(|_, attempts| attempts.back().is_some_and(|t| t.elapsed() <= window));
+    }
+}
+
+// Re-check the expiry of the on-disk certificate every interval
+async fn watch_cert_expiry(tls: ServerTlsOpt, interval: Duration) {
+    let path = match &tls.cert {
+        Some(path) => path.clone(),
+        None => return,
+    };
+    let mut ticker = tokio::time::interval(interval);
+    // The first tick completes immediately and startup already checked the certificate
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+        match load_certs_as(&path, tls.cert_format) {
+            Ok(chain) => check_cert_expiry(&chain[0]),
+            Err(e) => error!("[server] certificate expiry check failed: {}", e),
+        }
+    }
+}
+
+// Periodically connect to every configured backend and record those that are down
+async fn check_backend_health(
+    conf: SharedConf,
+    down: DownBackends,
+    interval: Duration,
+    timeout: Duration,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let addrs: HashSet<BackendAddr> = conf
+            .read()
+            .unwrap()
+            .proxy
+            .values()
+            .flat_map(|backends| backends.addrs.iter().cloned())
+            .collect();
+
+        let results = probe_backends(addrs, timeout).await;
+
+        let mut down = down.write().unwrap();
+        let mut now_down = HashSet::new();
+        for (addr, result) in results {
+            match &result {
+                Ok(()) if down.contains(&addr) => warn!("[server] backend {} is up again", addr),
+                Err(e) if !down.contains(&addr) => warn!("[server] backend {} is down: {}", addr, e),
+                _ => {}
+            }
+            if result.is_err() {
+                now_down.insert(addr);
+            }
+        }
+        // Backends removed from the configuration are forgotten
+        *down = now_down;
+    }
+}
+
+// Connect to each backend once, giving whether it accepted the connection
+async fn probe_backends(
+    addrs: HashSet<BackendAddr>,
+    timeout: Duration,
+) -> Vec<(BackendAddr, Result<(), String>)> {
+    let checks = addrs.into_iter().map(|addr| async move {
+        let result = match tokio::time::timeout(timeout, addr.connect()).await {
+            Ok(Ok(_)) => Ok(()),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err(format!("no answer within {} ms", timeout.as_millis())),
+        };
+        (addr, result)
+    });
+    future::join_all(checks).await
+}
+
+// Answer the health probes, which report draining once shutdown is announced
+fn serve_health(
+    port: u16,
+    options: &Arc<Opt>,
+    conf: &SharedConf,
+    connections: &ConnectionTable,
+    down_backends: &DownBackends,
+    mut shutdown: broadcast::Receiver<()>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let draining = Arc::new(AtomicBool::new(false));
+    let flag = draining.clone();
+    tokio::spawn(async move {
+        if shutdown.recv().await.is_ok() {
+            flag.store(true, Ordering::Relaxed);
+        }
+    });
+
+    let connections = connections.clone();
+    let (options, conf, down) = (options.clone(), conf.clone(), down_backends.clone());
+    health::serve(port, HealthState {
+        draining,
+        connections: Box::new(move || connections.lock().unwrap().len()),
+        degraded_threshold: options.health_degraded_threshold,
+        backends: Box::new(move || {
+            let conf = conf.read().unwrap().clone();
+            let down = down.read().unwrap();
+            conf.backend_health(&options, &down)
+        }),
+    })
+}
+
+// Periodically drop the rate limiter buckets of idle source IPs
+async fn prune_rate_limiter(limiter: Arc<ConnectionRateLimiter>) {
+    let mut ticker = tokio::time::interval(RATE_LIMIT_IDLE);
+    loop {
+        ticker.tick().await;
+        limiter.prune(RATE_LIMIT_IDLE);
+    }
+}
+
+// Log QUIC path statistics of a connection every interval until it closes
+async fn log_quic_stats(connection: quinn::Connection, interval: Duration) {
+    let id = connection.stable_id();
+    let mut ticker = tokio::time::interval(interval);
+    // The first tick completes immediately, skip it
+    ticker.tick().await;
+
+    loop {
+        tokio::select! {
+            _ = connection.closed() => break,
+            _ = ticker.tick() => {}
+        }
+
+        let path = connection.stats().path;
+        // Share of sent packets that were declared lost
+        let loss_pct = if path.sent_packets > 0 {
+            path.lost_packets as f64 * 100.0 / path.sent_packets as f64
+        } else {
+            0.0
+        };
+        // Quinn does not expose the path MTU, the datagram size is derived from it
+        let max_datagram = connection
+            .max_datagram_size()
+            .map_or("n/a".to_string(), |size| size.to_string());
+        debug!(
+            "[server] quic stats id {}: rtt={:?} cwnd={} sent_packets={} lost_packets={} lost_bytes={} loss={:.2}% max_datagram={}",
+            id,
+            path.rtt,
+            path.cwnd,
+            path.sent_packets,
+            path.lost_packets,
+            path.lost_bytes,
+            loss_pct,
+            max_datagram
+        );
+    }
+}
+
+// Accept the stream of a connection, traced as a child of the connection span
+async fn accept_stream(
+    connection: &quinn::Connection,
+) -> Result<(quinn::SendStream, quinn::RecvStream), quinn::ConnectionError> {
+    connection
+        .accept_bi()
+        .instrument(tracing::trace_span!("quicssh.stream_accept"))
+        .await
+}
+
+// Echo all data received on a stream back to the client
+async fn echo_stream(
+    (mut send, mut recv): (quinn::SendStream, quinn::RecvStream),
+    counters: Arc<TrafficCounters>,
+    buf_size: usize,
+) {
+    let mut buf = vec![0; buf_size];
+    loop {
+        match recv.read(&mut buf).await {
+            // Client finished sending, finish our side too
+            Ok(None) => {
+                let _ = send.finish().await;
+                break;
+            }
+            Ok(Some(n)) => {
+                counters.add_in(n);
+                if let Err(e) = send.write_all(&buf[..n]).await {
+                    error!("[server] echo write error: {}", e);
+                    break;
+                }
+                counters.add_out(n);
+            }
+            Err(e) => {
+                debug!("[server] echo read finished: {}", e);
+                break;
+            }
+        }
+    }
+    debug!("[server] echo stream {} finished", send.id());
+}
+
+// Read the start of a stream until it is a whole header magic or cannot
+// begin one, so streams without a header, like SSH, are never held up
+async fn read_magic(recv: &mut quinn::RecvStream) -> Result<Vec<u8>, quinn::ReadError> {
+    let mut magic = Vec::with_capacity(4);
+    let mut buf = [0u8; 4];
+    while [FORWARD_MAGIC, CONNECT_MAGIC, TRACE_MAGIC]
+        .iter()
+        .any(|header| header.len() > magic.len() && header.starts_with(&magic))
+    {
+        match recv.read(&mut buf[magic.len()..]).await? {
+            Some(n) => magic.extend_from_slice(&buf[magic.len()..magic.len() + n]),
+            None => break,
+        }
+    }
+    Ok(magic)
+}
+
+// Run HANDLER of CONN in its own task so a panic only drops this connection,
+// closing it with HANDLER_PANIC_CODE; returns the error the connection failed with
+async fn supervise_handler(
+    handler: impl Future<Output = Result<(), Box<dyn Error + Send + Sync>>> + Send + 'static,
+    conn: &quinn::Connection,
+) -> Option<Box<dyn Error + Send + Sync>> {
+    match tokio::spawn(handler).await {
+        Ok(result) => result.err(),
+        Err(e) if e.is_panic() => {
+            let total = metrics::handler_panicked();
+            error!(
+                "[server] connection handler panicked, connection dropped (panics total: {})",
+                total
+            );
+            conn.close(HANDLER_PANIC_CODE.into(), b"internal error");
+            Some("connection handler panicked".into())
+        }
+        Err(_) => None,
+    }
+}
+
+// Handle a single QUIC connection by proxying data to/from SSH server
+//
+// SNI selects the route and BACKENDS are tried in order; COUNTERS receive
+// the bytes relayed, FAILOVERS the failovers per SNI name, and TOTAL_THROTTLE
+// is shared by all connections when total bandwidth is limited. Errors that
+// end the connection early are logged, then returned.
+#[allow(clippy::too_many_arguments)]
+pub async fn handle_connection(
+    options: Arc<Opt>,
+    sni: String,
+    backends: Vec<BackendAddr>,
+    listen_addr: SocketAddr,
+    connection: quinn::Connection,
+    counters: Arc<TrafficCounters>,
+    failovers: FailoverCounts,
+    total_throttle: Option<Arc<SharedThrottle>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // Each accepted stream holds a permit until its handler finishes
+    let streams = Arc::new(Semaphore::new(options.max_pending_streams as usize));
+    let permit = match streams.clone().acquire_owned().await {
+        Ok(permit) => permit,
+        Err(_) => return Ok(()),
+    };
+    debug!(
+        "[server] stream permit acquired, {} of {} left",
+        streams.available_permits(),
+        options.max_pending_streams
+    );
+
+    // Echo mode never reaches a backend
+    if options.debug_echo {
+        let stream = match connection.accept_bi().await {
+            Ok(stream) => stream,
+            Err(e) => {
+                error!("[server] open quic stream error: {}", e);
+                return Err(format!("open quic stream error: {}", e).into());
+            }
+        };
+        let buf_size = options.buf_size as usize;
+        let first = echo_stream(stream, counters.clone(), buf_size);
+        run_sessions(&connection, streams, permit, first, |stream| {
+            echo_stream(stream, counters.clone(), buf_size)
+        })
+        .await;
+        info!("[server] exit echo client");
+        return Ok(());
+    }
+
+    // The start of the stream is inspected before picking a backend: it may
+    // carry a forwarding request, or a trace header that is skipped when
+    // tracing is off so clients exporting traces still reach SSH
+    let (quinn_send, mut quinn_recv) = match accept_stream(&connection).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            error!("[server] open quic stream error: {}", e);
+            return Err(format!("open quic stream error: {}", e).into());
+        }
+    };
+
+    let mut magic = match read_magic(&mut quinn_recv).await {
+        Ok(magic) => magic,
+        Err(e) => {
+            error!("[server] reading from quic client error: {}", e);
+            return Err(format!("reading from quic client error: {}", e).into());
+        }
+    };
+
+    // Join the client trace, the rest of the stream follows the header
+    if magic == TRACE_MAGIC {
+        match TraceHeader::read(&mut quinn_recv).await {
+            Ok(header) if options.otel_endpoint.is_some() => {
+                otel::set_parent(&tracing::Span::current(), &header.traceparent)
+            }
+            Ok(_) => debug!("[server] tracing disabled, ignoring trace header"),
+            Err(e) => {
+                error!("[server] reading trace header error: {}", e);
+                return Err(format!("reading trace header error: {}", e).into());
+            }
+        }
+        magic = match read_magic(&mut quinn_recv).await {
+            Ok(magic) => magic,
+            Err(e) => {
+                error!("[server] reading from quic client error: {}", e);
+                return Err(format!("reading from quic client error: {}", e).into());
+            }
+        };
+    }
+
+    if magic == FORWARD_MAGIC && options.allow_forward {
+        forward_stream(&options, listen_addr, &connection, quinn_send, quinn_recv).await;
+        return Ok(());
+    }
+    if magic == CONNECT_MAGIC && options.allow_tcp_forward {
+        serve_tcp_forwards(&options, &connection, quinn_send, quinn_recv).await;
+        return Ok(());
+    }
+    // Bytes read while checking for headers, replayed to SSH
+    let prefix = magic;
+
+    // Establish connection to SSH server, failing over to the next backend
+    // when one does not connect within --failover-timeout-ms
+    let (ssh_stream, proxy_for) = connect_backends(&options, &sni, &backends, &failovers).await;
+    let ssh_conn = match ssh_stream {
+        Ok(conn) => conn,
+        Err(e) => {
+            let (code, reason) = backend_failure(&options, &sni, backends.len(), proxy_for, &e);
+            connection.close(code.into(), reason);
+            return Err(format!("connect to ssh {} error: {}", proxy_for, e).into());
+        }
+    };
+
+    info!("[server] ssh connection established");
+
+    // Further streams are the other SSH sessions of a --multiplex client
+    let first = relay_ssh(
+        options.clone(),
+        ssh_conn,
+        proxy_for.clone(),
+        (quinn_send, quinn_recv),
+        prefix,
+        counters.clone(),
+        total_throttle.clone(),
+        SessionFailure::CloseConnection(connection.clone()),
+    );
+    run_sessions(&connection, streams, permit, first, |stream| {
+        info!("[server] new multiplexed session from {}", connection.remote_address());
+        serve_session(
+            options.clone(),
+            sni.clone(),
+            backends.clone(),
+            failovers.clone(),
+            stream,
+            counters.clone(),
+            total_throttle.clone(),
+        )
+    })
+    .await;
+
+    // The connection record is logged once the connection is finished
+    debug!("[server] exit client");
+    Ok(())
+}
+
+// Connect to the first backend that answers, failing over to the next one
+// when a backend does not connect within --failover-timeout-ms
+//
+// Also returns the backend of the last attempt, which is the one used on success.
+async fn connect_backends<'a>(
+    options: &Opt,
+    sni: &str,
+    backends: &'a [BackendAddr],
+    failovers: &FailoverCounts,
+) -> (std::io::Result<Box<dyn BackendStream>>, &'a BackendAddr) {
+    let failover_timeout = Duration::from_millis(options.failover_timeout_ms);
+    let mut ssh_stream = Err(std::io::Error::other("no backend"));
+    let mut proxy_for = &backends[0];
+    for (i, addr) in backends.iter().enumerate() {
+        proxy_for = addr;
+        if i > 0 {
+            let entry = failovers.entry(sni.to_string()).or_default();
+            let total = entry.fetch_add(1, Ordering::Relaxed) + 1;
+            metrics::failover(sni);
+            warn!("[server] failing over to ssh {} for {} (failovers: {})", addr, sni, total);
+        }
+        // Each attempt is traced as a child of the connection span
+        let connect = connect_backend(options, addr)
+            .instrument(tracing::trace_span!("quicssh.ssh_connect", backend = %addr));
+        if i + 1 == backends.len() {
+            ssh_stream = connect.await;
+            break;
+        }
+        ssh_stream = match tokio::time::timeout(failover_timeout, connect).await {
+            Ok(result) => result,
+            Err(_) => Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "failover timeout")),
+        };
+        match &ssh_stream {
+            Ok(_) => break,
+            Err(e) => {
+                metrics::backend_error(&addr.to_string());
+                warn!("[server] connect to ssh {} error: {}", addr, e);
+            }
+        }
+    }
+    if ssh_stream.is_err() {
+        metrics::backend_error(&proxy_for.to_string());
+    }
+    (ssh_stream, proxy_for)
+}
+
+// Log a failed backend connection and pick the error code and reason sent to
+// the client
+fn backend_failure(
+    options: &Opt,
+    sni: &str,
+    backend_count: usize,
+    proxy_for: &BackendAddr,
+    e: &std::io::Error,
+) -> (u32, &'static [u8]) {
+    if backend_count > 1 {
+        error!("[server] no backend available for {}: {}", sni, e);
+        (options.backend_error_code, b"no backend available")
+    } else if e.kind() == std::io::ErrorKind::TimedOut {
+        warn!("[server] connect to ssh {} timed out", proxy_for);
+        (BACKEND_CONNECT_TIMEOUT_CODE, b"backend connect timeout")
+    } else {
+        error!("[server] connect to ssh error: {}", e);
+        (options.backend_error_code, b"backend connect failed")
+    }
+}
+
+// How a session reports a backend failure to the client
+//
+// The first session of a connection closes the connection with the error
+// code, which clients with a single session expect; the further sessions of a
+// --multiplex client only reset their stream, so the other sessions go on.
+#[derive(Clone)]
+enum SessionFailure {
+    CloseConnection(quinn::Connection),
+    ResetStream,
+}
+
+impl SessionFailure {
+    // Fail the session from the task sending to the client
+    fn fail_send(&self, send: &mut quinn::SendStream, code: u32, reason: &[u8]) {
+        match self {
+            SessionFailure::CloseConnection(connection) => connection.close(code.into(), reason),
+            SessionFailure::ResetStream => {
+                let _ = send.reset(code.into());
+            }
+        }
+    }
+
+    // Fail the session from the task receiving from the client
+    fn fail_recv(&self, recv: &mut quinn::RecvStream, code: u32, reason: &[u8]) {
+        match self {
+            SessionFailure::CloseConnection(connection) => connection.close(code.into(), reason),
+            SessionFailure::ResetStream => {
+                let _ = recv.stop(code.into());
+            }
+        }
+    }
+}
 
-        // Extract SNI (Server Name Indication) from TLS handshake
-        let sni = conn
-            .handshake_data()
-            .unwrap()
-            .downcast::<crypto::rustls::HandshakeData>()
-            .unwrap()
-            .server_name
-            .unwrap_or(conn.remote_address().ip().to_string());  // Fall back to IP if no SNI
-        
-        // Determine which SSH server to proxy to based on SNI
-        let proxy_to = conf.proxy.get(&sni).unwrap_or(&default_proxy).clone();
-        
-        // Log connection details
-        info!(
-            "[server] connection accepted: ({}, {}) -> {}",
-            conn.remote_address(),
-            sni,
-            proxy_to
-        );
-        
-        // Spawn async task to handle this connection
-        tokio::spawn(async move {
-            handle_connection(proxy_to, conn).await;
-        });
-        
-        // Connection handling continues in background
-        // Server loop continues to accept new connections
+// Run FIRST, and a session made by NEW_SESSION for each further stream the
+// client opens, until the connection closes
+//
+// The connection lasts as long as it carries a session, it is closed once the
+// last one ended. Each session holds a permit of STREAMS until it finishes,
+// FIRST_PERMIT being the one of FIRST.
+async fn run_sessions<S>(
+    connection: &quinn::Connection,
+    streams: Arc<Semaphore>,
+    first_permit: OwnedSemaphorePermit,
+    first: impl Future<Output = ()> + Send + 'static,
+    mut new_session: impl FnMut((quinn::SendStream, quinn::RecvStream)) -> S,
+) where
+    S: Future<Output = ()> + Send + 'static,
+{
+    let mut sessions = tokio::task::JoinSet::new();
+    sessions.spawn(
+        async move {
+            first.await;
+            drop(first_permit);
+        }
+        .in_current_span(),
+    );
+    loop {
+        tokio::select! {
+            accepted = accept_stream(connection) => {
+                let stream = match accepted {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        debug!("[server] no more streams: {}", e);
+                        break;
+                    }
+                };
+                let Ok(permit) = streams.clone().acquire_owned().await else {
+                    break;
+                };
+                let session = new_session(stream);
+                sessions.spawn(
+                    async move {
+                        session.await;
+                        drop(permit);
+                    }
+                    .in_current_span(),
+                );
+            }
+            Some(joined) = sessions.join_next() => {
+                resume_panic(joined);
+                if sessions.is_empty() {
+                    break;
+                }
+            }
+        }
+    }
+    while let Some(joined) = sessions.join_next().await {
+        resume_panic(joined);
     }
 }
 
-// Handle a single QUIC connection by proxying data to/from SSH server
-async fn handle_connection(proxy_for: SocketAddr, connection: quinn::Connection) {
-    // Establish TCP connection to SSH server
-    let ssh_stream = TcpStream::connect(proxy_for).await;
+// Proxy a stream opened after the first one to a backend of its own
+//
+// These streams carry plain SSH sessions, without forwarding or trace headers.
+async fn serve_session(
+    options: Arc<Opt>,
+    sni: String,
+    backends: Vec<BackendAddr>,
+    failovers: FailoverCounts,
+    (mut send, recv): (quinn::SendStream, quinn::RecvStream),
+    counters: Arc<TrafficCounters>,
+    total_throttle: Option<Arc<SharedThrottle>>,
+) {
+    let (ssh_stream, proxy_for) = connect_backends(&options, &sni, &backends, &failovers).await;
     let ssh_conn = match ssh_stream {
         Ok(conn) => conn,
         Err(e) => {
-            error!("[server] connect to ssh error: {}", e);
-            return;  // Exit if SSH connection fails
+            let (code, _) = backend_failure(&options, &sni, backends.len(), proxy_for, &e);
+            let _ = send.reset(code.into());
+            return;
         }
     };
+    relay_ssh(
+        options.clone(),
+        ssh_conn,
+        proxy_for.clone(),
+        (send, recv),
+        Vec::new(),
+        counters,
+        total_throttle,
+        SessionFailure::ResetStream,
+    )
+    .await;
+}
 
-    info!("[server] ssh connection established");
-
-    // Accept bidirectional stream from QUIC client
-    let (mut quinn_send, mut quinn_recv) = match connection.accept_bi().await {
-        Ok(stream) => stream,
-        Err(e) => {
-            error!("[server] open quic stream error: {}", e);
-            return;  // Exit if QUIC stream fails
+// Let a session that panicked take the connection handler down with it, as
+// the connection task reports handler panics
+fn resume_panic(joined: Result<(), tokio::task::JoinError>) {
+    if let Err(e) = joined {
+        if e.is_panic() {
+            std::panic::resume_unwind(e.into_panic());
         }
-    };
+    }
+}
 
+// Relay one SSH session between a QUIC stream and its backend connection
+//
+// PREFIX holds bytes already read from the stream, sent to the backend first.
+#[allow(clippy::too_many_arguments)]
+async fn relay_ssh(
+    options: Arc<Opt>,
+    ssh_conn: Box<dyn BackendStream>,
+    proxy_for: BackendAddr,
+    (mut quinn_send, mut quinn_recv): (quinn::SendStream, quinn::RecvStream),
+    prefix: Vec<u8>,
+    counters: Arc<TrafficCounters>,
+    total_throttle: Option<Arc<SharedThrottle>>,
+    failure: SessionFailure,
+) {
     // Split SSH connection into read and write halves
-    let (mut ssh_recv, mut ssh_write) = tokio::io::split(ssh_conn);
+    let (ssh_recv, mut ssh_write) = tokio::io::split(ssh_conn);
+
+    // Each direction has its own bandwidth limit
+    let throttle = || options.max_bw_per_conn_mbps.map(Throttle::from_mbps);
+    let mut ssh_recv = ThrottledReader::new(ssh_recv, throttle());
+    let mut in_throttle = throttle();
+    let out_total_throttle = total_throttle.clone();
+
+    // Replay the bytes consumed while looking for a forwarding header
+    if !prefix.is_empty() {
+        if let Err(e) = ssh_write.write_all(&prefix).await {
+            error!("[server] writing to ssh server error: {}", e);
+            failure.fail_send(&mut quinn_send, options.backend_error_code, b"backend error");
+            return;
+        }
+    }
+
+    // Check the backend host key before any server data reaches the client
+    let mut verifier = options
+        .ssh_host_key_fingerprint
+        .clone()
+        .map(HostKeyVerifier::new);
+
+    // Connection handles used to report backend errors from the proxy tasks
+    let backend_error_code = options.backend_error_code;
+    let buf_size = options.buf_size as usize;
+    let out_failure = failure.clone();
+    let in_failure = failure;
 
     // Task to read from SSH server and send to QUIC client
+    let out_counters = counters.clone();
     let recv_thread = async move {
-        // Buffer for SSH server data (2KB)
-        let mut buf = [0; 2048];
+        // Buffer for SSH server data
+        let mut buf = vec![0; buf_size];
         
         // Continuous loop to proxy data from SSH to QUIC
         loop {
             match ssh_recv.read(&mut buf).await {
                 Ok(n) => {
-                    // Skip empty reads
+                    // SSH server closed the connection, signal EOF to the client
                     if n == 0 {
-                        continue;
+                        debug!("[server] ssh server closed the connection");
+                        if let Err(e) = quinn_send.finish().await {
+                            error!("[server] finishing quic stream error: {}", e);
+                        }
+                        return;
                     }
                     debug!("[server] recv data from ssh server {} bytes", n);
+
+                    // Hold back the key exchange reply until the host key has been verified
+                    let mut data = &buf[..n];
+                    let relayable;
+                    if let Some(v) = verifier.as_mut() {
+                        let verified = match v.feed(data) {
+                            HostKeyStatus::Pending => false,
+                            HostKeyStatus::Verified => {
+                                debug!("[server] ssh {} host key verified", proxy_for);
+                                true
+                            }
+                            HostKeyStatus::Mismatch(actual) => {
+                                warn!(
+                                    "[server] ssh {} host key mismatch: got {}",
+                                    proxy_for, actual
+                                );
+                                let reason = b"host key mismatch";
+                                out_failure.fail_send(&mut quinn_send, HOST_KEY_MISMATCH_CODE, reason);
+                                return;
+                            }
+                            HostKeyStatus::Invalid(reason) => {
+                                warn!(
+                                    "[server] ssh {} host key not verified: {}",
+                                    proxy_for, reason
+                                );
+                                let reason = b"host key mismatch";
+                                out_failure.fail_send(&mut quinn_send, HOST_KEY_MISMATCH_CODE, reason);
+                                return;
+                            }
+                        };
+                        relayable = v.take_relayable();
+                        data = &relayable;
+                        if verified {
+                            verifier = None;
+                        }
+                        if data.is_empty() {
+                            continue;
+                        }
+                    }
                     
                     // Forward data to QUIC client
-                    match quinn_send.write_all(&buf[..n]).await {
-                        Ok(_) => (),
+                    if let Some(total) = &out_total_throttle {
+                        total.acquire(data.len()).await;
+                    }
+                    match quinn_send.write_all(data).await {
+                        Ok(_) => out_counters.add_out(data.len()),
                         Err(e) => {
                             error!("[server] writing to quic stream error: {}", e);
                             return;  // Exit thread on write error
@@ -232,6 +2488,8 @@ async fn handle_connection(proxy_for: SocketAddr, connection: quinn::Connection)
                 }
                 Err(e) => {
                     error!("[server] reading from ssh server error: {}", e);
+                    metrics::backend_error(&proxy_for.to_string());
+                    out_failure.fail_send(&mut quinn_send, backend_error_code, b"backend error");
                     return;  // Exit thread on read error
                 }
             }
@@ -239,19 +2497,28 @@ async fn handle_connection(proxy_for: SocketAddr, connection: quinn::Connection)
     };
 
     // Task to read from QUIC client and send to SSH server
+    //
+    // A chunk is only taken from the stream once the previous one was written
+    // to SSH, and Quinn only extends the client's flow control window as
+    // chunks are consumed. A slow SSH server therefore stalls the client
+    // instead of growing a buffer here, which is bounded by the receive window.
     let write_thread = async move {
-        // Buffer for QUIC client data (2KB)
-        let mut buf = [0; 2048];
-        
         // Continuous loop to proxy data from QUIC to SSH
         loop {
-            match quinn_recv.read(&mut buf).await {
-                // No data available, continue waiting
+            match quinn_recv.read_chunk(buf_size, true).await {
+                // Client finished its side, half-close the SSH connection and
+                // keep relaying the SSH server output until it closes too
                 Ok(None) => {
-                    continue;
+                    debug!("[server] quic client finished the stream");
+                    if let Err(e) = ssh_write.shutdown().await {
+                        error!("[server] shutting down ssh write side error: {}", e);
+                        return;
+                    }
+                    std::future::pending::<()>().await;
                 }
                 // Data received successfully
-                Ok(Some(n)) => {
+                Ok(Some(chunk)) => {
+                    let n = chunk.bytes.len();
                     debug!("[server] recv data from quic stream {} bytes", n);
                     
                     // Skip empty reads
@@ -259,11 +2526,20 @@ async fn handle_connection(proxy_for: SocketAddr, connection: quinn::Connection)
                         continue;
                     }
                     
-                    // Forward data to SSH server
-                    match ssh_write.write_all(&buf[..n]).await {
-                        Ok(_) => (),
+                    // Forward data to SSH server, waiting for room in the TCP send buffer
+                    if let Some(total) = &total_throttle {
+                        total.acquire(n).await;
+                    }
+                    match ssh_write.write_all(&chunk.bytes).await {
+                        Ok(_) => {
+                            counters.add_in(n);
+                            if let Some(throttle) = in_throttle.as_mut() {
+                                throttle.wait(n).await;
+                            }
+                        }
                         Err(e) => {
                             error!("[server] writing to ssh server error: {}", e);
+                            in_failure.fail_recv(&mut quinn_recv, backend_error_code, b"backend error");
                             return;  // Exit thread on write error
                         }
                     }
@@ -282,9 +2558,683 @@ async fn handle_connection(proxy_for: SocketAddr, connection: quinn::Connection)
         _ = recv_thread => (),   // Exit if SSH->QUIC thread terminates
         _ = write_thread => (),  // Exit if QUIC->SSH thread terminates
     }
+}
+
+
+// Connect every stream of a port forwarding client to the TCP target named in
+// its header, until the connection closes
+//
+// The magic bytes of the first stream were already consumed by the caller.
+async fn serve_tcp_forwards(
+    options: &Opt,
+    connection: &quinn::Connection,
+    send: quinn::SendStream,
+    recv: quinn::RecvStream,
+) {
+    let timeout = Duration::from_millis(options.tcp_connect_timeout_ms);
+    tokio::spawn(tcp_forward_stream(connection.remote_address(), send, recv, timeout));
+
+    loop {
+        let (send, mut recv) = match connection.accept_bi().await {
+            Ok(stream) => stream,
+            Err(e) => {
+                debug!("[server] tcp forward connection finished: {}", e);
+                break;
+            }
+        };
+        let remote = connection.remote_address();
+        tokio::spawn(async move {
+            let mut magic = [0u8; 4];
+            match recv.read_exact(&mut magic).await {
+                Ok(()) if &magic == CONNECT_MAGIC => {
+                    tcp_forward_stream(remote, send, recv, timeout).await
+                }
+                Ok(()) => warn!("[server] tcp forward stream from {} without connect header", remote),
+                Err(e) => debug!("[server] reading tcp forward header error: {}", e),
+            }
+        });
+    }
+    info!("[server] exit tcp forward client");
+}
+
+// Connect one forwarded stream to its TCP target and relay it
+async fn tcp_forward_stream(
+    remote: SocketAddr,
+    mut send: quinn::SendStream,
+    mut recv: quinn::RecvStream,
+    timeout: Duration,
+) {
+    let header = match ConnectHeader::read(&mut recv).await {
+        Ok(header) => header,
+        Err(e) => {
+            warn!("[server] invalid tcp forward header from {}: {}", remote, e);
+            let _ = send.reset(FORWARD_REJECTED_CODE.into());
+            return;
+        }
+    };
+
+    // Host names are resolved here, on the server side of the tunnel
+    let target = (header.host.as_str(), header.port);
+    let tcp = match tokio::time::timeout(timeout, TcpStream::connect(target)).await {
+        Ok(Ok(tcp)) => tcp,
+        Ok(Err(e)) => {
+            warn!("[server] tcp forward {} -> {} failed: {}", remote, header, e);
+            let _ = send.reset(FORWARD_REJECTED_CODE.into());
+            return;
+        }
+        Err(_) => {
+            warn!("[server] tcp forward {} -> {} timed out", remote, header);
+            let _ = send.reset(FORWARD_REJECTED_CODE.into());
+            return;
+        }
+    };
+
+    info!("[server] tcp forward {} -> {}", remote, header);
+    match relay_tcp(tcp, send, recv).await {
+        Ok((up, down)) => debug!(
+            "[server] tcp forward {} -> {} finished, {} bytes in, {} bytes out",
+            remote, header, down, up
+        ),
+        Err(e) => debug!("[server] tcp forward {} -> {} error: {}", remote, header, e),
+    }
+}
+
+// Connect to the SSH backend, retrying transient failures with exponential backoff
+// Only refused and timed out connections are retried, other errors fail immediately
+async fn connect_backend(options: &Opt, addr: &BackendAddr) -> std::io::Result<Box<dyn BackendStream>> {
+    let timeout = Duration::from_millis(options.tcp_connect_timeout_ms);
+    let mut attempt = 0;
+    loop {
+        // Bound each attempt so blackholed routes do not hang for the OS default
+        let result = match tokio::time::timeout(timeout, addr.connect()).await {
+            Ok(result) => result,
+            Err(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "backend connect timeout",
+            )),
+        };
+
+        match result {
+            Ok(stream) => return Ok(stream),
+            Err(e)
+                if attempt < options.backend_connect_retry
+                    && matches!(
+                        e.kind(),
+                        std::io::ErrorKind::ConnectionRefused | std::io::ErrorKind::TimedOut
+                    ) =>
+            {
+                // Double the delay after every failed attempt
+                let factor = 1u64.checked_shl(attempt).unwrap_or(u64::MAX);
+                let delay = options.backend_connect_retry_delay_ms.saturating_mul(factor);
+                attempt += 1;
+                warn!(
+                    "[server] connect to ssh {} error: {}, retry {}/{} in {} ms",
+                    addr, e, attempt, options.backend_connect_retry, delay
+                );
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Forward a stream to the next quicssh-rs server named in its forwarding header
+async fn forward_stream(
+    options: &Opt,
+    listen_addr: SocketAddr,
+    connection: &quinn::Connection,
+    mut quinn_send: quinn::SendStream,
+    mut quinn_recv: quinn::RecvStream,
+) {
+    // Parse the rest of the header following the magic bytes
+    let header = match ForwardHeader::read(&mut quinn_recv).await {
+        Ok(header) => header,
+        Err(e) => {
+            error!("[server] invalid forward header: {}", e);
+            connection.close(FORWARD_REJECTED_CODE.into(), b"invalid forward header");
+            return;
+        }
+    };
+
+    // Refuse streams that have exhausted their hop budget
+    if header.ttl == 0 || header.hops.is_empty() {
+        warn!("[server] forward ttl exceeded, remaining hops: {:?}", header.hops);
+        connection.close(FORWARD_REJECTED_CODE.into(), b"forward ttl exceeded");
+        return;
+    }
+
+    // Record this server in the list of traversed servers
+    let mut via = header.via;
+    let local_ip = connection.local_ip().unwrap_or(listen_addr.ip());
+    via.push(SocketAddr::new(local_ip, listen_addr.port()).to_string());
+
+    // A chain longer than allowed most likely loops back on itself
+    if via.len() > options.max_proxy_hops {
+        warn!(
+            "[server] proxy loop detected after {} hops: {}",
+            via.len(),
+            via.join(" -> ")
+        );
+        connection.close(PROXY_LOOP_CODE.into(), b"proxy loop detected");
+        return;
+    }
+    debug!("[server] forwarded stream via: {}", via.join(" -> "));
+
+    // Connect to the next hop as a regular QUIC client
+    let mut hops = header.hops;
+    let next = hops.remove(0);
+    let next_conn = match connect_hop(&next, &options.transport).await {
+        Ok(conn) => conn,
+        Err(e) => {
+            error!("[server] connect to next hop {} error: {}", next, e);
+            return;
+        }
+    };
+
+    let (mut next_send, mut next_recv) = match next_conn.open_bi().await {
+        Ok(stream) => stream,
+        Err(e) => {
+            error!("[server] open stream to next hop {} error: {}", next, e);
+            return;
+        }
+    };
+
+    // Pass the remaining hops on to the next server
+    if !hops.is_empty() {
+        let header = ForwardHeader {
+            ttl: header.ttl - 1,
+            hops,
+            via,
+        };
+        let header = match header.encode() {
+            Ok(header) => header,
+            Err(e) => {
+                error!("[server] forward header to {} error: {}", next, e);
+                connection.close(FORWARD_REJECTED_CODE.into(), b"invalid forward header");
+                return;
+            }
+        };
+        if let Err(e) = next_send.write_all(&header).await {
+            error!("[server] writing forward header to {} error: {}", next, e);
+            return;
+        }
+    }
+
+    info!(
+        "[server] forwarding {} -> {}",
+        connection.remote_address(),
+        next_conn.remote_address()
+    );
+
+    // Relay bytes in both directions until either side finishes
+    tokio::select! {
+        r = tokio::io::copy(&mut quinn_recv, &mut next_send) => {
+            debug!("[server] forward to next hop finished: {:?}", r);
+        }
+        r = tokio::io::copy(&mut next_recv, &mut quinn_send) => {
+            debug!("[server] forward from next hop finished: {:?}", r);
+        }
+    }
+
+    next_conn.close(0u32.into(), b"forward finished");
+    info!("[server] exit forwarded client");
+}
+
+// Establish a client connection to the next server of a proxy chain
+async fn connect_hop(
+    url: &url::Url,
+    transport: &TransportOpt,
+) -> Result<quinn::Connection, Box<dyn Error + Send + Sync>> {
+    let (remote, sni) = client::resolve_url(url)?;
+    let client_cfg = client::configure_client(transport, &ClientTlsOpt::default())?;
+    let endpoint =
+        client::make_client_endpoint(client::default_bind_addr(&remote), transport, client_cfg.clone())?;
+    client::connect(&endpoint, &client_cfg, &[], remote, &sni).await
+}
+
+// Read connection ID patterns from the drain file, one per line
+// Blank lines and lines starting with '#' are ignored
+async fn read_drain_patterns(path: &Path) -> std::io::Result<Vec<String>> {
+    let content = read_to_string(path).await?;
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
+// Check whether a connection ID matches a drain pattern
+// A trailing '*' matches any ID starting with the given prefix
+fn drain_pattern_matches(pattern: &str, id: usize) -> bool {
+    let id = id.to_string();
+    match pattern.strip_suffix('*') {
+        Some(prefix) => id.starts_with(prefix),
+        None => id == pattern,
+    }
+}
+
+// Re-read the drain file whenever its modification time changes
+async fn watch_drain_file(path: PathBuf, patterns: Arc<RwLock<Vec<String>>>) {
+    let mut last_modified: Option<SystemTime> = None;
+    let mut interval = tokio::time::interval(DRAIN_FILE_POLL_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        // Skip reloading if the file has not changed since the last read
+        let modified = match tokio::fs::metadata(&path).await.and_then(|m| m.modified()) {
+            Ok(modified) => modified,
+            Err(e) => {
+                debug!("[server] stat drain file {} error: {}", path.display(), e);
+                continue;
+            }
+        };
+        if last_modified == Some(modified) {
+            continue;
+        }
+
+        match read_drain_patterns(&path).await {
+            Ok(new_patterns) => {
+                info!(
+                    "[server] loaded {} drain patterns from {}",
+                    new_patterns.len(),
+                    path.display()
+                );
+                *patterns.write().unwrap() = new_patterns;
+                last_modified = Some(modified);
+            }
+            Err(e) => {
+                error!("[server] read drain file {} error: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+// Request a graceful shutdown when SIGTERM is received
+#[cfg(not(windows))]
+async fn shutdown_on_signal(shutdown: broadcast::Sender<()>, enabled: bool) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    // SIGTERM is left to drain_on_signal; the sender is kept, dropping it
+    // would close the channel and stop the accept loop
+    if !enabled {
+        return std::future::pending().await;
+    }
+    let mut stream = match signal(SignalKind::terminate()) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("[server] create signal stream error: {}", e);
+            return std::future::pending().await;
+        }
+    };
+
+    stream.recv().await;
+    info!("[server] got signal TERM, shutting down");
+    let _ = shutdown.send(());
+}
+
+// Request a graceful shutdown on Ctrl-C, Windows has no SIGTERM
+#[cfg(windows)]
+async fn shutdown_on_signal(shutdown: broadcast::Sender<()>, _enabled: bool) {
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        error!("[server] create signal stream error: {}", e);
+        return std::future::pending().await;
+    }
+    info!("[server] got signal Ctrl-C, shutting down");
+    let _ = shutdown.send(());
+}
+
+// Reload the configuration file and the TLS configuration each time SIGHUP
+// or SIGUSR1 is received
+// Only new connections use them, existing ones keep their routing and TLS session
+#[cfg(not(windows))]
+async fn reload_on_signal(
+    endpoints: Vec<Endpoint>,
+    options: Arc<Opt>,
+    conf: SharedConf,
+    access_log: Option<Arc<AccessLog>>,
+) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let (mut hangup, mut user1) = match (
+        signal(SignalKind::hangup()),
+        signal(SignalKind::user_defined1()),
+    ) {
+        (Ok(hangup), Ok(user1)) => (hangup, user1),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("[server] create signal stream error: {}", e);
+            return;
+        }
+    };
+
+    loop {
+        tokio::select! {
+            _ = hangup.recv() => info!("[server] got signal HUP, reloading"),
+            _ = user1.recv() => info!("[server] got signal USR1, reloading"),
+        }
+
+        // Follow log rotation
+        if let Some(access_log) = &access_log {
+            access_log.reopen();
+        }
+
+        // Keep the previous configuration if the file cannot be loaded
+        if let Some(path) = &options.conf_path {
+            match ServerConf::load(&options).await {
+                Ok(new_conf) => {
+                    log_route_changes(&conf.read().unwrap().proxy, &new_conf.proxy);
+                    *conf.write().unwrap() = Arc::new(new_conf);
+                    info!("[server] configuration reloaded from {}", path.display());
+                }
+                Err(e) => error!("[server] configuration reload failed: {}", e),
+            }
+        }
+        let sni_certs = conf.read().unwrap().certs.clone();
+
+        // A new self-signed certificate would break clients that pinned the current one
+        if options.tls.cert.is_none() && sni_certs.is_empty() {
+            debug!("[server] no --cert or [certs] configured, keeping the self-signed certificate");
+            continue;
+        }
+
+        match build_server_config(&options, &sni_certs) {
+            Ok((server_config, _)) => {
+                for endpoint in &endpoints {
+                    endpoint.set_server_config(Some(server_config.clone()));
+                }
+                info!("[server] certificate reloaded");
+            }
+            Err(e) => error!("[server] certificate reload failed: {}", e),
+        }
+    }
+}
+
+// Signals are not available on Windows, files are only loaded at startup
+#[cfg(windows)]
+async fn reload_on_signal(
+    _endpoints: Vec<Endpoint>,
+    _options: Arc<Opt>,
+    _conf: SharedConf,
+    _access_log: Option<Arc<AccessLog>>,
+) {
+}
+
+// Close connections matching the drain patterns each time SIGTERM is received
+// New connections keep being accepted so traffic can migrate gradually
+#[cfg(not(windows))]
+async fn drain_on_signal(patterns: Arc<RwLock<Vec<String>>>, connections: ConnectionTable) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut stream = match signal(SignalKind::terminate()) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("[server] create signal stream error: {}", e);
+            return;
+        }
+    };
+
+    while stream.recv().await.is_some() {
+        let patterns = patterns.read().unwrap().clone();
+        let connections = connections.lock().unwrap();
+        info!("[server] got signal TERM, draining matching connections");
+
+        for (id, conn) in connections.iter() {
+            if patterns.iter().any(|p| drain_pattern_matches(p, *id)) {
+                info!("[server] draining connection id {}", id);
+                conn.close(0u32.into(), b"drained");
+            }
+        }
+    }
+}
+
+// SIGTERM is not available on Windows, so selective draining is unsupported
+#[cfg(windows)]
+async fn drain_on_signal(_patterns: Arc<RwLock<Vec<String>>>, _connections: ConnectionTable) {
+    error!("[server] --drain-connections-file is not supported on Windows");
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Parse the server command line ARGS, with the [server] section of CONF
+    // applied as config_file_args does
+    fn parse_with_conf(args: &[&str], conf: &str) -> Result<Opt, Box<dyn Error + Send + Sync>> {
+        let path = std::env::temp_dir().join(format!("quicssh-server-test-{}.toml", std::process::id()));
+        std::fs::write(&path, conf)?;
+        let mut args: Vec<OsString> = args.iter().map(OsString::from).collect();
+        args.extend([OsString::from("--conf"), path.clone().into()]);
+        let extra = config_file_args(&Opt::command().try_get_matches_from(&args)?);
+        std::fs::remove_file(&path)?;
+        args.extend(extra?);
+        Ok(Opt::try_parse_from(args)?)
+    }
+
+    #[test]
+    fn metric_label_names_validated() {
+        for label in ["region=eu", "_a:b=", "dc_1=x=y"] {
+            assert!(Opt::try_parse_from(["server", "--metric-label", label]).is_ok(), "{} rejected", label);
+        }
+        for label in ["region", "1dc=x", "a-b=x", "=x", "environment=prod"] {
+            assert!(Opt::try_parse_from(["server", "--metric-label", label]).is_err(), "{} accepted", label);
+        }
+        let opt = Opt::try_parse_from(["server", "--metric-label", "dc_1=x=y"]).unwrap();
+        assert_eq!(opt.metric_label, [("dc_1".to_string(), "x=y".to_string())]);
+    }
+
+    #[test]
+    fn reconnect_limits_at_least_one() {
+        for flag in ["--rate-limit-reconnect-max", "--rate-limit-reconnect-window-secs"] {
+            assert!(Opt::try_parse_from(["server", flag, "0"]).is_err(), "{} 0 accepted", flag);
+            assert!(Opt::try_parse_from(["server", flag, "1"]).is_ok(), "{} 1 rejected", flag);
+        }
+        let args = ["server", "-l", "127.0.0.1:4433", "-p", "127.0.0.1:22"];
+        assert!(parse_with_conf(&args, "[server]\nrate_limit_reconnect_max = 0\n").is_err());
+        assert!(parse_with_conf(&args, "[server]\nrate_limit_reconnect_window_secs = 0\n").is_err());
+        let options = parse_with_conf(&args, "[server]\nrate_limit_reconnect_max = 2\n").unwrap();
+        assert_eq!(options.rate_limit_reconnect_max, 2);
+    }
+
+    #[test]
+    fn reconnects_counted_per_ip() {
+        let reconnects: ReconnectTable = Arc::new(DashMap::new());
+        let window = Duration::from_secs(60);
+        let (a, b): (IpAddr, IpAddr) = ("192.0.2.1".parse().unwrap(), "192.0.2.2".parse().unwrap());
+        assert!(record_reconnect(&reconnects, a, window, 2));
+        assert!(record_reconnect(&reconnects, a, window, 2));
+        assert!(!record_reconnect(&reconnects, a, window, 2));
+        assert!(record_reconnect(&reconnects, b, window, 2));
+        // Attempts older than the window no longer count
+        assert!(record_reconnect(&reconnects, b, Duration::ZERO, 1));
+    }
+
+    #[test]
+    fn toml_and_yaml_routes_match() {
+        let toml = r#"
+            [proxy]
+            "single.example.com" = "127.0.0.1:22"
+            "pool.example.com" = ["10.0.0.1:22", "10.0.0.2:22", "10.0.0.3:22"]
+
+            [[proxy."failover.example.com".backends]]
+            addr = "10.0.1.2:22"
+            priority = 2
+
+            [[proxy."failover.example.com".backends]]
+            addr = "10.0.1.1:22"
+            priority = 1
+        "#;
+        // YAML nesting is indentation, the document starts at the first column
+        let yaml = "\
+proxy:
+  single.example.com: 127.0.0.1:22
+  pool.example.com: [10.0.0.1:22, 10.0.0.2:22, 10.0.0.3:22]
+  failover.example.com:
+    backends:
+      - addr: 10.0.1.2:22
+        priority: 2
+      - addr: 10.0.1.1:22
+        priority: 1
+";
+        let from_toml: ServerConf = parse_conf(Path::new("server.toml"), toml, ConfFormat::Auto).unwrap();
+        let from_yaml: ServerConf = parse_conf(Path::new("server.yml"), yaml, ConfFormat::Auto).unwrap();
+        assert_eq!(from_toml.proxy.len(), 3);
+        assert_eq!(from_toml.proxy, from_yaml.proxy);
+        // The same file with --conf-format yaml
+        let forced: ServerConf = parse_conf(Path::new("server.conf"), yaml, ConfFormat::Yaml).unwrap();
+        assert_eq!(forced.proxy, from_yaml.proxy);
+
+        // Errors name the parser the format selected
+        let e = parse_conf::<ServerConf>(Path::new("server.yaml"), toml, ConfFormat::Auto).unwrap_err();
+        assert!(e.to_string().starts_with("invalid YAML config file server.yaml"), "{}", e);
+        let e = parse_conf::<ServerConf>(Path::new("server.yaml"), yaml, ConfFormat::Toml).unwrap_err();
+        assert!(e.to_string().starts_with("invalid TOML config file server.yaml"), "{}", e);
+    }
+
+    // EXAMPLE with the commented-out settings enabled: comment lines that are
+    // valid TOML on their own, and table headers
+    fn uncomment(example: &str) -> String {
+        example
+            .lines()
+            .map(|line| match line.strip_prefix("# ") {
+                Some(code) if code.starts_with("[[") || toml::from_str::<toml::Table>(code).is_ok() => code,
+                _ => line,
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    // Error carrying the field names a struct asked FieldNames for
+    #[derive(Debug)]
+    struct Fields(Vec<&'static str>);
+
+    impl fmt::Display for Fields {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "fields {:?}", self.0)
+        }
+    }
+
+    impl std::error::Error for Fields {}
+
+    impl serde::de::Error for Fields {
+        fn custom<T: fmt::Display>(_: T) -> Self {
+            Fields(Vec::new())
+        }
+    }
+
+    // Deserializer failing with the field names of the struct it is asked for
+    struct FieldNames;
+
+    impl<'de> serde::Deserializer<'de> for FieldNames {
+        type Error = Fields;
+
+        fn deserialize_any<V: serde::de::Visitor<'de>>(self, _: V) -> Result<V::Value, Fields> {
+            Err(Fields(Vec::new()))
+        }
+
+        fn deserialize_struct<V: serde::de::Visitor<'de>>(
+            self,
+            _: &'static str,
+            fields: &'static [&'static str],
+            _: V,
+        ) -> Result<V::Value, Fields> {
+            Err(Fields(fields.to_vec()))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+            option unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    // Keys of the configuration struct T, as its Deserialize implementation sees them
+    fn field_names<T: DeserializeOwned>() -> Vec<&'static str> {
+        match T::deserialize(FieldNames) {
+            Ok(_) => panic!("not a struct"),
+            Err(Fields(names)) => names,
+        }
+    }
+
+    #[test]
+    fn example_config_round_trips() {
+        for example in [example_config(), uncomment(&example_config())] {
+            // generate -> parse -> serialize -> compare
+            let table: toml::Table = toml::from_str(&example).unwrap();
+            let serialized = toml::to_string(&table).unwrap();
+            assert_eq!(toml::from_str::<toml::Table>(&serialized).unwrap(), table);
+
+            // Both readers of the file take it, every [server] key included
+            let conf: ServerConf = parse_conf(Path::new("example.toml"), &example, ConfFormat::Auto).unwrap();
+            let again: ServerConf = parse_conf(Path::new("example.toml"), &serialized, ConfFormat::Auto).unwrap();
+            assert_eq!(conf.proxy, again.proxy);
+            let options: ServerOptConf = parse_conf(Path::new("example.toml"), &example, ConfFormat::Auto).unwrap();
+            let matches = Opt::command().get_matches_from(["server"]);
+            option_args(&Opt::command(), &matches, options.server, "[server] section", "conf").unwrap();
+        }
+
+        // Nothing is enabled by default, and every example setting is valid
+        let conf: ServerConf = parse_conf(Path::new("example.toml"), &example_config(), ConfFormat::Auto).unwrap();
+        assert!(conf.proxy.is_empty());
+        let conf: ServerConf =
+            parse_conf(Path::new("example.toml"), &uncomment(&example_config()), ConfFormat::Auto).unwrap();
+        let options: ServerOptConf =
+            parse_conf(Path::new("example.toml"), &uncomment(&example_config()), ConfFormat::Auto).unwrap();
+        assert!(options.server.contains_key("max-connections"));
+        let mut routes: Vec<&str> = conf.proxy.keys().map(String::as_str).collect();
+        routes.sort();
+        assert_eq!(routes, ["cluster", "db.example.com", "default", "hostname1"]);
+        assert!(conf.certs.contains_key("hostname1"));
+
+        // Every section and key of the configuration is documented
+        let example: toml::Table = toml::from_str(&uncomment(&example_config())).unwrap();
+        for section in field_names::<ServerConf>() {
+            let table = example.get(section).and_then(toml::Value::as_table);
+            assert!(table.is_some_and(|table| !table.is_empty()), "[{}] missing from the example", section);
+        }
+        for key in field_names::<AccessList>() {
+            assert!(example["access"].get(key).is_some(), "access {} missing from the example", key);
+        }
+    }
+
+    // Connection between a server endpoint and a client endpoint on loopback,
+    // seen from both sides
+    async fn connection_pair() -> (Endpoint, quinn::Connection, Endpoint, quinn::Connection) {
+        let transport = TransportOpt::default();
+        let (server_config, _) = configure_server(&transport, &ServerTlsOpt::default(), &SniCerts::new()).unwrap();
+        let server = Endpoint::server(server_config, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let client_cfg = client::configure_client(&transport, &ClientTlsOpt::default()).unwrap();
+        let client = client::make_client_endpoint("127.0.0.1:0".parse().unwrap(), &transport, client_cfg).unwrap();
+        let connecting = client.connect(server.local_addr().unwrap(), "localhost").unwrap();
+        let (client_conn, incoming) = tokio::join!(connecting, server.accept());
+        let server_conn = incoming.unwrap().await.unwrap();
+        (server, server_conn, client, client_conn.unwrap())
+    }
 
-    // Log connection termination
-    info!("[server] exit client");
+    #[tokio::test]
+    async fn handler_panic_drops_only_its_connection() {
+        let (_server, conn, _client, client_conn) = connection_pair().await;
+        // The panics are exported as quicssh_panics_total
+        let registry = metrics::registry(HashMap::new()).unwrap();
+        let exported_panics = || {
+            let body = String::from_utf8(metrics::render(&registry)).unwrap();
+            let line = body.lines().find(|line| line.starts_with("quicssh_panics_total ")).unwrap();
+            line["quicssh_panics_total ".len()..].parse::<u64>().unwrap()
+        };
+        let panics = exported_panics();
+        let failure = supervise_handler(async { panic!("injected handler panic") }, &conn).await;
+        assert_eq!(failure.unwrap().to_string(), "connection handler panicked");
+        assert_eq!(exported_panics(), panics + 1);
+        match client_conn.closed().await {
+            quinn::ConnectionError::ApplicationClosed(close) => {
+                assert_eq!(close.error_code, HANDLER_PANIC_CODE.into());
+            }
+            e => panic!("unexpected close: {}", e),
+        }
 
-    // Connection cleanup is handled automatically by tokio
+        // Handlers that return are reported as they are, the connection stays up
+        let (_server, conn, _client, _client_conn) = connection_pair().await;
+        assert!(supervise_handler(async { Ok(()) }, &conn).await.is_none());
+        let failure = supervise_handler(async { Err("no backend available".into()) }, &conn).await;
+        assert_eq!(failure.unwrap().to_string(), "no backend available");
+        assert!(conn.close_reason().is_none());
+    }
 }
@@ -0,0 +1,449 @@
+// Import Quinn socket abstraction types
+use quinn::udp::{RecvMeta, Transmit, UdpState};
+use quinn::AsyncUdpSocket;
+// Import standard library I/O, address and polling utilities
+use std::io::{self, IoSliceMut};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::task::{ready, Context, Poll};
+// Import tokio sockets for the control connection and the relayed datagrams
+use tokio::io::{AsyncReadExt, AsyncWriteExt, ReadBuf};
+use tokio::net::{TcpStream, UdpSocket};
+
+// Protocol constants from RFC 1928
+const VERSION: u8 = 5;
+const NO_AUTH: u8 = 0;
+const NO_ACCEPTABLE_METHODS: u8 = 0xff;
+const CMD_CONNECT: u8 = 1;
+const CMD_UDP_ASSOCIATE: u8 = 3;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+// Explain a SOCKS5 reply code
+fn reply_message(code: u8) -> &'static str {
+    match code {
+        1 => "general SOCKS server failure",
+        2 => "connection not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+fn protocol_error(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Append ATYP, address and port
+fn encode_addr(buf: &mut Vec<u8>, addr: SocketAddr) {
+    // Addresses of a dual-stack endpoint are IPv4-mapped, the proxy wants IPv4
+    match addr.ip().to_canonical() {
+        IpAddr::V4(ip) => {
+            buf.push(ATYP_IPV4);
+            buf.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            buf.push(ATYP_IPV6);
+            buf.extend_from_slice(&ip.octets());
+        }
+    }
+    buf.extend_from_slice(&addr.port().to_be_bytes());
+}
+
+// Parse ATYP, address and port, returning the address and the bytes consumed
+fn decode_addr(data: &[u8]) -> Option<(SocketAddr, usize)> {
+    let (ip, len): (IpAddr, usize) = match *data.first()? {
+        ATYP_IPV4 => (<[u8; 4]>::try_from(data.get(1..5)?).ok()?.into(), 4),
+        ATYP_IPV6 => (<[u8; 16]>::try_from(data.get(1..17)?).ok()?.into(), 16),
+        _ => return None,
+    };
+    let port = u16::from_be_bytes(data.get(1 + len..3 + len)?.try_into().ok()?);
+    Some((SocketAddr::new(ip, port), 3 + len))
+}
+
+// Append the SOCKS5 UDP request header naming ADDR: RSV, FRAG 0, then the address
+fn encode_udp_header(buf: &mut Vec<u8>, addr: SocketAddr) {
+    buf.extend_from_slice(&[0, 0, 0]);
+    encode_addr(buf, addr);
+}
+
+// Parse the UDP request header at the start of DATAGRAM, returning the
+// address it names and the header length; fragments (FRAG != 0) are not supported
+fn decode_udp_header(datagram: &[u8]) -> Option<(SocketAddr, usize)> {
+    if datagram.get(..3)? != [0, 0, 0] {
+        return None;
+    }
+    let (addr, len) = decode_addr(&datagram[3..])?;
+    Some((addr, 3 + len))
+}
+
+// SOCKS5 reply codes sent by the local proxy
+pub const REPLY_SUCCEEDED: u8 = 0;
+pub const REPLY_GENERAL_FAILURE: u8 = 1;
+const REPLY_COMMAND_NOT_SUPPORTED: u8 = 7;
+const REPLY_ADDRESS_NOT_SUPPORTED: u8 = 8;
+
+// Read a SOCKS5 CONNECT request from a client, returning the target host and port
+//
+// Only the no authentication method and the CONNECT command are accepted;
+// other commands, UDP ASSOCIATE included, are answered with an error. The
+// caller must answer a successful request with reply().
+pub async fn accept_connect(client: &mut TcpStream) -> io::Result<(String, u16)> {
+    // Greeting: VER NMETHODS METHODS
+    let mut greeting = [0u8; 2];
+    client.read_exact(&mut greeting).await?;
+    if greeting[0] != VERSION {
+        return Err(protocol_error(format!(
+            "unexpected SOCKS version {}",
+            greeting[0]
+        )));
+    }
+    let mut methods = vec![0u8; greeting[1] as usize];
+    client.read_exact(&mut methods).await?;
+    if !methods.contains(&NO_AUTH) {
+        client.write_all(&[VERSION, NO_ACCEPTABLE_METHODS]).await?;
+        return Err(protocol_error(
+            "SOCKS5 client does not offer no authentication".to_string(),
+        ));
+    }
+    client.write_all(&[VERSION, NO_AUTH]).await?;
+
+    // Request: VER CMD RSV ATYP DST.ADDR DST.PORT
+    let mut head = [0u8; 4];
+    client.read_exact(&mut head).await?;
+    if head[0] != VERSION {
+        return Err(protocol_error(format!(
+            "unexpected SOCKS version {}",
+            head[0]
+        )));
+    }
+    let host = match head[3] {
+        ATYP_IPV4 => {
+            let mut ip = [0u8; 4];
+            client.read_exact(&mut ip).await?;
+            Ipv4Addr::from(ip).to_string()
+        }
+        ATYP_IPV6 => {
+            let mut ip = [0u8; 16];
+            client.read_exact(&mut ip).await?;
+            Ipv6Addr::from(ip).to_string()
+        }
+        ATYP_DOMAIN => {
+            let len = client.read_u8().await?;
+            let mut name = vec![0u8; len as usize];
+            client.read_exact(&mut name).await?;
+            String::from_utf8(name)
+                .map_err(|_| protocol_error("invalid SOCKS5 host name".to_string()))?
+        }
+        atyp => {
+            reply(client, REPLY_ADDRESS_NOT_SUPPORTED).await?;
+            return Err(protocol_error(format!(
+                "unknown SOCKS5 address type {}",
+                atyp
+            )));
+        }
+    };
+    let port = client.read_u16().await?;
+
+    if head[1] != CMD_CONNECT {
+        reply(client, REPLY_COMMAND_NOT_SUPPORTED).await?;
+        let command = match head[1] {
+            CMD_UDP_ASSOCIATE => "UDP ASSOCIATE".to_string(),
+            cmd => format!("command {}", cmd),
+        };
+        return Err(protocol_error(format!(
+            "SOCKS5 {} is not supported",
+            command
+        )));
+    }
+    Ok((host, port))
+}
+
+// Answer a SOCKS5 request; the bound address is not meaningful through the tunnel
+pub async fn reply(client: &mut TcpStream, code: u8) -> io::Result<()> {
+    let mut reply = vec![VERSION, code, 0];
+    encode_addr(&mut reply, SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0));
+    client.write_all(&reply).await
+}
+
+// Ask the proxy to relay UDP datagrams (UDP ASSOCIATE)
+//
+// Returns the control connection, which must stay open for the association to
+// last, and the address datagrams are relayed through.
+async fn udp_associate(proxy: SocketAddr) -> io::Result<(TcpStream, SocketAddr)> {
+    let mut control = TcpStream::connect(proxy).await?;
+    control.write_all(&[VERSION, 1, NO_AUTH]).await?;
+    let mut method = [0u8; 2];
+    control.read_exact(&mut method).await?;
+    if method != [VERSION, NO_AUTH] {
+        return Err(protocol_error(
+            "SOCKS5 proxy requires an authentication method that is not supported".to_string(),
+        ));
+    }
+
+    // The address seen by the proxy is unknown behind NAT, let it accept any
+    let mut request = vec![VERSION, CMD_UDP_ASSOCIATE, 0];
+    encode_addr(
+        &mut request,
+        SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+    );
+    control.write_all(&request).await?;
+
+    let mut head = [0u8; 4];
+    control.read_exact(&mut head).await?;
+    if head[0] != VERSION {
+        return Err(protocol_error(format!(
+            "unexpected SOCKS version {}",
+            head[0]
+        )));
+    }
+    if head[1] != 0 {
+        return Err(protocol_error(format!(
+            "SOCKS5 UDP ASSOCIATE failed: {}",
+            reply_message(head[1])
+        )));
+    }
+    let mut reply = vec![head[3]];
+    match head[3] {
+        ATYP_IPV4 => reply.resize(1 + 4 + 2, 0),
+        ATYP_IPV6 => reply.resize(1 + 16 + 2, 0),
+        ATYP_DOMAIN => {
+            return Err(protocol_error(
+                "SOCKS5 proxy answered with a relay host name, which is not supported".to_string(),
+            ))
+        }
+        atyp => {
+            return Err(protocol_error(format!(
+                "unknown SOCKS5 address type {}",
+                atyp
+            )))
+        }
+    }
+    control.read_exact(&mut reply[1..]).await?;
+    let (relay, _) =
+        decode_addr(&reply).ok_or_else(|| protocol_error("invalid relay address".to_string()))?;
+
+    // An unspecified relay address means the proxy address itself
+    let relay = match relay.ip().is_unspecified() {
+        true => SocketAddr::new(proxy.ip(), relay.port()),
+        false => relay,
+    };
+    Ok((control, relay))
+}
+
+// UDP socket sending every datagram through a SOCKS5 proxy
+//
+// Each datagram is prefixed with the SOCKS5 UDP request header naming its
+// real destination and sent to the relay; replies carry the same header
+// naming their source, which is what Quinn sees as the peer address.
+#[derive(Debug)]
+pub struct Socks5UdpSocket {
+    socket: UdpSocket,
+    relay: SocketAddr,
+    // Closing the control connection ends the association
+    _control: TcpStream,
+}
+
+impl Socks5UdpSocket {
+    // Associate a bound socket with the proxy
+    pub async fn new(proxy: SocketAddr, socket: std::net::UdpSocket) -> io::Result<Self> {
+        let (control, relay) = udp_associate(proxy).await?;
+        socket.set_nonblocking(true)?;
+        Ok(Socks5UdpSocket {
+            socket: UdpSocket::from_std(socket)?,
+            relay,
+            _control: control,
+        })
+    }
+
+    // Relay address of the association
+    pub fn relay(&self) -> SocketAddr {
+        self.relay
+    }
+}
+
+impl AsyncUdpSocket for Socks5UdpSocket {
+    fn poll_send(
+        &self,
+        _state: &UdpState,
+        cx: &mut Context,
+        transmits: &[Transmit],
+    ) -> Poll<Result<usize, io::Error>> {
+        let mut sent = 0;
+        for transmit in transmits {
+            // Segmentation offload is not relayed, every segment is a datagram of its own
+            let segment_size = transmit
+                .segment_size
+                .unwrap_or(transmit.contents.len())
+                .max(1);
+            for (i, segment) in transmit.contents.chunks(segment_size).enumerate() {
+                let mut datagram = Vec::with_capacity(22 + segment.len());
+                encode_udp_header(&mut datagram, transmit.destination);
+                datagram.extend_from_slice(segment);
+                match self.socket.poll_send_to(cx, &datagram, self.relay) {
+                    Poll::Ready(Ok(_)) => {}
+                    // The rest of a partly sent transmit is lost, QUIC recovers it
+                    Poll::Pending if sent > 0 || i > 0 => {
+                        return Poll::Ready(Ok(sent + (i > 0) as usize))
+                    }
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(Err(_)) if sent > 0 => return Poll::Ready(Ok(sent)),
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                }
+            }
+            sent += 1;
+        }
+        Poll::Ready(Ok(sent))
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            let mut buf = ReadBuf::new(&mut bufs[0]);
+            let from = ready!(self.socket.poll_recv_from(cx, &mut buf))?;
+            let len = buf.filled().len();
+
+            // Only the relay sends to this socket
+            if from != self.relay {
+                continue;
+            }
+            let (mut source, header_len) = match decode_udp_header(&bufs[0][..len]) {
+                Some(decoded) => decoded,
+                None => continue,
+            };
+
+            // Quinn expects IPv4 peers of an IPv6 socket to be IPv4-mapped
+            if let (IpAddr::V4(ip), true) = (source.ip(), self.socket.local_addr()?.is_ipv6()) {
+                source = SocketAddr::new(IpAddr::V6(ip.to_ipv6_mapped()), source.port());
+            }
+            bufs[0].copy_within(header_len..len, 0);
+            meta[0] = RecvMeta {
+                addr: source,
+                len: len - header_len,
+                stride: len - header_len,
+                ecn: None,
+                dst_ip: None,
+            };
+            return Poll::Ready(Ok(1));
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    fn may_fragment(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn udp_header_ipv4() {
+        let mut datagram = Vec::new();
+        encode_udp_header(&mut datagram, "192.0.2.1:4433".parse().unwrap());
+        assert_eq!(datagram, [0, 0, 0, ATYP_IPV4, 192, 0, 2, 1, 0x11, 0x51]);
+        datagram.extend_from_slice(b"payload");
+        assert_eq!(
+            decode_udp_header(&datagram),
+            Some(("192.0.2.1:4433".parse().unwrap(), 10))
+        );
+        assert_eq!(&datagram[10..], b"payload");
+    }
+
+    #[test]
+    fn udp_header_ipv6() {
+        let addr: SocketAddr = "[2001:db8::1]:443".parse().unwrap();
+        let mut datagram = Vec::new();
+        encode_udp_header(&mut datagram, addr);
+        assert_eq!(datagram.len(), 3 + 1 + 16 + 2);
+        assert_eq!(datagram[3], ATYP_IPV6);
+        assert_eq!(decode_udp_header(&datagram), Some((addr, 22)));
+    }
+
+    #[test]
+    fn udp_header_ipv4_mapped_sent_as_ipv4() {
+        let mut datagram = Vec::new();
+        encode_udp_header(&mut datagram, "[::ffff:192.0.2.1]:4433".parse().unwrap());
+        assert_eq!(
+            decode_udp_header(&datagram),
+            Some(("192.0.2.1:4433".parse().unwrap(), 10))
+        );
+    }
+
+    #[test]
+    fn udp_header_rejected() {
+        // Truncated address or port
+        assert_eq!(decode_udp_header(&[0, 0, 0, ATYP_IPV4, 192, 0, 2]), None);
+        assert_eq!(
+            decode_udp_header(&[0, 0, 0, ATYP_IPV4, 192, 0, 2, 1, 0x11]),
+            None
+        );
+        assert_eq!(decode_udp_header(&[0, 0, 0, ATYP_IPV6, 0x20, 0x01]), None);
+        assert_eq!(decode_udp_header(&[0, 0]), None);
+        // Fragment, host name and unknown address type
+        assert_eq!(
+            decode_udp_header(&[0, 0, 1, ATYP_IPV4, 192, 0, 2, 1, 0, 1]),
+            None
+        );
+        assert_eq!(
+            decode_udp_header(&[0, 0, 0, ATYP_DOMAIN, 1, b'a', 0, 1]),
+            None
+        );
+        assert_eq!(decode_udp_header(&[0, 0, 0, 9, 192, 0, 2, 1, 0, 1]), None);
+    }
+
+    // Proxy answering the UDP ASSOCIATE request with REPLY, then closing
+    async fn fake_proxy(reply: &'static [u8]) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut client, _) = listener.accept().await.unwrap();
+            let mut greeting = [0u8; 3];
+            client.read_exact(&mut greeting).await.unwrap();
+            client.write_all(&[VERSION, NO_AUTH]).await.unwrap();
+            let mut request = [0u8; 10];
+            client.read_exact(&mut request).await.unwrap();
+            assert_eq!(request[..4], [VERSION, CMD_UDP_ASSOCIATE, 0, ATYP_IPV4]);
+            client.write_all(reply).await.unwrap();
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn associate_relay_address() {
+        let proxy = fake_proxy(&[VERSION, 0, 0, ATYP_IPV4, 0, 0, 0, 0, 0x1f, 0x90]).await;
+        let (_control, relay) = udp_associate(proxy).await.unwrap();
+        // An unspecified relay address is the proxy's own
+        assert_eq!(relay, SocketAddr::new(proxy.ip(), 8080));
+    }
+
+    #[tokio::test]
+    async fn associate_truncated_reply() {
+        let proxy = fake_proxy(&[VERSION, 0, 0, ATYP_IPV6, 0x20, 0x01, 0x0d]).await;
+        let e = udp_associate(proxy).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn associate_failure_reply() {
+        let proxy = fake_proxy(&[VERSION, 2, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0]).await;
+        let e = udp_associate(proxy).await.unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "SOCKS5 UDP ASSOCIATE failed: connection not allowed by ruleset"
+        );
+    }
+}
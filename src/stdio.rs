@@ -0,0 +1,113 @@
+// Import nix file status flags
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+// Import standard library file, descriptor and polling utilities
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::mem::ManuallyDrop;
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+// Import tokio readiness polling of raw descriptors
+use tokio::io::unix::AsyncFd;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+// Standard input or output, switched to non-blocking mode
+//
+// The descriptor stays open when dropped, only its previous flags are restored,
+// as they are shared with the process that passed it (e.g. ssh).
+pub struct RawStdio {
+    fd: AsyncFd<StdFd>,
+    flags: OFlag,
+}
+
+// Descriptor inherited from the parent process, never closed by us
+struct StdFd(ManuallyDrop<File>);
+
+impl AsRawFd for StdFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl RawStdio {
+    // Standard input, reading as soon as data arrives
+    pub fn stdin() -> io::Result<Self> {
+        Self::new(0)
+    }
+
+    // Standard output, writing without a blocking thread in between
+    pub fn stdout() -> io::Result<Self> {
+        Self::new(1)
+    }
+
+    // Set O_NONBLOCK on FD and register it with the runtime
+    //
+    // Fails when FD cannot be polled, e.g. for a regular file; ProxyCommand
+    // descriptors are pipes or sockets.
+    fn new(fd: RawFd) -> io::Result<Self> {
+        let flags = OFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFL)?);
+        fcntl(fd, FcntlArg::F_SETFL(flags | OFlag::O_NONBLOCK))?;
+        // SAFETY: the descriptor is open for the lifetime of the process and
+        // ManuallyDrop keeps File from closing it
+        let file = StdFd(ManuallyDrop::new(unsafe { File::from_raw_fd(fd) }));
+        match AsyncFd::new(file) {
+            Ok(fd) => Ok(RawStdio { fd, flags }),
+            Err(e) => {
+                let _ = fcntl(fd, FcntlArg::F_SETFL(flags));
+                Err(e)
+            }
+        }
+    }
+}
+
+impl Drop for RawStdio {
+    fn drop(&mut self) {
+        let _ = fcntl(self.fd.as_raw_fd(), FcntlArg::F_SETFL(self.flags));
+    }
+}
+
+impl AsyncRead for RawStdio {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            let mut guard = ready!(self.fd.poll_read_ready(cx))?;
+            let unfilled = buf.initialize_unfilled();
+            match guard.try_io(|fd| (&*fd.get_ref().0).read(unfilled)) {
+                Ok(result) => {
+                    buf.advance(result?);
+                    return Poll::Ready(Ok(()));
+                }
+                // Readiness was stale, wait for the next notification
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+impl AsyncWrite for RawStdio {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            let mut guard = ready!(self.fd.poll_write_ready(cx))?;
+            match guard.try_io(|fd| (&*fd.get_ref().0).write(buf)) {
+                Ok(result) => return Poll::Ready(result),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    // Writes go straight to the descriptor, there is nothing to flush
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
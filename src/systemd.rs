@@ -0,0 +1,64 @@
+// Import socket2 to check the type of the passed sockets
+use socket2::{Socket, Type};
+// Import standard library error handling, networking and descriptor utilities
+use std::error::Error;
+use std::net::UdpSocket;
+use std::os::fd::FromRawFd;
+use std::os::unix::net::UnixDatagram;
+
+// First file descriptor passed by socket activation (SD_LISTEN_FDS_START)
+const LISTEN_FDS_START: i32 = 3;
+
+// UDP sockets passed by systemd socket activation, if any
+//
+// The sockets are only taken when LISTEN_PID names this process.
+pub fn listen_sockets() -> Result<Vec<UdpSocket>, Box<dyn Error + Send + Sync>> {
+    let for_us = std::env::var("LISTEN_PID").is_ok_and(|pid| pid == std::process::id().to_string());
+    let count = match std::env::var("LISTEN_FDS") {
+        Ok(count) if for_us => count
+            .parse::<i32>()
+            .map_err(|_| format!("invalid LISTEN_FDS: {}", count))?,
+        _ => 0,
+    };
+
+    let mut sockets = Vec::new();
+    for fd in LISTEN_FDS_START..LISTEN_FDS_START + count {
+        // SAFETY: systemd passes these descriptors to this process only
+        let socket = unsafe { Socket::from_raw_fd(fd) };
+        if socket.r#type()? != Type::DGRAM {
+            return Err(format!(
+                "socket {} passed by systemd is not a UDP socket, use ListenDatagram=",
+                fd
+            )
+            .into());
+        }
+        socket.set_cloexec(true)?;
+        sockets.push(socket.into());
+    }
+    Ok(sockets)
+}
+
+// Send a state change such as READY=1 to the service manager
+//
+// Does nothing when the service was not started with Type=notify.
+pub fn notify(state: &str) -> std::io::Result<()> {
+    let path = match std::env::var_os("NOTIFY_SOCKET") {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    let socket = UnixDatagram::unbound()?;
+    let path = path.to_string_lossy();
+    match path.strip_prefix('@') {
+        // Abstract socket names are Linux only
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        _ => {
+            socket.send_to(state.as_bytes(), path.as_ref())?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,445 @@
+// Import clap for the shared command line options
+use clap::{Args, ValueEnum};
+// Import rcgen to generate self-signed certificates
+use rcgen::{CertificateParams, SanType};
+// Import ring SHA-256 used for certificate fingerprints
+use ring::digest;
+// Import rustls client certificate verification types
+use rustls::server::{
+    AllowAnyAuthenticatedClient, ClientCertVerifier, ClientHello, ResolvesServerCert,
+};
+use rustls::{sign, RootCertStore};
+// Import serde for the [certs] section of the config file and certificate reports
+use serde::{Deserialize, Serialize};
+// Import X.509 subject alternative names
+use x509_parser::extensions::GeneralName;
+// Import standard library error handling and file utilities
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+// Certificate chain and matching private key
+pub type CertifiedKey = (Vec<rustls::Certificate>, rustls::PrivateKey);
+
+// TLS options of the client
+#[derive(Args, Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct ClientTlsOpt {
+    // PEM certificate chain presented to servers that require client authentication
+    #[clap(long = "client-cert", requires = "client_key")]
+    pub client_cert: Option<PathBuf>,
+    // PEM private key (PKCS#8, PKCS#1 RSA or SEC1) matching --client-cert
+    #[clap(long = "client-key", requires = "client_cert")]
+    pub client_key: Option<PathBuf>,
+    // Hex-encoded SHA-256 fingerprint the server certificate must match
+    #[clap(long = "server-fingerprint", value_parser = parse_cert_fingerprint)]
+    pub server_fingerprint: Option<[u8; 32]>,
+    // Append the TLS secrets of each connection to this file in the NSS key log
+    // format, so captured traffic can be decrypted (e.g. by Wireshark)
+    #[clap(long = "keylog", value_name = "PATH")]
+    pub keylog: Option<PathBuf>,
+    // Send data with 0-RTT when reconnecting to a server of an earlier connection,
+    // whose session tickets are kept in memory; the data may be replayed
+    #[clap(long = "enable-0rtt", conflicts_with = "quic_supported_versions")]
+    pub enable_0rtt: bool,
+}
+
+impl ClientTlsOpt {
+    // Load the client certificate chain and key when mTLS is configured
+    pub fn client_identity(&self) -> Result<Option<CertifiedKey>, Box<dyn Error + Send + Sync>> {
+        let (cert_path, key_path) = match (&self.client_cert, &self.client_key) {
+            (Some(cert_path), Some(key_path)) => (cert_path, key_path),
+            (None, None) => return Ok(None),
+            _ => return Err("--client-cert and --client-key must be given together".into()),
+        };
+
+        let chain = load_certs(cert_path)?;
+        let key = load_private_key(key_path)?;
+        Ok(Some((chain, key)))
+    }
+}
+
+// TLS options of the server
+#[derive(Args, Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct ServerTlsOpt {
+    // PEM certificate chain (or DER certificate) presented to clients instead of an ephemeral self-signed one
+    #[clap(long = "cert", env = "QUICSSH_CERT")]
+    pub cert: Option<PathBuf>,
+    // PEM or DER private key (PKCS#8, PKCS#1 RSA or SEC1) matching --cert
+    #[clap(long = "key", env = "QUICSSH_KEY")]
+    pub key: Option<PathBuf>,
+    // Encoding of the --cert and --key files
+    #[clap(long = "cert-format", value_enum, default_value = "auto")]
+    pub cert_format: CertFormat,
+    // Interval in hours between expiry checks of the --cert file
+    #[clap(long = "cert-check-interval-hours", default_value = "24", value_parser = clap::value_parser!(u64).range(1..))]
+    pub cert_check_interval_hours: u64,
+    // Subject Alternative Names (DNS names or IP addresses) of the generated
+    // self-signed certificate, used when --cert is not given
+    #[clap(
+        long = "cert-san",
+        value_name = "NAME",
+        default_values = ["localhost", "127.0.0.1", "::1"],
+        conflicts_with = "cert"
+    )]
+    pub cert_san: Vec<String>,
+    // PEM CA bundle; when given, clients must present a certificate signed by one of these CAs
+    #[clap(long = "client-ca")]
+    pub client_ca: Option<PathBuf>,
+    // Append the TLS secrets of each connection to this file in the NSS key log
+    // format, so captured traffic can be decrypted (e.g. by Wireshark)
+    #[clap(long = "keylog", value_name = "PATH")]
+    pub keylog: Option<PathBuf>,
+}
+
+// Key log appending TLS secrets to a file, one NSS key log line per secret
+//
+// Lines are `<label> <client random> <secret>` in hex, e.g. CLIENT_RANDOM for
+// TLS 1.2 or CLIENT_TRAFFIC_SECRET_0 for the TLS 1.3 secrets QUIC uses.
+pub struct KeyLogFile {
+    path: PathBuf,
+    file: Mutex<BufWriter<fs::File>>,
+}
+
+impl KeyLogFile {
+    // Open PATH for appending, creating it if needed
+    pub fn open(path: &Path) -> Result<Arc<Self>, Box<dyn Error + Send + Sync>> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("cannot open key log {}: {}", path.display(), e))?;
+        Ok(Arc::new(KeyLogFile {
+            path: path.to_path_buf(),
+            file: Mutex::new(BufWriter::new(file)),
+        }))
+    }
+}
+
+impl rustls::KeyLog for KeyLogFile {
+    fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+        let mut file = self.file.lock().unwrap();
+        // Flush each line so the file is usable while the connection is open
+        let result = writeln!(
+            file,
+            "{} {} {}",
+            label,
+            hex::encode(client_random),
+            hex::encode(secret)
+        )
+        .and_then(|_| file.flush());
+        if let Err(e) = result {
+            log::error!("writing key log {} error: {}", self.path.display(), e);
+        }
+    }
+}
+
+impl std::fmt::Debug for KeyLogFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyLogFile")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+// Encoding of certificate and private key files
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CertFormat {
+    // Base64 PEM blocks
+    Pem,
+    // A single raw DER certificate or key
+    Der,
+    // DER if the file starts with an ASN.1 SEQUENCE, PEM otherwise
+    #[default]
+    Auto,
+}
+
+impl CertFormat {
+    // Resolve auto-detection for the given file contents
+    fn detect(self, data: &[u8]) -> CertFormat {
+        match self {
+            // A DER certificate or key is a SEQUENCE with a long-form length
+            CertFormat::Auto if matches!(data, [0x30, 0x81..=0x84, ..]) => CertFormat::Der,
+            CertFormat::Auto => CertFormat::Pem,
+            format => format,
+        }
+    }
+}
+
+impl ServerTlsOpt {
+    // Load the operator supplied certificate chain and key, if any
+    pub fn server_identity(&self) -> Result<Option<CertifiedKey>, Box<dyn Error + Send + Sync>> {
+        let (cert_path, key_path) = match (&self.cert, &self.key) {
+            (Some(cert_path), Some(key_path)) => (cert_path, key_path),
+            (None, None) => return Ok(None),
+            (Some(_), None) => {
+                return Err("--cert requires --key with the matching private key".into())
+            }
+            (None, Some(_)) => {
+                return Err("--key requires --cert with the matching certificate".into())
+            }
+        };
+
+        let chain = load_certs_as(cert_path, self.cert_format)?;
+        let key = load_private_key_as(key_path, self.cert_format)?;
+        Ok(Some((chain, key)))
+    }
+}
+
+impl ServerTlsOpt {
+    // Build the verifier requiring client certificates when --client-ca is set
+    pub fn client_verifier(
+        &self,
+    ) -> Result<Option<Arc<dyn ClientCertVerifier>>, Box<dyn Error + Send + Sync>> {
+        let path = match &self.client_ca {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+
+        let mut roots = RootCertStore::empty();
+        for cert in load_certs(path)? {
+            roots
+                .add(&cert)
+                .map_err(|e| format!("invalid CA certificate in {}: {}", path.display(), e))?;
+        }
+        Ok(Some(AllowAnyAuthenticatedClient::new(roots).boxed()))
+    }
+}
+
+// Generate an ephemeral self-signed certificate for the given names
+pub fn self_signed_identity(
+    names: &[String],
+) -> Result<CertifiedKey, Box<dyn Error + Send + Sync>> {
+    let subject_alt_names = names
+        .iter()
+        .map(|name| match IpAddr::from_str(name) {
+            Ok(ip) => SanType::IpAddress(ip),
+            Err(_) => SanType::DnsName(name.clone()),
+        })
+        .collect();
+    let mut params = CertificateParams::default();
+    params.subject_alt_names = subject_alt_names;
+    let cert = rcgen::Certificate::from_params(params)?;
+
+    let cert_der = cert.serialize_der()?;
+    let key_der = cert.serialize_private_key_der();
+    Ok((
+        vec![rustls::Certificate(cert_der)],
+        rustls::PrivateKey(key_der),
+    ))
+}
+
+// Certificate and key files presented for one SNI name
+#[derive(Deserialize, Debug, Clone)]
+pub struct SniCert {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+// Certificate files indexed by SNI name, from the [certs] section of the config file
+pub type SniCerts = HashMap<String, SniCert>;
+
+// Certificate resolver picking the certificate by SNI
+// Clients sending an unlisted name or no name at all get the default certificate
+pub struct SniCertResolver {
+    by_name: HashMap<String, Arc<sign::CertifiedKey>>,
+    default: Arc<sign::CertifiedKey>,
+}
+
+impl SniCertResolver {
+    // Load the certificate of each SNI name
+    pub fn new(
+        certs: &SniCerts,
+        default: CertifiedKey,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut by_name = HashMap::new();
+        for (name, files) in certs {
+            let chain = load_certs_as(&files.cert, CertFormat::Auto)?;
+            let key = load_private_key_as(&files.key, CertFormat::Auto)?;
+            by_name.insert(
+                name.to_ascii_lowercase(),
+                certified_key(chain, &key, &files.key)?,
+            );
+        }
+
+        let (chain, key) = default;
+        let signing_key = sign::any_supported_type(&key)
+            .map_err(|e| format!("unsupported private key: {}", e))?;
+        Ok(SniCertResolver {
+            by_name,
+            default: Arc::new(sign::CertifiedKey::new(chain, signing_key)),
+        })
+    }
+}
+
+impl ResolvesServerCert for SniCertResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<sign::CertifiedKey>> {
+        let cert = client_hello
+            .server_name()
+            .and_then(|name| self.by_name.get(&name.to_ascii_lowercase()));
+        Some(cert.unwrap_or(&self.default).clone())
+    }
+}
+
+// Pair a certificate chain with its signing key
+fn certified_key(
+    chain: Vec<rustls::Certificate>,
+    key: &rustls::PrivateKey,
+    key_path: &Path,
+) -> Result<Arc<sign::CertifiedKey>, Box<dyn Error + Send + Sync>> {
+    let signing_key = sign::any_supported_type(key)
+        .map_err(|e| format!("unsupported private key in {}: {}", key_path.display(), e))?;
+    Ok(Arc::new(sign::CertifiedKey::new(chain, signing_key)))
+}
+
+// Read a whole certificate or key file
+fn read_file(path: &Path) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    fs::read(path).map_err(|e| format!("failed to open {}: {}", path.display(), e).into())
+}
+
+// Load all certificates of a PEM file
+pub fn load_certs(path: &Path) -> Result<Vec<rustls::Certificate>, Box<dyn Error + Send + Sync>> {
+    load_certs_as(path, CertFormat::Pem)
+}
+
+// Load the certificates of a PEM file or the single certificate of a DER file
+pub fn load_certs_as(
+    path: &Path,
+    format: CertFormat,
+) -> Result<Vec<rustls::Certificate>, Box<dyn Error + Send + Sync>> {
+    let data = read_file(path)?;
+    if format.detect(&data) == CertFormat::Der {
+        return Ok(vec![rustls::Certificate(data)]);
+    }
+
+    let certs = rustls_pemfile::certs(&mut data.as_slice())
+        .map(|cert| cert.map(|cert| rustls::Certificate(cert.to_vec())))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("failed to read certificates from {}: {}", path.display(), e))?;
+
+    if certs.is_empty() {
+        return Err(format!("no certificate found in {}", path.display()).into());
+    }
+    Ok(certs)
+}
+
+// Load the first private key of a PEM file
+pub fn load_private_key(path: &Path) -> Result<rustls::PrivateKey, Box<dyn Error + Send + Sync>> {
+    load_private_key_as(path, CertFormat::Pem)
+}
+
+// Load the first private key of a PEM file or the key of a DER file
+pub fn load_private_key_as(
+    path: &Path,
+    format: CertFormat,
+) -> Result<rustls::PrivateKey, Box<dyn Error + Send + Sync>> {
+    let data = read_file(path)?;
+    // rustls accepts PKCS#8, PKCS#1 RSA and SEC1 EC keys in DER form as is
+    if format.detect(&data) == CertFormat::Der {
+        return Ok(rustls::PrivateKey(data));
+    }
+
+    // Accepts PKCS#8, PKCS#1 RSA and SEC1 EC keys
+    match rustls_pemfile::private_key(&mut data.as_slice())
+        .map_err(|e| format!("failed to read private key from {}: {}", path.display(), e))?
+    {
+        Some(key) => Ok(rustls::PrivateKey(key.secret_der().to_vec())),
+        None => Err(format!("no private key found in {}", path.display()).into()),
+    }
+}
+
+// Parse a SHA-256 certificate fingerprint given as hex, optionally colon-separated
+pub fn parse_cert_fingerprint(s: &str) -> Result<[u8; 32], String> {
+    let bytes =
+        hex::decode(s.trim().replace(':', "")).map_err(|e| format!("invalid hex: {}", e))?;
+    bytes
+        .try_into()
+        .map_err(|bytes: Vec<u8>| format!("expected 32 bytes, got {}", bytes.len()))
+}
+
+// SHA-256 digest of a DER certificate
+pub fn cert_digest(cert: &rustls::Certificate) -> [u8; 32] {
+    let digest = digest::digest(&digest::SHA256, &cert.0);
+    digest.as_ref().try_into().unwrap()
+}
+
+// Expiry time (notAfter) of a DER certificate as a Unix timestamp
+pub fn cert_not_after(cert: &rustls::Certificate) -> Option<i64> {
+    let (_, cert) = x509_parser::parse_x509_certificate(&cert.0).ok()?;
+    Some(cert.validity().not_after.timestamp())
+}
+
+// Human-readable summary of a certificate
+#[derive(Serialize, Debug)]
+pub struct CertInfo {
+    pub subject: String,
+    pub issuer: String,
+    // Subject alternative names such as DNS:example.com or IP:192.0.2.1
+    pub sans: Vec<String>,
+    // Validity period, RFC 3339
+    pub not_before: String,
+    pub not_after: String,
+    // Hex-encoded SHA-256 digest, as taken by --server-fingerprint
+    pub fingerprint: String,
+}
+
+// Format an ASN.1 time as RFC 3339
+fn format_asn1_time(time: x509_parser::time::ASN1Time) -> String {
+    chrono::DateTime::from_timestamp(time.timestamp(), 0)
+        .map(|time| time.to_rfc3339())
+        .unwrap_or_else(|| time.to_string())
+}
+
+// Format a subject alternative name the way OpenSSL does
+fn format_general_name(name: &GeneralName) -> String {
+    match name {
+        GeneralName::DNSName(name) => format!("DNS:{}", name),
+        GeneralName::RFC822Name(email) => format!("email:{}", email),
+        GeneralName::URI(uri) => format!("URI:{}", uri),
+        GeneralName::IPAddress(bytes) => match <[u8; 4]>::try_from(*bytes) {
+            Ok(ip) => format!("IP:{}", IpAddr::from(ip)),
+            Err(_) => match <[u8; 16]>::try_from(*bytes) {
+                Ok(ip) => format!("IP:{}", IpAddr::from(ip)),
+                Err(_) => format!("IP:{}", hex::encode(bytes)),
+            },
+        },
+        name => name.to_string(),
+    }
+}
+
+// Describe a DER certificate
+pub fn cert_info(cert: &rustls::Certificate) -> Result<CertInfo, Box<dyn Error + Send + Sync>> {
+    let (_, parsed) = x509_parser::parse_x509_certificate(&cert.0)
+        .map_err(|e| format!("invalid certificate: {}", e))?;
+    let sans = match parsed.subject_alternative_name() {
+        Ok(Some(san)) => san
+            .value
+            .general_names
+            .iter()
+            .map(format_general_name)
+            .collect(),
+        Ok(None) => Vec::new(),
+        Err(e) => return Err(format!("invalid subject alternative names: {}", e).into()),
+    };
+    Ok(CertInfo {
+        subject: parsed.subject().to_string(),
+        issuer: parsed.issuer().to_string(),
+        sans,
+        not_before: format_asn1_time(parsed.validity().not_before),
+        not_after: format_asn1_time(parsed.validity().not_after),
+        fingerprint: hex::encode(cert_digest(cert)),
+    })
+}
+
+// Subject common name of a DER certificate, if it has one
+pub fn subject_common_name(cert: &rustls::Certificate) -> Option<String> {
+    let (_, cert) = x509_parser::parse_x509_certificate(&cert.0).ok()?;
+    let cn = cert.subject().iter_common_name().next()?;
+    cn.as_str().ok().map(String::from)
+}
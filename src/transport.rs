@@ -0,0 +1,282 @@
+// Import clap for the shared command line options
+use clap::{Args, ValueEnum};
+// Import Quinn congestion controllers
+use quinn::congestion::{BbrConfig, CubicConfig, NewRenoConfig};
+// Import Quinn QUIC configuration types
+use quinn::{
+    AsyncUdpSocket, ConfigError, Endpoint, EndpointConfig, IdleTimeout, MtuDiscoveryConfig,
+    Runtime, ServerConfig, TokioRuntime, TransportConfig, VarInt,
+};
+// Import standard library error handling, networking, synchronization and time utilities
+use std::error::Error;
+use std::io;
+use std::net::UdpSocket;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Import fault injection options and socket wrapper
+use crate::chaos::{ChaosOpt, ChaosUdpSocket};
+
+// QUIC transport options shared by the server and the client
+#[derive(Args, Debug, Clone)]
+#[non_exhaustive]
+pub struct TransportOpt {
+    // Largest UDP payload accepted, also the upper bound probed by MTU discovery
+    // Raise it (e.g. to 8952 for 9000-byte jumbo frames) on LANs with a larger MTU
+    #[clap(
+        long = "quic-max-udp-payload-size",
+        value_parser = clap::value_parser!(u16).range(1200..=65527)
+    )]
+    pub max_udp_payload_size: Option<u16>,
+    // Cap on the MTU discovered for a path, even if the path supports more
+    #[clap(
+        long = "quic-receive-mss-clamp",
+        value_parser = clap::value_parser!(u16).range(1200..=65527)
+    )]
+    pub receive_mss_clamp: Option<u16>,
+    // Advanced: consecutive probe timeouts (PTOs) before persistent congestion is
+    // declared and the congestion window collapses (RFC 9002 kPersistentCongestionThreshold,
+    // default 3). Raising it avoids needless window resets on high-latency satellite links;
+    // the default suits most deployments.
+    #[clap(
+        long = "quic-pto-exponent",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub pto_exponent: Option<u32>,
+    // Congestion control algorithm of the connections sent from this side
+    #[clap(long = "congestion-controller", value_enum, default_value = "cubic")]
+    pub congestion_controller: CongestionController,
+    // Interval between keep-alive packets, which also keep NAT bindings open;
+    // raise it on high-latency links, lower it on mobile networks dropping idle flows
+    #[clap(
+        long = "keep-alive-secs",
+        default_value_t = DEFAULT_KEEP_ALIVE_SECS,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub keep_alive_secs: u64,
+    // Time without any packet from the peer after which a connection is closed;
+    // the smaller of the values of both sides applies
+    #[clap(
+        long = "idle-timeout-secs",
+        default_value_t = DEFAULT_IDLE_TIMEOUT_SECS,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub idle_timeout_secs: u64,
+    // Bytes the peer may send ahead of what was read, per stream and per
+    // connection; throughput is capped at window / RTT, so high bandwidth-delay
+    // paths need window >= bandwidth_bps * rtt_seconds / 8 (e.g. 12.5 MB for
+    // 1 Gbit/s at 100 ms)
+    #[clap(long = "recv-window-bytes", value_name = "BYTES", value_parser = parse_window)]
+    pub recv_window_bytes: Option<VarInt>,
+    // UDP payload size used from the start of a connection, before MTU
+    // discovery; a value the path cannot carry stalls the connection
+    #[clap(long = "mtu-initial", value_name = "BYTES", value_parser = clap::value_parser!(u16).range(1200..=65527))]
+    pub mtu_initial: Option<u16>,
+    // Largest UDP payload size probed by MTU discovery
+    #[clap(long = "mtu-upper-bound", value_name = "BYTES", value_parser = clap::value_parser!(u16).range(1200..=65527))]
+    pub mtu_upper_bound: Option<u16>,
+    // Keep the initial UDP payload size (1200 bytes unless --mtu-initial is given)
+    // instead of probing for larger ones
+    #[clap(long = "disable-mtu-discovery", conflicts_with = "mtu_upper_bound")]
+    pub disable_mtu_discovery: bool,
+    // Fault injection options for testing
+    #[clap(flatten)]
+    pub chaos: ChaosOpt,
+}
+
+// Keep-alive interval when --keep-alive-secs is not given
+const DEFAULT_KEEP_ALIVE_SECS: u64 = 1;
+
+// Idle timeout when --idle-timeout-secs is not given
+const DEFAULT_IDLE_TIMEOUT_SECS: u64 = 60;
+
+// Defaults of the command line options, for connections made without them
+impl Default for TransportOpt {
+    fn default() -> Self {
+        TransportOpt {
+            max_udp_payload_size: None,
+            receive_mss_clamp: None,
+            pto_exponent: None,
+            congestion_controller: CongestionController::default(),
+            keep_alive_secs: DEFAULT_KEEP_ALIVE_SECS,
+            idle_timeout_secs: DEFAULT_IDLE_TIMEOUT_SECS,
+            recv_window_bytes: None,
+            mtu_initial: None,
+            mtu_upper_bound: None,
+            disable_mtu_discovery: false,
+            chaos: ChaosOpt::default(),
+        }
+    }
+}
+
+// Parse a flow control window, which QUIC encodes as a varint
+fn parse_window(s: &str) -> Result<VarInt, String> {
+    let bytes: u64 = s
+        .parse()
+        .map_err(|e| format!("invalid window size: {}", e))?;
+    VarInt::from_u64(bytes)
+        .map_err(|_| format!("window size must be below {}", VarInt::MAX.into_inner() + 1))
+}
+
+// Congestion control algorithms provided by Quinn
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CongestionController {
+    // Model based (experimental in Quinn): paces to the measured bandwidth and
+    // RTT instead of backing off on every loss, which keeps throughput up on
+    // long or lossy paths such as satellite links, but it can take more than
+    // its share from loss-based flows on a shared bottleneck
+    Bbr,
+    // Loss based, the Quinn and Linux TCP default: grows quickly back to the
+    // window where the last loss happened and shares links fairly with TCP
+    #[default]
+    Cubic,
+    // Loss based (RFC 9002): the simplest and most conservative, slow to use
+    // the bandwidth of high bandwidth-delay product paths
+    Newreno,
+}
+
+impl TransportOpt {
+    // Build the endpoint-wide configuration
+    pub fn endpoint_config(&self) -> Result<EndpointConfig, ConfigError> {
+        let mut endpoint_config = EndpointConfig::default();
+        self.apply_endpoint(&mut endpoint_config)?;
+        Ok(endpoint_config)
+    }
+
+    // Apply the options to an existing endpoint configuration
+    pub fn apply_endpoint(&self, endpoint_config: &mut EndpointConfig) -> Result<(), ConfigError> {
+        if let Some(size) = self.max_udp_payload_size {
+            endpoint_config.max_udp_payload_size(size)?;
+        }
+        Ok(())
+    }
+
+    // Create an endpoint on a bound socket, wrapped for fault injection if enabled
+    pub fn make_endpoint(
+        &self,
+        endpoint_config: EndpointConfig,
+        server_config: Option<ServerConfig>,
+        socket: UdpSocket,
+    ) -> io::Result<Endpoint> {
+        let runtime = Arc::new(TokioRuntime);
+        if !self.chaos.enabled() {
+            return Endpoint::new(endpoint_config, server_config, socket, runtime);
+        }
+        let socket = ChaosUdpSocket::new(runtime.wrap_udp_socket(socket)?, &self.chaos)?;
+        Endpoint::new_with_abstract_socket(endpoint_config, server_config, socket, runtime)
+    }
+
+    // Create an endpoint on a custom socket, wrapped for fault injection if enabled
+    pub fn make_abstract_endpoint(
+        &self,
+        endpoint_config: EndpointConfig,
+        server_config: Option<ServerConfig>,
+        socket: impl AsyncUdpSocket,
+    ) -> io::Result<Endpoint> {
+        let runtime = Arc::new(TokioRuntime);
+        if !self.chaos.enabled() {
+            return Endpoint::new_with_abstract_socket(
+                endpoint_config,
+                server_config,
+                socket,
+                runtime,
+            );
+        }
+        let socket = ChaosUdpSocket::new(Box::new(socket), &self.chaos)?;
+        Endpoint::new_with_abstract_socket(endpoint_config, server_config, socket, runtime)
+    }
+
+    // MTU discovery settings bounded by the payload size, the clamp and the upper bound
+    pub fn mtu_discovery_config(&self) -> MtuDiscoveryConfig {
+        let mut mtu_config = MtuDiscoveryConfig::default();
+        let upper_bound = [
+            self.max_udp_payload_size,
+            self.receive_mss_clamp,
+            self.mtu_upper_bound,
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        if let Some(upper_bound) = upper_bound {
+            mtu_config.upper_bound(upper_bound);
+        }
+        mtu_config
+    }
+
+    // Apply the options to a connection transport configuration
+    pub fn apply(
+        &self,
+        transport_config: &mut TransportConfig,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // Without a keep-alive within the idle timeout, idle connections are closed
+        if self.keep_alive_secs >= self.idle_timeout_secs {
+            return Err(format!(
+                "--keep-alive-secs ({}) must be less than --idle-timeout-secs ({})",
+                self.keep_alive_secs, self.idle_timeout_secs
+            )
+            .into());
+        }
+        let idle_timeout = IdleTimeout::try_from(Duration::from_secs(self.idle_timeout_secs))
+            .map_err(|_| {
+                format!(
+                    "--idle-timeout-secs {} is too large",
+                    self.idle_timeout_secs
+                )
+            })?;
+        transport_config.max_idle_timeout(Some(idle_timeout));
+        transport_config.keep_alive_interval(Some(Duration::from_secs(self.keep_alive_secs)));
+        if let Some(window) = self.recv_window_bytes {
+            transport_config.stream_receive_window(window);
+            transport_config.receive_window(window);
+        }
+
+        if let Some(threshold) = self.pto_exponent {
+            transport_config.persistent_congestion_threshold(threshold);
+        }
+        match self.congestion_controller {
+            CongestionController::Bbr => {
+                transport_config.congestion_controller_factory(Arc::new(BbrConfig::default()))
+            }
+            CongestionController::Cubic => {
+                transport_config.congestion_controller_factory(Arc::new(CubicConfig::default()))
+            }
+            CongestionController::Newreno => {
+                transport_config.congestion_controller_factory(Arc::new(NewRenoConfig::default()))
+            }
+        };
+
+        if let Some(mtu) = self.mtu_initial {
+            transport_config.initial_mtu(mtu);
+        }
+
+        // MTU discovery is only enabled on platforms that support it
+        if self.disable_mtu_discovery {
+            transport_config.mtu_discovery_config(None);
+        } else {
+            #[cfg(any(windows, target_os = "linux"))]
+            transport_config.mtu_discovery_config(Some(self.mtu_discovery_config()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_fits_in_varint() {
+        assert_eq!(parse_window("4611686018427387903"), Ok(VarInt::MAX));
+        assert_eq!(parse_window("65536"), Ok(VarInt::from_u32(65536)));
+    }
+
+    #[test]
+    fn window_above_varint_rejected() {
+        assert_eq!(
+            parse_window("4611686018427387904"),
+            Err("window size must be below 4611686018427387904".to_string())
+        );
+        assert!(parse_window("-1").is_err());
+        assert!(parse_window("64k").is_err());
+    }
+}
@@ -0,0 +1,106 @@
+// Import nix process control and signal handling
+use nix::errno::Errno;
+use nix::sys::signal::{kill, SigSet, SigmaskHow, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::{fork, ForkResult, Pid};
+// Import standard library error handling and time utilities
+use std::error::Error;
+use std::time::Duration;
+
+// Import logging macros
+use log::{info, warn};
+
+// Delay before a worker that exited is started again
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+// Signals relayed by the supervisor to its workers
+const RELAYED_SIGNALS: [Signal; 3] = [Signal::SIGTERM, Signal::SIGINT, Signal::SIGHUP];
+
+// Fork COUNT worker processes and supervise them
+//
+// Must be called before any thread is started, so before the tokio runtime.
+// Returns true in each worker, which then runs the server, and false in the
+// supervisor once all workers exited after SIGTERM or SIGINT. Workers that
+// exit on their own are started again; SIGHUP is relayed to every worker.
+pub fn supervise(count: u32) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    // The supervisor keeps the signals it waits for blocked, so one arriving
+    // while workers are reaped stays pending instead of being missed
+    let waited = RELAYED_SIGNALS
+        .into_iter()
+        .chain([Signal::SIGCHLD])
+        .collect::<SigSet>();
+    let mask = waited.thread_swap_mask(SigmaskHow::SIG_BLOCK)?;
+
+    let mut workers = Vec::new();
+    for index in 0..count {
+        match spawn_worker(index, &mask)? {
+            Some(pid) => workers.push((pid, index)),
+            None => return Ok(true),
+        }
+    }
+    info!("[server] started {} workers", count);
+
+    let mut stopping = false;
+    while !workers.is_empty() {
+        // Reap the workers that exited since the last signal
+        let (pid, status) = match waitpid(None, Some(WaitPidFlag::WNOHANG)) {
+            Ok(WaitStatus::Exited(pid, code)) => (pid, format!("exit code {}", code)),
+            Ok(WaitStatus::Signaled(pid, signal, _)) => (pid, format!("signal {}", signal)),
+            Ok(WaitStatus::StillAlive) => {
+                // Relay the next signal, SIGCHLD meaning another worker exited
+                let signal = waited.wait()?;
+                if signal != Signal::SIGCHLD {
+                    stopping |= signal != Signal::SIGHUP;
+                    for (pid, _) in &workers {
+                        let _ = kill(*pid, signal);
+                    }
+                }
+                continue;
+            }
+            Ok(_) | Err(Errno::EINTR) => continue,
+            Err(e) => return Err(format!("waiting for workers error: {}", e).into()),
+        };
+
+        let Some(position) = workers.iter().position(|(worker, _)| *worker == pid) else {
+            continue;
+        };
+        let (_, index) = workers.swap_remove(position);
+        if stopping {
+            info!("[server] worker {} exited ({})", index, status);
+            continue;
+        }
+        warn!(
+            "[server] worker {} exited ({}), restarting it",
+            index, status
+        );
+        std::thread::sleep(RESTART_DELAY);
+        match spawn_worker(index, &mask)? {
+            Some(pid) => workers.push((pid, index)),
+            None => return Ok(true),
+        }
+    }
+    info!("[server] all workers exited");
+    mask.thread_set_mask()?;
+    Ok(false)
+}
+
+// Fork worker INDEX, returning its PID in the supervisor and None in the worker
+//
+// The worker starts with the signal MASK the supervisor had before blocking
+// the signals it waits for; one sent right after the fork stays pending until
+// then and gets the default handling, like before the server installs its own.
+fn spawn_worker(index: u32, mask: &SigSet) -> Result<Option<Pid>, Box<dyn Error + Send + Sync>> {
+    // SAFETY: the supervisor is single-threaded
+    match unsafe { fork() }? {
+        ForkResult::Parent { child } => Ok(Some(child)),
+        ForkResult::Child => {
+            mask.thread_set_mask()?;
+            info!(
+                "[server] worker {} running as pid {}",
+                index,
+                std::process::id()
+            );
+            Ok(None)
+        }
+    }
+}
@@ -0,0 +1,1346 @@
+// End-to-end tests of the tunnel: an in-process server relays to a TCP echo
+// backend, and in-process clients check that their bytes come back intact
+
+// Import clap to build the server options from arguments
+use clap::{Parser, ValueEnum};
+// Import the library under test
+use quicssh::backend::BackendAddr;
+use quicssh::client::{configure_client, connect_0rtt, make_socks5_endpoint, open_stream_0rtt};
+use quicssh::connlog::TrafficCounters;
+use quicssh::make_client_endpoint;
+use quicssh::server::{self, configure_server, handle_connection, make_server_endpoints};
+use quicssh::tls::{cert_digest, CertFormat, ClientTlsOpt, ServerTlsOpt, SniCerts};
+use quicssh::transport::TransportOpt;
+// Import quinn endpoint and stream types
+use quinn::{Connection, Endpoint, EndpointConfig, RecvStream, SendStream};
+// Import standard library error handling, networking and synchronization utilities
+use std::error::Error;
+use std::future::Future;
+use std::net::{SocketAddr, UdpSocket};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+// Import tokio networking, I/O and task utilities
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+// Time a test may take before it is considered hung
+const TEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Fail the test when FUTURE does not finish within TEST_TIMEOUT
+async fn with_timeout<T>(future: impl Future<Output = T>) -> T {
+    tokio::time::timeout(TEST_TIMEOUT, future)
+        .await
+        .expect("test timed out")
+}
+
+// TCP backend on a random port writing back everything it reads, and closing
+// its side once the client closed its own
+async fn echo_backend() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.into_split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+                let _ = writer.shutdown().await;
+            });
+        }
+    });
+    addr
+}
+
+// TCP backend on a random port that sends DATA to each connection and then
+// closes it, without reading anything
+async fn finite_backend(data: &'static [u8]) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let _ = stream.write_all(data).await;
+                let _ = stream.shutdown().await;
+            });
+        }
+    });
+    addr
+}
+
+// Minimal SOCKS5 proxy on a random port serving UDP ASSOCIATE (RFC 1928)
+//
+// Only no authentication and IPv4 addresses are handled. The first sender of
+// an association is its client, whose datagrams are sent on without their
+// request header; datagrams from anyone else go back to the client with a
+// header naming their source. Returns the proxy address and the count of
+// datagrams relayed in both directions.
+async fn socks5_relay() -> (SocketAddr, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let relayed = Arc::new(AtomicUsize::new(0));
+    let count = relayed.clone();
+    tokio::spawn(async move {
+        while let Ok((mut control, _)) = listener.accept().await {
+            let count = count.clone();
+            tokio::spawn(async move {
+                // Greeting: VER NMETHODS METHODS, answered with no authentication
+                let mut greeting = [0u8; 2];
+                control.read_exact(&mut greeting).await.unwrap();
+                let mut methods = vec![0u8; greeting[1] as usize];
+                control.read_exact(&mut methods).await.unwrap();
+                control.write_all(&[5, 0]).await.unwrap();
+
+                // Request: VER CMD RSV ATYP DST.ADDR DST.PORT, an IPv4 UDP ASSOCIATE
+                let mut request = [0u8; 10];
+                control.read_exact(&mut request).await.unwrap();
+                assert_eq!(request[..4], [5, 3, 0, 1]);
+                let udp = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+                let port = udp.local_addr().unwrap().port().to_be_bytes();
+                control
+                    .write_all(&[5, 0, 0, 1, 127, 0, 0, 1, port[0], port[1]])
+                    .await
+                    .unwrap();
+
+                // Relay until the control connection closes
+                let mut client = None;
+                let mut buf = vec![0u8; 65536];
+                let mut closed = [0u8; 1];
+                loop {
+                    let (len, from) = tokio::select! {
+                        received = udp.recv_from(&mut buf) => received.unwrap(),
+                        _ = control.read(&mut closed) => break,
+                    };
+                    let client = *client.get_or_insert(from);
+                    if from == client {
+                        let [0, 0, 0, 1, a, b, c, d, p0, p1, ..] = buf[..len] else {
+                            continue;
+                        };
+                        let target = SocketAddr::from(([a, b, c, d], u16::from_be_bytes([p0, p1])));
+                        udp.send_to(&buf[10..len], target).await.unwrap();
+                    } else {
+                        let SocketAddr::V4(source) = from else {
+                            continue;
+                        };
+                        let mut datagram = vec![0, 0, 0, 1];
+                        datagram.extend_from_slice(&source.ip().octets());
+                        datagram.extend_from_slice(&source.port().to_be_bytes());
+                        datagram.extend_from_slice(&buf[..len]);
+                        udp.send_to(&datagram, client).await.unwrap();
+                    }
+                    count.fetch_add(1, Ordering::Relaxed);
+                }
+            });
+        }
+    });
+    (addr, relayed)
+}
+
+// In-process server relaying to a single backend
+struct TestServer {
+    endpoint: Endpoint,
+    addr: SocketAddr,
+    accept_loop: JoinHandle<()>,
+}
+
+impl TestServer {
+    // Listen on LISTEN (port 0 for a random one) and relay to BACKEND
+    fn start(
+        listen: SocketAddr,
+        backend: SocketAddr,
+    ) -> Result<TestServer, Box<dyn Error + Send + Sync>> {
+        TestServer::start_with(listen, backend, &[])
+    }
+
+    // Same as start, with the transport options TRANSPORT_ARGS
+    fn start_with(
+        listen: SocketAddr,
+        backend: SocketAddr,
+        transport_args: &[&str],
+    ) -> Result<TestServer, Box<dyn Error + Send + Sync>> {
+        let (listen, backend_arg) = (listen.to_string(), backend.to_string());
+        let mut args = vec!["server", "--listen", &listen, "--proxy-to", &backend_arg];
+        args.extend_from_slice(transport_args);
+        let options = Arc::new(server::Opt::try_parse_from(args).unwrap());
+        let (server_config, _) = configure_server(
+            &transport_opt(transport_args),
+            &ServerTlsOpt::default(),
+            &SniCerts::new(),
+        )
+        .unwrap();
+        let mut endpoints =
+            make_server_endpoints(&options, server_config, EndpointConfig::default())?;
+        let endpoint = endpoints.remove(0);
+        let addr = endpoint.local_addr().unwrap();
+
+        let backend: BackendAddr = backend.to_string().parse().unwrap();
+        let accept_loop = tokio::spawn({
+            let endpoint = endpoint.clone();
+            async move {
+                while let Some(connecting) = endpoint.accept().await {
+                    let Ok(connection) = connecting.await else {
+                        continue;
+                    };
+                    tokio::spawn(handle_connection(
+                        options.clone(),
+                        "localhost".to_string(),
+                        vec![backend.clone()],
+                        addr,
+                        connection,
+                        Arc::new(TrafficCounters::default()),
+                        Default::default(),
+                        None,
+                    ));
+                }
+            }
+        });
+        Ok(TestServer {
+            endpoint,
+            addr,
+            accept_loop,
+        })
+    }
+
+    // Close every connection and stop accepting, releasing the port
+    async fn stop(self) {
+        self.accept_loop.abort();
+        let _ = self.accept_loop.await;
+        self.endpoint.close(0u32.into(), b"stopped");
+        self.endpoint.wait_idle().await;
+    }
+}
+
+// Transport options of the server and client command lines
+#[derive(Parser)]
+struct TransportArgs {
+    #[clap(flatten)]
+    transport: TransportOpt,
+}
+
+// Parse the transport options ARGS
+fn transport_opt(args: &[&str]) -> TransportOpt {
+    let args = std::iter::once("transport").chain(args.iter().copied());
+    TransportArgs::try_parse_from(args).unwrap().transport
+}
+
+// quicssh-rs server process, killed when dropped
+struct ServerProcess(Child);
+
+impl ServerProcess {
+    // Run the server binary with ARGS, logging nothing
+    fn spawn(args: &[&str]) -> ServerProcess {
+        let child = Command::new(env!("CARGO_BIN_EXE_quicssh-rs"))
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        ServerProcess(child)
+    }
+}
+
+impl Drop for ServerProcess {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+// UDP address on the loopback interface that was free a moment ago
+fn free_udp_addr() -> SocketAddr {
+    UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+// Connect a client to SERVER and open the stream relayed to the backend
+async fn connect(server: SocketAddr) -> (Endpoint, Connection, SendStream, RecvStream) {
+    connect_with(server, &[]).await
+}
+
+// Same as connect, with the transport options TRANSPORT_ARGS
+async fn connect_with(
+    server: SocketAddr,
+    transport_args: &[&str],
+) -> (Endpoint, Connection, SendStream, RecvStream) {
+    let transport = transport_opt(transport_args);
+    let client_cfg = configure_client(&transport, &ClientTlsOpt::default()).unwrap();
+    let endpoint =
+        make_client_endpoint("127.0.0.1:0".parse().unwrap(), &transport, client_cfg).unwrap();
+    let connection = endpoint
+        .connect(server, "localhost")
+        .unwrap()
+        .await
+        .unwrap();
+    let (send, recv) = connection.open_bi().await.unwrap();
+    (endpoint, connection, send, recv)
+}
+
+// Client endpoint and configuration resuming sessions with 0-RTT
+fn zero_rtt_client() -> (Endpoint, quinn::ClientConfig) {
+    let transport = TransportOpt::default();
+    let mut tls = ClientTlsOpt::default();
+    tls.enable_0rtt = true;
+    let client_cfg = configure_client(&transport, &tls).unwrap();
+    let endpoint = make_client_endpoint(
+        "127.0.0.1:0".parse().unwrap(),
+        &transport,
+        client_cfg.clone(),
+    )
+    .unwrap();
+    (endpoint, client_cfg)
+}
+
+// Make a full handshake with SERVER as SNI, leaving a session ticket in the
+// cache of the 0-RTT clients
+async fn fetch_session_ticket(
+    endpoint: &Endpoint,
+    client_cfg: &quinn::ClientConfig,
+    server: SocketAddr,
+    sni: &str,
+) {
+    let (connection, zero_rtt) = connect_0rtt(endpoint, client_cfg, server, sni)
+        .await
+        .unwrap();
+    assert!(
+        zero_rtt.is_none(),
+        "no session ticket should be cached for {}",
+        sni
+    );
+    let (mut send, mut recv) = connection.open_bi().await.unwrap();
+    assert_eq!(round_trip(&mut send, &mut recv, b"ticket").await, b"ticket");
+    connection.close(0u32.into(), b"done");
+}
+
+// Send DATA through the tunnel and return what comes back, writing and
+// reading at the same time so flow control never stalls large payloads
+async fn round_trip(send: &mut SendStream, recv: &mut RecvStream, data: &[u8]) -> Vec<u8> {
+    let mut received = vec![0u8; data.len()];
+    let (written, read) = tokio::join!(send.write_all(data), recv.read_exact(&mut received));
+    written.unwrap();
+    read.unwrap();
+    received
+}
+
+// Deterministic payload of LEN bytes, different for each SEED
+fn payload(len: usize, seed: u8) -> Vec<u8> {
+    (0..len)
+        .map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed))
+        .collect()
+}
+
+#[tokio::test]
+async fn clean_connection() {
+    with_timeout(async {
+        let server =
+            TestServer::start("127.0.0.1:0".parse().unwrap(), echo_backend().await).unwrap();
+        let (endpoint, connection, mut send, mut recv) = connect(server.addr).await;
+
+        let data = b"SSH-2.0-OpenSSH_9.6\r\n";
+        assert_eq!(round_trip(&mut send, &mut recv, data).await, data);
+
+        connection.close(0u32.into(), b"done");
+        endpoint.wait_idle().await;
+        server.stop().await;
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn trace_header_skipped_without_tracing() {
+    with_timeout(async {
+        let server =
+            TestServer::start("127.0.0.1:0".parse().unwrap(), echo_backend().await).unwrap();
+        let (_endpoint, _connection, mut send, mut recv) = connect(server.addr).await;
+
+        // A client exporting traces starts the stream with its trace header,
+        // which must not reach SSH when the server does not trace
+        let traceparent = b"00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let mut header = b"QSTP".to_vec();
+        header.extend_from_slice(&(traceparent.len() as u16).to_be_bytes());
+        header.extend_from_slice(traceparent);
+        send.write_all(&header).await.unwrap();
+        let data = b"SSH-2.0-OpenSSH_9.6\r\n";
+        assert_eq!(round_trip(&mut send, &mut recv, data).await, data);
+
+        server.stop().await;
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn eof_propagation() {
+    with_timeout(async {
+        let server =
+            TestServer::start("127.0.0.1:0".parse().unwrap(), echo_backend().await).unwrap();
+        let (_endpoint, _connection, mut send, mut recv) = connect(server.addr).await;
+
+        // Finishing the stream closes the backend connection for writing, the
+        // backend then closes its side and the client sees the end of the stream
+        send.write_all(b"last words").await.unwrap();
+        send.finish().await.unwrap();
+        let received = recv.read_to_end(1024).await.unwrap();
+        assert_eq!(received, b"last words");
+
+        server.stop().await;
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn server_restart() {
+    with_timeout(async {
+        let backend = echo_backend().await;
+        let server = TestServer::start("127.0.0.1:0".parse().unwrap(), backend).unwrap();
+        let addr = server.addr;
+        let (_endpoint, connection, mut send, mut recv) = connect(addr).await;
+        assert_eq!(round_trip(&mut send, &mut recv, b"before").await, b"before");
+
+        // The connection does not survive the server, a new one works once
+        // the server is back on the same port
+        server.stop().await;
+        assert!(recv.read(&mut [0u8; 16]).await.is_err() || connection.close_reason().is_some());
+        // The socket is released once the connection tasks of the old server
+        // dropped their endpoint handles
+        let server = loop {
+            match TestServer::start(addr, backend) {
+                Ok(server) => break server,
+                Err(_) => tokio::time::sleep(Duration::from_millis(50)).await,
+            }
+        };
+        let (_endpoint, _connection, mut send, mut recv) = connect(addr).await;
+        assert_eq!(round_trip(&mut send, &mut recv, b"after").await, b"after");
+
+        server.stop().await;
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn large_payload() {
+    with_timeout(async {
+        let server =
+            TestServer::start("127.0.0.1:0".parse().unwrap(), echo_backend().await).unwrap();
+        let (_endpoint, _connection, mut send, mut recv) = connect(server.addr).await;
+
+        let data = payload(8 * 1024 * 1024, 7);
+        assert!(round_trip(&mut send, &mut recv, &data).await == data);
+
+        server.stop().await;
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn concurrent_connections() {
+    with_timeout(async {
+        let server =
+            TestServer::start("127.0.0.1:0".parse().unwrap(), echo_backend().await).unwrap();
+        let addr = server.addr;
+
+        let clients = (0..16u8).map(|seed| {
+            tokio::spawn(async move {
+                let (_endpoint, _connection, mut send, mut recv) = connect(addr).await;
+                let data = payload(256 * 1024, seed);
+                round_trip(&mut send, &mut recv, &data).await == data
+            })
+        });
+        for client in futures::future::join_all(clients).await {
+            assert!(client.unwrap(), "a client got back different bytes");
+        }
+
+        server.stop().await;
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn drain_file_server_keeps_running() {
+    with_timeout(async {
+        let backend = echo_backend().await;
+        let addr = free_udp_addr();
+        let drain_file =
+            std::env::temp_dir().join(format!("quicssh-drain-{}.txt", std::process::id()));
+        let mut server = ServerProcess::spawn(&[
+            "server",
+            "--listen",
+            &addr.to_string(),
+            "--proxy-to",
+            &backend.to_string(),
+            "--drain-connections-file",
+            drain_file.to_str().unwrap(),
+        ]);
+
+        // SIGTERM is left to draining, the server must still serve until killed
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(
+            server.0.try_wait().unwrap().is_none(),
+            "server exited after startup"
+        );
+        let (_endpoint, _connection, mut send, mut recv) = connect(addr).await;
+        assert_eq!(
+            round_trip(&mut send, &mut recv, b"still up").await,
+            b"still up"
+        );
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn zero_rtt_resumption() {
+    with_timeout(async {
+        let server =
+            TestServer::start("127.0.0.1:0".parse().unwrap(), echo_backend().await).unwrap();
+        let (endpoint, client_cfg) = zero_rtt_client();
+        // Each 0-RTT test has its own name, tickets are cached per server name
+        let sni = "zero-rtt-resumption.test";
+        fetch_session_ticket(&endpoint, &client_cfg, server.addr, sni).await;
+
+        // The second connection of the endpoint sends its data before the
+        // handshake completes, and the server takes it
+        let (connection, zero_rtt) = connect_0rtt(&endpoint, &client_cfg, server.addr, sni)
+            .await
+            .unwrap();
+        let accepted = zero_rtt.expect("second connection did not use 0-RTT");
+        let (mut send, mut recv) = connection.open_bi().await.unwrap();
+        send.write_all(b"early data").await.unwrap();
+        assert!(accepted.await, "server rejected the 0-RTT data");
+        send.finish().await.unwrap();
+        assert_eq!(recv.read_to_end(1024).await.unwrap(), b"early data");
+
+        server.stop().await;
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn zero_rtt_rejected_data_sent_once() {
+    with_timeout(async {
+        let backend = echo_backend().await;
+        let (endpoint, client_cfg) = zero_rtt_client();
+        let sni = "zero-rtt-rejected.test";
+        let first = TestServer::start("127.0.0.1:0".parse().unwrap(), backend).unwrap();
+        fetch_session_ticket(&endpoint, &client_cfg, first.addr, sni).await;
+        first.stop().await;
+
+        // Another server does not know the session, it rejects the early data
+        // and open_stream_0rtt sends it again on a new stream
+        let server = TestServer::start("127.0.0.1:0".parse().unwrap(), backend).unwrap();
+        let (connection, zero_rtt) = connect_0rtt(&endpoint, &client_cfg, server.addr, sni)
+            .await
+            .unwrap();
+        let accepted = zero_rtt.expect("cached ticket was not used");
+        let mut input: &[u8] = b"client key exchange";
+        let (mut send, mut recv) =
+            open_stream_0rtt(&connection, accepted, &mut input, b"SSH-2.0-test\r\n", 1024)
+                .await
+                .unwrap();
+        // Whatever the first read did not take follows on the stream
+        tokio::io::copy(&mut input, &mut send).await.unwrap();
+        send.finish().await.unwrap();
+        assert_eq!(
+            recv.read_to_end(1024).await.unwrap(),
+            b"SSH-2.0-test\r\nclient key exchange"
+        );
+
+        server.stop().await;
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn keylog_nss_format_appended() {
+    with_timeout(async {
+        let server =
+            TestServer::start("127.0.0.1:0".parse().unwrap(), echo_backend().await).unwrap();
+        let path = std::env::temp_dir().join(format!("quicssh-keylog-{}.txt", std::process::id()));
+        std::fs::write(&path, "# earlier session\n").unwrap();
+
+        let transport = TransportOpt::default();
+        let mut tls = ClientTlsOpt::default();
+        tls.keylog = Some(path.clone());
+        let client_cfg = configure_client(&transport, &tls).unwrap();
+        let endpoint =
+            make_client_endpoint("127.0.0.1:0".parse().unwrap(), &transport, client_cfg).unwrap();
+        let mut sizes = Vec::new();
+        for _ in 0..2 {
+            let connection = endpoint
+                .connect(server.addr, "localhost")
+                .unwrap()
+                .await
+                .unwrap();
+            let (mut send, mut recv) = connection.open_bi().await.unwrap();
+            assert_eq!(round_trip(&mut send, &mut recv, b"keys").await, b"keys");
+            connection.close(0u32.into(), b"done");
+            sizes.push(std::fs::metadata(&path).unwrap().len());
+        }
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // Earlier lines are kept and each handshake adds its own
+        let mut lines = content.lines();
+        assert_eq!(lines.next(), Some("# earlier session"));
+        assert!(
+            sizes[0] < sizes[1],
+            "second handshake did not append: {:?}",
+            sizes
+        );
+
+        // NSS key log format: <LABEL> <64 hex client random> <hex secret>
+        let mut labels = Vec::new();
+        for line in lines {
+            let fields: Vec<&str> = line.split(' ').collect();
+            let [label, client_random, secret] = fields[..] else {
+                panic!("not three fields: {:?}", line);
+            };
+            let is_hex = |s: &str| s.bytes().all(|b| b.is_ascii_hexdigit());
+            assert!(
+                !label.is_empty()
+                    && label
+                        .bytes()
+                        .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit() || b == b'_'),
+                "bad label: {:?}",
+                line
+            );
+            assert!(
+                client_random.len() == 64 && is_hex(client_random),
+                "bad client random: {:?}",
+                line
+            );
+            assert!(
+                !secret.is_empty() && secret.len() % 2 == 0 && is_hex(secret),
+                "bad secret: {:?}",
+                line
+            );
+            labels.push(label.to_string());
+        }
+        for label in [
+            "CLIENT_HANDSHAKE_TRAFFIC_SECRET",
+            "SERVER_HANDSHAKE_TRAFFIC_SECRET",
+            "CLIENT_TRAFFIC_SECRET_0",
+            "SERVER_TRAFFIC_SECRET_0",
+        ] {
+            assert_eq!(
+                labels.iter().filter(|l| *l == label).count(),
+                2,
+                "{} not logged per handshake",
+                label
+            );
+        }
+
+        server.stop().await;
+    })
+    .await;
+}
+
+// Time to echo SIZE bytes through a tunnel whose ends delay every datagram
+// they receive by 25 ms, with both receive windows set to WINDOW bytes
+async fn delayed_echo_time(size: usize, window: &str) -> Duration {
+    let args = [
+        "--chaos-packet-delay-ms",
+        "25",
+        "0",
+        "--recv-window-bytes",
+        window,
+    ];
+    let server =
+        TestServer::start_with("127.0.0.1:0".parse().unwrap(), echo_backend().await, &args)
+            .unwrap();
+    let (_endpoint, _connection, mut send, mut recv) = connect_with(server.addr, &args).await;
+
+    let data = payload(size, 3);
+    let start = std::time::Instant::now();
+    assert!(round_trip(&mut send, &mut recv, &data).await == data);
+    let elapsed = start.elapsed();
+    server.stop().await;
+    elapsed
+}
+
+#[tokio::test]
+async fn recv_window_throughput() {
+    with_timeout(async {
+        // At 50 ms RTT a 64 KiB window caps each direction near 1.3 MB/s,
+        // a 16 MiB window leaves the transfer to congestion control
+        let small = delayed_echo_time(2_000_000, "65536").await;
+        let large = delayed_echo_time(2_000_000, "16777216").await;
+        assert!(
+            small.as_secs_f64() > 1.5 * large.as_secs_f64(),
+            "64 KiB window took {:?}, 16 MiB window took {:?}",
+            small,
+            large
+        );
+    })
+    .await;
+}
+
+// Time to echo SIZE bytes through a server process relaying with --buf-size BUF_SIZE
+async fn buf_size_echo_time(size: usize, buf_size: &str) -> Duration {
+    let (addr, backend) = (free_udp_addr(), echo_backend().await);
+    let _server = ServerProcess::spawn(&[
+        "server",
+        "--listen",
+        &addr.to_string(),
+        "--proxy-to",
+        &backend.to_string(),
+        "--buf-size",
+        buf_size,
+    ]);
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let (_endpoint, _connection, mut send, mut recv) = connect(addr).await;
+
+    let data = payload(size, 5);
+    let start = std::time::Instant::now();
+    assert!(round_trip(&mut send, &mut recv, &data).await == data);
+    start.elapsed()
+}
+
+#[tokio::test]
+async fn buf_size_throughput() {
+    with_timeout(async {
+        // On loopback the QUIC client is the bottleneck, so each buffer size
+        // must relay the payload intact without slowing the transfer down
+        let size = 8 * 1024 * 1024;
+        let small = buf_size_echo_time(size, "2048").await;
+        for buf_size in ["65536", "262144"] {
+            let time = buf_size_echo_time(size, buf_size).await;
+            assert!(
+                time.as_secs_f64() < 2.0 * small.as_secs_f64(),
+                "--buf-size {} took {:?}, 2 KB took {:?}",
+                buf_size,
+                time,
+                small
+            );
+        }
+        assert!(
+            small < Duration::from_secs(5),
+            "8 MiB took {:?} with 2 KB buffers",
+            small
+        );
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn backend_eof_finishes_quic_stream() {
+    with_timeout(async {
+        let data = b"SSH-2.0-OpenSSH_9.6\r\nbanner and then the backend closes";
+        let server =
+            TestServer::start("127.0.0.1:0".parse().unwrap(), finite_backend(data).await).unwrap();
+        let (_endpoint, _connection, mut send, mut recv) = connect(server.addr).await;
+
+        // The client keeps its side open, the end of the stream can only come
+        // from the backend closing its connection
+        send.write_all(b"x").await.unwrap();
+        let mut received = Vec::new();
+        let mut buf = [0u8; 16];
+        while let Some(n) = recv.read(&mut buf).await.unwrap() {
+            received.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(received, data);
+
+        server.stop().await;
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn client_exits_on_stream_end() {
+    with_timeout(async {
+        let data = b"SSH-2.0-OpenSSH_9.6\r\noutput of a finished session";
+        let server =
+            TestServer::start("127.0.0.1:0".parse().unwrap(), finite_backend(data).await).unwrap();
+
+        // Run as ssh runs it; stdin stays open, so the client must exit
+        // because the server finished the stream, with all of it on stdout
+        let mut client = tokio::process::Command::new(env!("CARGO_BIN_EXE_quicssh-rs"))
+            .args([
+                "client",
+                "--proxycommand",
+                &format!("quic://127.0.0.1:{}", server.addr.port()),
+            ])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap();
+        let mut stdin = client.stdin.take().unwrap();
+        stdin.write_all(b"x").await.unwrap();
+        let output = client.wait_with_output().await.unwrap();
+        assert!(
+            output.status.success(),
+            "client failed: {:?}",
+            output.status
+        );
+        assert_eq!(output.stdout, data);
+        drop(stdin);
+
+        server.stop().await;
+    })
+    .await;
+}
+
+// Time a reconnecting client gets to give up on a session
+const CLIENT_EXIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Run the client binary as ssh runs it with --reconnect, connecting to SERVER
+fn reconnecting_client(server: SocketAddr, args: &[&str]) -> tokio::process::Child {
+    tokio::process::Command::new(env!("CARGO_BIN_EXE_quicssh-rs"))
+        .args(["client", "--proxycommand", "--reconnect"])
+        .args(args)
+        .arg(format!("quic://{}", server))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .unwrap()
+}
+
+// Wait for CLIENT to give up, within CLIENT_EXIT_TIMEOUT
+async fn client_exit(client: &mut tokio::process::Child) {
+    tokio::time::timeout(CLIENT_EXIT_TIMEOUT, client.wait())
+        .await
+        .expect("client kept reconnecting")
+        .unwrap();
+}
+
+#[tokio::test]
+async fn reconnect_retries_until_server_up() {
+    with_timeout(async {
+        // Nothing listens yet, each attempt times out
+        let addr = free_udp_addr();
+        let mut client = reconnecting_client(addr, &["--connection-timeout-ms", "300"]);
+        let mut stdin = client.stdin.take().unwrap();
+        let mut stdout = client.stdout.take().unwrap();
+        stdin.write_all(b"ping").await.unwrap();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(
+            client.try_wait().unwrap().is_none(),
+            "client gave up while the server was down"
+        );
+
+        let backend = echo_backend().await;
+        let server = loop {
+            match TestServer::start(addr, backend) {
+                Ok(server) => break server,
+                Err(_) => tokio::time::sleep(Duration::from_millis(50)).await,
+            }
+        };
+        let mut echoed = [0u8; 4];
+        stdout.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(&echoed, b"ping");
+
+        drop(stdin);
+        client.kill().await.unwrap();
+        server.stop().await;
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn reconnect_stops_on_final_errors() {
+    with_timeout(async {
+        // A certificate not matching --server-fingerprint is not retried
+        let server =
+            TestServer::start("127.0.0.1:0".parse().unwrap(), echo_backend().await).unwrap();
+        let fingerprint = "00".repeat(32);
+        let mut client = reconnecting_client(server.addr, &["--server-fingerprint", &fingerprint]);
+        client_exit(&mut client).await;
+        server.stop().await;
+
+        // Neither is the server refusing the client, here because the test
+        // already used the only connection its address may open per minute
+        let addr = free_udp_addr();
+        let _server = ServerProcess::spawn(&[
+            "server",
+            "--listen",
+            &addr.to_string(),
+            "--proxy-to",
+            &echo_backend().await.to_string(),
+            "--rate-limit-reconnect-max",
+            "1",
+            "--rate-limit-reconnect-window-secs",
+            "60",
+        ]);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let (_endpoint, _connection, mut send, mut recv) = connect(addr).await;
+        assert_eq!(round_trip(&mut send, &mut recv, b"first").await, b"first");
+        let mut client = reconnecting_client(addr, &[]);
+        client_exit(&mut client).await;
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn reconnect_stops_after_session_started() {
+    with_timeout(async {
+        let server =
+            TestServer::start("127.0.0.1:0".parse().unwrap(), echo_backend().await).unwrap();
+        let mut client = reconnecting_client(server.addr, &["--connection-timeout-ms", "300"]);
+        let mut stdin = client.stdin.take().unwrap();
+        let mut stdout = client.stdout.take().unwrap();
+        stdin.write_all(b"ping").await.unwrap();
+        let mut echoed = [0u8; 4];
+        stdout.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(&echoed, b"ping");
+
+        // A new connection would hand the rest of the SSH stream to a new
+        // session on the server, the client must exit instead
+        server.stop().await;
+        client_exit(&mut client).await;
+        drop(stdin);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn socks5_proxy_relays_connection() {
+    with_timeout(async {
+        let server =
+            TestServer::start("127.0.0.1:0".parse().unwrap(), echo_backend().await).unwrap();
+        let (proxy, relayed) = socks5_relay().await;
+
+        let transport = TransportOpt::default();
+        let client_cfg = configure_client(&transport, &ClientTlsOpt::default()).unwrap();
+        let endpoint = make_socks5_endpoint(
+            proxy,
+            "127.0.0.1:0".parse().unwrap(),
+            &transport,
+            client_cfg,
+        )
+        .await
+        .unwrap();
+        let connection = endpoint
+            .connect(server.addr, "localhost")
+            .unwrap()
+            .await
+            .unwrap();
+        assert_eq!(connection.remote_address(), server.addr);
+        let (mut send, mut recv) = connection.open_bi().await.unwrap();
+        let data = payload(256 * 1024, 5);
+        assert!(round_trip(&mut send, &mut recv, &data).await == data);
+        assert!(
+            relayed.load(Ordering::Relaxed) > 0,
+            "no datagram went through the proxy"
+        );
+
+        connection.close(0u32.into(), b"done");
+        server.stop().await;
+    })
+    .await;
+}
+
+// PEM block of DER data under LABEL
+fn pem(label: &str, der: &[u8]) -> String {
+    use base64::Engine;
+    let encoded = base64::engine::general_purpose::STANDARD.encode(der);
+    let lines: Vec<&str> = encoded
+        .as_bytes()
+        .chunks(64)
+        .map(|line| std::str::from_utf8(line).unwrap())
+        .collect();
+    format!(
+        "-----BEGIN {}-----\n{}\n-----END {}-----\n",
+        label,
+        lines.join("\n"),
+        label
+    )
+}
+
+#[tokio::test]
+async fn der_and_pem_certificates_handshake() {
+    with_timeout(async {
+        // The same certificate and key, written in both encodings
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let (cert_der, key_der) = (
+            cert.serialize_der().unwrap(),
+            cert.serialize_private_key_der(),
+        );
+        let dir = std::env::temp_dir().join(format!("quicssh-certs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let files = [
+            ("cert.pem", pem("CERTIFICATE", &cert_der).into_bytes()),
+            ("key.pem", pem("PRIVATE KEY", &key_der).into_bytes()),
+            ("cert.der", cert_der.clone()),
+            ("key.der", key_der),
+        ];
+        for (name, data) in &files {
+            std::fs::write(dir.join(name), data).unwrap();
+        }
+
+        let transport = TransportOpt::default();
+        let mut client_tls = ClientTlsOpt::default();
+        client_tls.server_fingerprint = Some(cert_digest(&rustls::Certificate(cert_der.clone())));
+        let client_cfg = configure_client(&transport, &client_tls).unwrap();
+        let endpoint =
+            make_client_endpoint("127.0.0.1:0".parse().unwrap(), &transport, client_cfg).unwrap();
+        for (ext, format) in [
+            ("pem", "auto"),
+            ("der", "auto"),
+            ("pem", "pem"),
+            ("der", "der"),
+        ] {
+            let mut tls = ServerTlsOpt::default();
+            tls.cert = Some(dir.join(format!("cert.{}", ext)));
+            tls.key = Some(dir.join(format!("key.{}", ext)));
+            tls.cert_format = CertFormat::from_str(format, true).unwrap();
+            let (server_config, _) = configure_server(&transport, &tls, &SniCerts::new()).unwrap();
+            let server = Endpoint::server(server_config, "127.0.0.1:0".parse().unwrap()).unwrap();
+
+            // The client only completes the handshake if it got this certificate
+            let addr = server.local_addr().unwrap();
+            let (connection, incoming) = tokio::join!(
+                endpoint.connect(addr, "localhost").unwrap(),
+                server.accept()
+            );
+            let _server_connection = incoming.unwrap().await.unwrap();
+            let connection = connection.unwrap_or_else(|e| panic!("{} as {}: {}", ext, format, e));
+            let chain = connection
+                .peer_identity()
+                .unwrap()
+                .downcast::<Vec<rustls::Certificate>>()
+                .unwrap();
+            assert_eq!(chain[0].0, cert_der, "{} as {}", ext, format);
+            connection.close(0u32.into(), b"done");
+            server.close(0u32.into(), b"done");
+        }
+
+        // Forcing the other encoding is an error rather than a bad certificate
+        let mut tls = ServerTlsOpt::default();
+        tls.cert = Some(dir.join("cert.der"));
+        tls.key = Some(dir.join("key.der"));
+        tls.cert_format = CertFormat::Pem;
+        assert!(configure_server(&transport, &tls, &SniCerts::new()).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    })
+    .await;
+}
+
+// DER certificate the server presented on CONNECTION
+fn peer_cert(connection: &Connection) -> Vec<u8> {
+    let chain = connection
+        .peer_identity()
+        .unwrap()
+        .downcast::<Vec<rustls::Certificate>>()
+        .unwrap();
+    chain[0].0.clone()
+}
+
+// Write a new self-signed certificate and its key to CERT and KEY in PEM,
+// returning the DER certificate
+fn write_cert(cert: &std::path::Path, key: &std::path::Path) -> Vec<u8> {
+    let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let der = generated.serialize_der().unwrap();
+    std::fs::write(
+        key,
+        pem("PRIVATE KEY", &generated.serialize_private_key_der()),
+    )
+    .unwrap();
+    std::fs::write(cert, pem("CERTIFICATE", &der)).unwrap();
+    der
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn certificate_reloaded_on_sighup() {
+    use nix::sys::signal::{kill, Signal};
+    use nix::unistd::Pid;
+
+    with_timeout(async {
+        let dir = std::env::temp_dir().join(format!("quicssh-reload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (cert, key) = (dir.join("cert.pem"), dir.join("key.pem"));
+        let old_cert = write_cert(&cert, &key);
+        let addr = free_udp_addr();
+        let server = ServerProcess::spawn(&[
+            "server",
+            "--listen",
+            &addr.to_string(),
+            "--proxy-to",
+            &echo_backend().await.to_string(),
+            "--cert",
+            cert.to_str().unwrap(),
+            "--key",
+            key.to_str().unwrap(),
+        ]);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let (_endpoint, connection, mut send, mut recv) = connect(addr).await;
+        assert!(peer_cert(&connection) == old_cert);
+        send.write_all(b"before").await.unwrap();
+        let mut echoed = [0u8; 6];
+        recv.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(&echoed, b"before");
+
+        // New connections get the new certificate once the server reloaded it
+        let new_cert = write_cert(&cert, &key);
+        kill(Pid::from_raw(server.0.id() as i32), Signal::SIGHUP).unwrap();
+        loop {
+            let (_endpoint, connection, _, _) = connect(addr).await;
+            if peer_cert(&connection) == new_cert {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        // while the connection opened before keeps relaying
+        assert_eq!(round_trip(&mut send, &mut recv, b"after").await, b"after");
+        assert!(connection.close_reason().is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    })
+    .await;
+}
+
+// TCP backend that takes one connection and reads nothing from it until
+// RELEASE is notified, then returns the number of bytes it read until EOF
+async fn stalled_backend(release: Arc<tokio::sync::Notify>) -> (SocketAddr, JoinHandle<usize>) {
+    // A fixed receive buffer, so the kernel does not absorb what it does not read
+    let socket = tokio::net::TcpSocket::new_v4().unwrap();
+    socket.set_recv_buffer_size(64 * 1024).unwrap();
+    socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let listener = socket.listen(1).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let reader = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        release.notified().await;
+        let mut sink = Vec::new();
+        stream.read_to_end(&mut sink).await.unwrap();
+        sink.len()
+    });
+    (addr, reader)
+}
+
+#[tokio::test]
+async fn stalled_backend_stops_client() {
+    with_timeout(async {
+        const TOTAL: usize = 32 * 1024 * 1024;
+        let release = Arc::new(tokio::sync::Notify::new());
+        let (backend, reader) = stalled_backend(release.clone()).await;
+        let args = ["--recv-window-bytes", "262144"];
+        let server =
+            TestServer::start_with("127.0.0.1:0".parse().unwrap(), backend, &args).unwrap();
+        let (_endpoint, _connection, mut send, _recv) = connect_with(server.addr, &args).await;
+
+        let written = Arc::new(AtomicUsize::new(0));
+        let writer = tokio::spawn({
+            let written = written.clone();
+            async move {
+                let chunk = payload(64 * 1024, 9);
+                while written.load(Ordering::Relaxed) < TOTAL {
+                    send.write_all(&chunk).await.unwrap();
+                    written.fetch_add(chunk.len(), Ordering::Relaxed);
+                }
+                send.finish().await.unwrap();
+            }
+        });
+
+        // The server stops reading the stream once the backend does, leaving
+        // the client blocked on flow control instead of buffering its data
+        let mut stalled = 0;
+        loop {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            if stalled > 0 && written.load(Ordering::Relaxed) == stalled {
+                break;
+            }
+            stalled = written.load(Ordering::Relaxed);
+        }
+        assert!(
+            stalled < 8 * 1024 * 1024,
+            "{} bytes went past a stalled backend",
+            stalled
+        );
+
+        // Everything arrives once the backend reads again
+        release.notify_one();
+        writer.await.unwrap();
+        assert_eq!(reader.await.unwrap(), TOTAL);
+        server.stop().await;
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn connection_limit_queues_extra_connection() {
+    with_timeout(async {
+        let addr = free_udp_addr();
+        let _server = ServerProcess::spawn(&[
+            "server",
+            "--listen",
+            &addr.to_string(),
+            "--proxy-to",
+            &echo_backend().await.to_string(),
+            "--max-connections",
+            "2",
+        ]);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        // Sessions stay open, their streams are not finished
+        let mut handled = Vec::new();
+        for _ in 0..2 {
+            let (endpoint, connection, mut send, mut recv) = connect(addr).await;
+            send.write_all(b"served").await.unwrap();
+            let mut echoed = [0u8; 6];
+            recv.read_exact(&mut echoed).await.unwrap();
+            handled.push((endpoint, connection, send, recv));
+        }
+
+        // The third connection waits for a slot
+        let (_endpoint, _connection, mut send, mut recv) = connect(addr).await;
+        send.write_all(b"third").await.unwrap();
+        let mut echoed = [0u8; 5];
+        let early =
+            tokio::time::timeout(Duration::from_secs(1), recv.read_exact(&mut echoed)).await;
+        assert!(
+            early.is_err(),
+            "third connection served while two were open"
+        );
+
+        // and is served once one of the first two closes
+        let (_, first, _, _) = handled.remove(0);
+        first.close(0u32.into(), b"done");
+        recv.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(&echoed, b"third");
+    })
+    .await;
+}
+
+// Echo backend counting in SESSIONS the connections that carry data, which
+// leaves out the health checks of the server; aborting the task stops it
+async fn counting_backend(sessions: Arc<AtomicUsize>) -> (SocketAddr, JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let accept_loop = tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let sessions = sessions.clone();
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.into_split();
+                let mut buf = [0u8; 1024];
+                let Ok(n @ 1..) = reader.read(&mut buf).await else {
+                    return;
+                };
+                sessions.fetch_add(1, Ordering::Relaxed);
+                let _ = writer.write_all(&buf[..n]).await;
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+                let _ = writer.shutdown().await;
+            });
+        }
+    });
+    (addr, accept_loop)
+}
+
+#[tokio::test]
+async fn round_robin_spreads_connections() {
+    with_timeout(async {
+        let counts: Vec<Arc<AtomicUsize>> = (0..3).map(|_| Arc::new(AtomicUsize::new(0))).collect();
+        let mut backends = Vec::new();
+        for count in &counts {
+            backends.push(format!("\"{}\"", counting_backend(count.clone()).await.0));
+        }
+        let conf =
+            std::env::temp_dir().join(format!("quicssh-round-robin-{}.toml", std::process::id()));
+        std::fs::write(
+            &conf,
+            format!("[proxy]\nlocalhost = [{}]\n", backends.join(", ")),
+        )
+        .unwrap();
+        let addr = free_udp_addr();
+        let _server = ServerProcess::spawn(&[
+            "server",
+            "--listen",
+            &addr.to_string(),
+            "--conf",
+            conf.to_str().unwrap(),
+            "--rate-limit-reconnect-max",
+            "1000",
+        ]);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        for _ in 0..99 {
+            let (_endpoint, connection, mut send, mut recv) = connect(addr).await;
+            assert_eq!(round_trip(&mut send, &mut recv, b"routed").await, b"routed");
+            connection.close(0u32.into(), b"done");
+        }
+        let counts: Vec<usize> = counts
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .collect();
+        assert_eq!(counts, [33, 33, 33]);
+        std::fs::remove_file(&conf).unwrap();
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn failover_to_secondary_backend() {
+    with_timeout(async {
+        let (primary_sessions, secondary_sessions) =
+            (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let (primary, primary_backend) = counting_backend(primary_sessions.clone()).await;
+        let (secondary, _) = counting_backend(secondary_sessions.clone()).await;
+        let conf =
+            std::env::temp_dir().join(format!("quicssh-failover-{}.toml", std::process::id()));
+        let routes = format!(
+            "[[proxy.localhost.backends]]\naddr = \"{}\"\npriority = 1\n\n\
+             [[proxy.localhost.backends]]\naddr = \"{}\"\npriority = 2\n",
+            primary, secondary
+        );
+        std::fs::write(&conf, routes).unwrap();
+        let addr = free_udp_addr();
+        let metrics_addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let _server = ServerProcess::spawn(&[
+            "server",
+            "--listen",
+            &addr.to_string(),
+            "--conf",
+            conf.to_str().unwrap(),
+            "--failover-timeout-ms",
+            "500",
+            "--metrics-addr",
+            &metrics_addr.to_string(),
+        ]);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        // The primary takes the sessions while it is up
+        let (_endpoint, _connection, mut send, mut recv) = connect(addr).await;
+        assert_eq!(
+            round_trip(&mut send, &mut recv, b"primary").await,
+            b"primary"
+        );
+        assert_eq!(primary_sessions.load(Ordering::Relaxed), 1);
+
+        // Once it is gone the next client is routed to the secondary, without
+        // noticing anything
+        primary_backend.abort();
+        let _ = primary_backend.await;
+        let (_endpoint, _connection, mut send, mut recv) = connect(addr).await;
+        assert_eq!(
+            round_trip(&mut send, &mut recv, b"secondary").await,
+            b"secondary"
+        );
+        assert_eq!(primary_sessions.load(Ordering::Relaxed), 1);
+        assert_eq!(secondary_sessions.load(Ordering::Relaxed), 1);
+
+        // The failover is counted for the route
+        let mut scrape = TcpStream::connect(metrics_addr).await.unwrap();
+        scrape
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        scrape.read_to_string(&mut response).await.unwrap();
+        let failovers = response.lines().find(|line| {
+            line.starts_with("quicssh_failovers_total{") && line.contains(r#"sni="localhost""#)
+        });
+        assert!(
+            failovers.is_some_and(|line| line.ends_with(" 1")),
+            "{}",
+            response
+        );
+        std::fs::remove_file(&conf).unwrap();
+    })
+    .await;
+}